@@ -51,7 +51,7 @@
 				racer-tmp-file-name)))
       (delete-file racer-tmp-file-name)
       (dolist (line lines)
-	(when (string-match "^MATCH \\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\(.+\\)$" line)
+	(when (string-match "^MATCH \\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\([^,]+\\),\\(.+\\),[^,]+,[^,]+$" line)
 	  (let ((completion (match-string 1 line))
 		(linenum (match-string 2 line))
 		(colnum (match-string 3 line))
@@ -42,10 +42,11 @@ function! racer#GetExpCompletions()
     let col = b:racer_col      " use the column from the previous racer#GetPrefixCol() call, since vim ammends it afterwards
     let fname = expand("%:p")
     let tmpfname=fname.".racertmp"
-    let cmd = g:racer_cmd." complete ".line(".")." ".col." ".tmpfname
+    let cmd = g:racer_cmd." --format json complete ".line(".")." ".col." ".tmpfname
     if has('python')
     python << EOF
 from subprocess import check_output
+import json
 import vim
 
 typeMap = { 'Struct' : 's', 'Module' : 'M', 'Function' : 'f',
@@ -53,19 +54,21 @@ typeMap = { 'Struct' : 's', 'Module' : 'M', 'Function' : 'f',
             'Impl' : 'i', 'Enum' : 'e', 'EnumVariant' : 'E',
             'Type' : 't', 'FnArg' : 'v', 'Trait' : 'T'
             }
-lines = [l[6:] for l in check_output(vim.eval('cmd').split()).splitlines() if l.startswith('MATCH')]
+matches = [json.loads(l) for l in check_output(vim.eval('cmd').split()).splitlines()]
 candidates = []
-for line in lines:
-    completions = line.split(',',5)
-    kind = typeMap[completions[4]]
-    completion = {'kind' : kind, 'word' : completions[0]}
+for m in matches:
+    if m['type'] != 'match':
+        continue
+    kind = typeMap.get(m['mtype'], 'v')
+    context = m['contextstr'].encode('utf-8')
+    completion = {'kind' : kind, 'word' : m['matchstr'].encode('utf-8')}
     if kind == 'f': #function
-        completion['abbr'] = completions[5].replace('pub ','').replace('fn ','').rstrip('{')
+        completion['abbr'] = context.replace('pub ','').replace('fn ','').rstrip('{')
         if int(vim.eval('g:racer_insert_paren')):
             completion['word'] += '('
-        completion['info'] = completions[5]
+        completion['info'] = context
     elif kind == 's' : #struct
-        completion['abbr'] = completions[5].replace('pub ','').replace('struct ','').rstrip('{')
+        completion['abbr'] = context.replace('pub ','').replace('struct ','').rstrip('{')
     candidates.append(completion)
 
 vim.command("return %s" % candidates)
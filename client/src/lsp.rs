@@ -0,0 +1,317 @@
+// Language Server Protocol front-end for libracer.
+//
+// Talks JSON-RPC over stdin/stdout using the LSP 'Content-Length' framing.
+// Open documents are registered with libracer's virtual file system, so the
+// editor doesn't need to save the buffer (or write a temp file) before asking
+// for completions.
+
+extern crate libracer;
+extern crate serialize;
+
+#[cfg(not(test))]
+use serialize::json;
+#[cfg(not(test))]
+use serialize::json::Json;
+#[cfg(not(test))]
+use libracer::{Match, MatchType};
+#[cfg(not(test))]
+use libracer::scopes;
+
+// LSP CompletionItemKind values
+#[cfg(not(test))]
+fn completion_item_kind(mtype: MatchType) -> uint {
+    match mtype {
+        MatchType::Struct => 22,       // Struct
+        MatchType::Module => 9,        // Module
+        MatchType::Function => 3,      // Function
+        MatchType::Crate => 9,         // Module
+        MatchType::Let => 6,           // Variable
+        MatchType::StructField => 5,   // Field
+        MatchType::Impl => 7,          // Class
+        MatchType::Enum => 13,         // Enum
+        MatchType::EnumVariant => 20,  // EnumMember
+        MatchType::Type => 7,          // Class
+        MatchType::Builtin => 7,       // Class
+        MatchType::FnArg => 6,         // Variable
+        MatchType::ClosureArg => 6,    // Variable
+        MatchType::MatchArm => 6,      // Variable
+        MatchType::TypeParameter => 25, // TypeParameter
+        MatchType::Trait => 8,         // Interface
+        MatchType::Const => 21,        // Constant
+        MatchType::Static => 21        // Constant
+    }
+}
+
+#[cfg(not(test))]
+fn quote(s: &str) -> String {
+    Json::String(s.to_string()).to_string()
+}
+
+// Decodes the %XX escapes in a URI, e.g. 'my%20file.rs'
+#[cfg(not(test))]
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(bytes.slice(i + 1, i + 3)).ok()
+                .and_then(|h| std::num::from_str_radix::<u8>(h, 16));
+            if let Some(b) = hex {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(out.as_slice()).into_owned()
+}
+
+// Escapes the bytes of a path that can't appear as they are in a URI
+#[cfg(not(test))]
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for &b in s.as_bytes().iter() {
+        match b as char {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '/' | '-' | '_' | '.' | '~' | ':' => out.push(b as char),
+            _ => out.push_str(format!("%{:02X}", b).as_slice())
+        }
+    }
+    return out;
+}
+
+#[cfg(not(test))]
+fn uri_to_path(uri: &str) -> Path {
+    if uri.starts_with("file://") {
+        Path::new(percent_decode(uri.slice_from(7)))
+    } else {
+        Path::new(uri)
+    }
+}
+
+#[cfg(not(test))]
+fn path_to_uri(path: &Path) -> String {
+    // a path that isn't utf-8 is shown lossily rather than failing
+    format!("file://{}", percent_encode(path.display().to_string().as_slice()))
+}
+
+// LSP columns count UTF-16 code units, racer's count bytes
+#[cfg(not(test))]
+fn utf16_len(c: char) -> uint {
+    if c as u32 > 0xFFFF { 2 } else { 1 }
+}
+
+// The byte column of the character'th UTF-16 code unit of the line
+#[cfg(not(test))]
+fn utf16_to_byte_col(line: &str, character: uint) -> uint {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += utf16_len(c);
+    }
+    return line.len();
+}
+
+// The UTF-16 column of the byte column col of the line
+#[cfg(not(test))]
+fn byte_col_to_utf16(line: &str, col: uint) -> uint {
+    line.char_indices().take_while(|&(i, _)| i < col).fold(0, |n, (_, c)| n + utf16_len(c))
+}
+
+#[cfg(not(test))]
+struct Server {
+    shutting_down: bool
+}
+
+#[cfg(not(test))]
+impl Server {
+    fn new() -> Server {
+        Server { shutting_down: false }
+    }
+
+    // Returns (src, filepath, point) for a TextDocumentPositionParams object
+    fn position_params(&self, params: &Json) -> Option<(String, Path, uint)> {
+        let uri = match params.find_path(&["textDocument", "uri"]).and_then(|u| u.as_string()) {
+            Some(uri) => uri.to_string(),
+            None => return None
+        };
+        let line = params.find_path(&["position", "line"]).and_then(|l| l.as_u64());
+        let character = params.find_path(&["position", "character"]).and_then(|c| c.as_u64());
+        let (line, character) = match (line, character) {
+            (Some(l), Some(c)) => (l as uint, c as uint),
+            _ => return None
+        };
+
+        let path = uri_to_path(uri.as_slice());
+        let src = libracer::load_file(&path);
+        // LSP lines are zero based, racer's are one based
+        let charnum = utf16_to_byte_col(src.as_slice().lines().nth(line).unwrap_or(""), character);
+        let point = scopes::coords_to_point(src.as_slice(), line + 1, charnum);
+        return Some((src, path, point));
+    }
+
+    fn location_json(&self, m: &Match) -> String {
+        let src = libracer::load_file(&m.filepath);
+        let (linenum, charnum) = scopes::point_to_coords(src.as_slice(), m.point);
+        let line = src.as_slice().lines().nth(linenum - 1).unwrap_or("");
+        let endchar = byte_col_to_utf16(line, charnum + m.matchstr.len());
+        let charnum = byte_col_to_utf16(line, charnum);
+        format!("{{\"uri\":{},\"range\":{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}}}",
+                quote(path_to_uri(&m.filepath).as_slice()),
+                linenum - 1, charnum, linenum - 1, endchar)
+    }
+
+    fn completion(&self, params: &Json) -> String {
+        let mut items = Vec::new();
+        self.position_params(params).map(|(src, path, point)| {
+            // a failed query just means no completions
+            if let Ok(matches) = libracer::complete_from_file(src.as_slice(), &path, point) {
+                // the matches come best first, sortText keeps them in that order
+                for (i, m) in matches.enumerate() {
+                    items.push(format!("{{\"label\":{},\"kind\":{},\"detail\":{},\"sortText\":\"{:05}\"}}",
+                                       quote(m.matchstr.as_slice()),
+                                       completion_item_kind(m.mtype),
+                                       quote(m.contextstr.as_slice()),
+                                       i));
+                }
+            }
+        });
+        format!("[{}]", items.connect(","))
+    }
+
+    fn definition(&self, params: &Json) -> String {
+        self.position_params(params)
+            .and_then(|(src, path, point)| libracer::find_definition(src.as_slice(), &path, point).ok())
+            .map_or("null".to_string(), |m| self.location_json(&m))
+    }
+
+    fn did_open(&mut self, params: &Json) {
+        let uri = params.find_path(&["textDocument", "uri"]).and_then(|u| u.as_string());
+        let text = params.find_path(&["textDocument", "text"]).and_then(|t| t.as_string());
+        if let (Some(uri), Some(text)) = (uri, text) {
+            libracer::vfs::set_buffer(&uri_to_path(uri), text.to_string());
+        }
+    }
+
+    fn did_change(&mut self, params: &Json) {
+        // we only advertise full document sync, so the last change holds the whole text
+        let uri = params.find_path(&["textDocument", "uri"]).and_then(|u| u.as_string());
+        let text = params.find("contentChanges")
+            .and_then(|changes| changes.as_array())
+            .and_then(|changes| changes.last())
+            .and_then(|change| change.find("text"))
+            .and_then(|t| t.as_string());
+        if let (Some(uri), Some(text)) = (uri, text) {
+            libracer::vfs::set_buffer(&uri_to_path(uri), text.to_string());
+        }
+    }
+
+    fn did_close(&mut self, params: &Json) {
+        params.find_path(&["textDocument", "uri"]).and_then(|u| u.as_string()).map(|uri| {
+            libracer::vfs::remove_buffer(&uri_to_path(uri));
+        });
+    }
+
+    // Handles a single message. Returns the response body for requests
+    fn handle(&mut self, msg: &Json) -> Option<String> {
+        let method = match msg.find("method").and_then(|m| m.as_string()) {
+            Some(method) => method.to_string(),
+            None => return None    // a response from the client; ignore it
+        };
+        let null = Json::Null;
+        let params = msg.find("params").unwrap_or(&null);
+        let id = msg.find("id").map(|id| id.to_string());
+
+        let result = match method.as_slice() {
+            "initialize" => Some(concat!("{\"capabilities\":{",
+                                         "\"textDocumentSync\":1,",
+                                         "\"completionProvider\":{\"triggerCharacters\":[\".\",\":\"]},",
+                                         "\"definitionProvider\":true}}").to_string()),
+            "shutdown" => {
+                self.shutting_down = true;
+                Some("null".to_string())
+            }
+            "textDocument/completion" => Some(self.completion(params)),
+            "textDocument/definition" => Some(self.definition(params)),
+            "textDocument/didOpen" => { self.did_open(params); None }
+            "textDocument/didChange" => { self.did_change(params); None }
+            "textDocument/didClose" => { self.did_close(params); None }
+            _ => None
+        };
+
+        return id.map(|id| {
+            match result {
+                Some(result) =>
+                    format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}", id, result),
+                None =>
+                    format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":-32601,\"message\":{}}}}}",
+                            id, quote(format!("Unhandled method {}", method).as_slice()))
+            }
+        });
+    }
+}
+
+// Reads one 'Content-Length' framed message from stdin
+#[cfg(not(test))]
+fn read_message(stdin: &mut std::io::stdio::StdinReader) -> Option<String> {
+    let mut content_length = None;
+    loop {
+        let line = match stdin.read_line() {
+            Ok(line) => line,
+            Err(_) => return None
+        };
+        let line = line.as_slice().trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("Content-Length:") {
+            content_length = std::str::from_str::<uint>(line.slice_from(15).trim());
+        }
+    }
+
+    return content_length.and_then(|len| {
+        stdin.read_exact(len).ok().and_then(|bytes| String::from_utf8(bytes).ok())
+    });
+}
+
+#[cfg(not(test))]
+fn write_message(body: &str) {
+    print!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    std::io::stdio::flush();
+}
+
+#[cfg(not(test))]
+fn main() {
+    if std::os::getenv("RUST_SRC_PATH").is_none() {
+        // stdout carries the protocol, so this goes to stderr
+        let _ = std::io::stderr().write_line("RUST_SRC_PATH environment variable must be set");
+        std::os::set_exit_status(1);
+        return;
+    }
+
+    let mut stdin = std::io::stdin();
+    let mut server = Server::new();
+    loop {
+        let body = match read_message(&mut stdin) {
+            Some(body) => body,
+            None => break
+        };
+        let msg = match json::from_str(body.as_slice()) {
+            Ok(msg) => msg,
+            Err(_) => continue   // not much we can do with a garbled message
+        };
+
+        if msg.find("method").and_then(|m| m.as_string()) == Some("exit") {
+            if !server.shutting_down {
+                std::os::set_exit_status(1);
+            }
+            break;
+        }
+
+        server.handle(&msg).map(|response| write_message(response.as_slice()));
+    }
+}
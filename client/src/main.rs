@@ -1,61 +1,222 @@
-
 extern crate libracer;
+extern crate serialize;
 
 #[cfg(not(test))]
-use libracer::Match;
+use libracer::{Match, TextEdit, RacerError, RacerResult};
+#[cfg(not(test))]
+use serialize::json::Json;
 #[cfg(not(test))]
 use libracer::util::getline;
 #[cfg(not(test))]
 use libracer::nameres::{do_file_search, do_external_search};
 #[cfg(not(test))]
 use libracer::scopes;
+#[cfg(not(test))]
+use libracer::trace;
+#[cfg(not(test))]
+use libracer::trace::Step;
+#[cfg(not(test))]
+use libracer::cfg;
+
+#[cfg(not(test))]
+enum OutputFormat {
+    Text,
+    Json
+}
+
+#[cfg(not(test))]
+impl Copy for OutputFormat {}
 
 #[cfg(not(test))]
-fn match_fn(m:Match) {
-    let (linenum, charnum) = scopes::point_to_coords2(&m.filepath, m.point).unwrap();
+fn quote(s: &str) -> String {
+    Json::String(s.to_string()).to_string()
+}
+
+#[cfg(not(test))]
+fn match_fn(m:Match, format: OutputFormat) {
+    let (linenum, charnum) = match scopes::point_to_coords2(&m.filepath, m.point) {
+        Some(coords) => coords,
+        None => return
+    };
     if m.matchstr.as_slice() == "" {
-        panic!("MATCHSTR is empty - waddup?");
+        return;
+    }
+    match format {
+        OutputFormat::Text => {
+            // the context can contain commas, so new fields go after it
+            println!("MATCH {},{},{},{},{},{},{},{}", m.matchstr,
+                                            linenum.to_string(),
+                                            charnum.to_string(),
+                                            m.filepath.as_str().unwrap(),
+                                            m.mtype,
+                                            m.contextstr,
+                                            m.accessible,
+                                            m.score
+                     );
+        }
+        OutputFormat::Json => {
+            let generic_args = m.generic_args.iter()
+                .map(|a| quote(a.as_slice()))
+                .collect::<Vec<_>>();
+            println!("{{\"type\":\"match\",\"matchstr\":{},\"filepath\":{},\"line\":{},\"column\":{},\"point\":{},\"mtype\":{},\"contextstr\":{},\"local\":{},\"accessible\":{},\"score\":{},\"generic_args\":[{}]}}",
+                     quote(m.matchstr.as_slice()),
+                     quote(m.filepath.as_str().unwrap()),
+                     linenum,
+                     charnum,
+                     m.point,
+                     quote(m.mtype.to_string().as_slice()),
+                     quote(m.contextstr.as_slice()),
+                     m.local,
+                     m.accessible,
+                     m.score,
+                     generic_args.connect(","));
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn prefix_fn(start: uint, pos: uint, prefix: &str, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => println!("PREFIX {},{},{}", start, pos, prefix),
+        OutputFormat::Json =>
+            println!("{{\"type\":\"prefix\",\"start\":{},\"end\":{},\"prefix\":{}}}",
+                     start, pos, quote(prefix))
+    }
+}
+
+#[cfg(not(test))]
+fn error_fn(kind: &str, msg: &str, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => println!("ERROR {},{}", kind, msg),
+        OutputFormat::Json =>
+            println!("{{\"type\":\"error\",\"kind\":{},\"message\":{}}}", quote(kind), quote(msg))
+    }
+}
+
+#[cfg(not(test))]
+fn step_to_json(step: &Step) -> String {
+    format!("{{\"desc\":{},\"result\":{},\"failed\":{},\"steps\":[{}]}}",
+            quote(step.desc.as_slice()),
+            step.result.as_ref().map_or("null".to_string(), |r| quote(r.as_slice())),
+            step.failed,
+            step.steps.iter().map(step_to_json).collect::<Vec<_>>().connect(","))
+}
+
+#[cfg(not(test))]
+fn print_steps(steps: &[Step], depth: uint) {
+    for step in steps.iter() {
+        let result = match step.result {
+            Some(ref r) if step.failed => format!(" => FAILED: {}", r),
+            Some(ref r) => format!(" => {}", r),
+            None => "".to_string()
+        };
+        println!("EXPLAIN {}{}{}", String::from_char(depth * 2, ' '), step.desc, result);
+        print_steps(step.steps.as_slice(), depth + 1);
+    }
+}
+
+// Prints the steps taken to resolve a query, and where it broke (if it did)
+#[cfg(not(test))]
+fn explain_fn(steps: &[Step], format: OutputFormat) {
+    let broke = trace::find_break(steps);
+    match format {
+        OutputFormat::Text => {
+            print_steps(steps, 0);
+            broke.map(|step| println!("BROKE {}", step.desc));
+        }
+        OutputFormat::Json =>
+            println!("{{\"type\":\"explain\",\"steps\":[{}],\"broke\":{}}}",
+                     steps.iter().map(step_to_json).collect::<Vec<_>>().connect(","),
+                     broke.map_or("null".to_string(), |step| quote(step.desc.as_slice())))
     }
-    println!("MATCH {},{},{},{},{},{}", m.matchstr,
-                                    linenum.to_string(),
-                                    charnum.to_string(),
-                                    m.filepath.as_str().unwrap(),
-                                    m.mtype,
-                                    m.contextstr
-             );
 }
 
+// Runs the command, followed by an explanation of how it was resolved if
+// explain is set
 #[cfg(not(test))]
-fn complete() {
-    let args = std::os::args();
-    match std::str::from_str(std::os::args().as_slice()[2].as_slice()) {
-        Some(linenum) => { 
+fn with_explain<F: FnOnce()>(explain: bool, format: OutputFormat, f: F) {
+    if explain {
+        let (_, steps) = trace::record(f);
+        explain_fn(steps.as_slice(), format);
+    } else {
+        f();
+    }
+}
+
+// Parses a numeric argument, reporting an error if it isn't a number
+#[cfg(not(test))]
+fn parse_uint(arg: &str, what: &str, format: OutputFormat) -> Option<uint> {
+    let n = std::str::from_str(arg);
+    if n.is_none() {
+        error_fn("args", format!("{} should be a number, not '{}'", what, arg).as_slice(), format);
+    }
+    return n;
+}
+
+// Parses the linenum and charnum arguments that start most commands
+#[cfg(not(test))]
+fn parse_coords(args: &[String], format: OutputFormat) -> Option<(uint, uint)> {
+    let linenum = match parse_uint(args[0].as_slice(), "linenum", format) {
+        Some(n) => n,
+        None => return None
+    };
+    return parse_uint(args[1].as_slice(), "charnum", format).map(|charnum| (linenum, charnum));
+}
+
+#[cfg(not(test))]
+fn racer_error_fn(e: RacerError, format: OutputFormat) {
+    error_fn(e.kind(), e.to_string().as_slice(), format);
+}
+
+// Loads the source of fname, which is the text of its substitute file if
+// the command was given one (see run_command)
+#[cfg(not(test))]
+fn load_src(fpath: &Path) -> RacerResult<String> {
+    return libracer::try_load_file(fpath);
+}
+
+#[cfg(not(test))]
+fn complete(args: &[String], format: OutputFormat, include_inaccessible: bool) {
+    match std::str::from_str(args[0].as_slice()) {
+        Some(linenum) => {
             // input: linenum, colnum, fname
-            let charnum = std::str::from_str(std::os::args().as_slice()[3].as_slice()).unwrap();
-            let fname = args.as_slice()[4].as_slice();
+            let charnum = match parse_uint(args[1].as_slice(), "charnum", format) {
+                Some(n) => n,
+                None => return
+            };
+            let fname = args[2].as_slice();
             let fpath = Path::new(fname);
-            let src = libracer::load_file(&fpath);
-            let line = getline(&fpath, linenum);
-            let (start, pos) = libracer::util::expand_ident(line.as_slice(), charnum);
-            println!("PREFIX {},{},{}", start, pos, line.as_slice().slice(start, pos));
+            let src = match load_src(&fpath) {
+                Ok(src) => src,
+                Err(e) => return racer_error_fn(e, format)
+            };
+            if !line_prefix(&fpath, linenum, charnum, format) {
+                return;
+            }
 
             let point = scopes::coords_to_point(&*src, linenum, charnum);
-            for m in libracer::complete_from_file(&*src, &fpath, point) {
-                match_fn(m);
+            let matches = if include_inaccessible {
+                libracer::complete_from_file_including_inaccessible(&*src, &fpath, point)
+            } else {
+                libracer::complete_from_file(&*src, &fpath, point)
+            };
+            match matches {
+                Ok(matches) => for m in matches { match_fn(m, format); },
+                Err(e) => racer_error_fn(e, format)
             }
         }
         None => {
             // input: a command line string passed in
-            let arg = args.as_slice()[2].as_slice();
+            let arg = args[0].as_slice();
             let it = arg.split_str("::");
             let p : Vec<&str> = it.collect();
 
             for m in do_file_search(p.as_slice()[0], &Path::new(".")) {
                 if p.len() == 1 {
-                    match_fn(m);
+                    match_fn(m, format);
                 } else {
                     for m in do_external_search(p.slice_from(1), &m.filepath, m.point, libracer::SearchType::StartsWith, libracer::Namespace::BothNamespaces) {
-                        match_fn(m);
+                        match_fn(m, format);
                     }
                 }
             }
@@ -64,41 +225,408 @@ fn complete() {
 }
 
 #[cfg(not(test))]
-fn prefix() {
-    let args_ = std::os::args();
-    let args = args_.as_slice();
-    let linenum = std::str::from_str(args[2].as_slice()).unwrap();
-    let charnum = std::str::from_str(args[3].as_slice()).unwrap();
-    let fname = args[4].as_slice();
+fn prefix(args: &[String], format: OutputFormat) {
+    let (linenum, charnum) = match parse_coords(args, format) {
+        Some(coords) => coords,
+        None => return
+    };
+    line_prefix(&Path::new(args[2].as_slice()), linenum, charnum, format);
+}
 
-    // print the start, end, and the identifier prefix being matched
-    let path = Path::new(fname);
-    let line = getline(&path, linenum);
-    let (start, pos) = libracer::util::expand_ident(line.as_slice(), charnum);
-    println!("PREFIX {},{},{}", start, pos, line.as_slice().slice(start, pos));
+// Prints the start, end, and the identifier prefix being matched. Reports an
+// error and returns false if charnum isn't on the line.
+#[cfg(not(test))]
+fn line_prefix(fpath: &Path, linenum: uint, charnum: uint, format: OutputFormat) -> bool {
+    let line = getline(fpath, linenum);
+    let line = line.as_slice();
+    if charnum > line.len() || !line.is_char_boundary(charnum) {
+        error_fn("args", format!("charnum {} isn't a column of line {}", charnum, linenum).as_slice(),
+                 format);
+        return false;
+    }
+    let (start, pos) = libracer::util::expand_ident(line, charnum);
+    prefix_fn(start, pos, line.slice(start, pos), format);
+    return true;
 }
 
 #[cfg(not(test))]
-fn find_definition() {
-    let args_ = std::os::args();
-    let args = args_.as_slice();
-    let linenum = std::str::from_str(args[2].as_slice()).unwrap();
-    let charnum = std::str::from_str(args[3].as_slice()).unwrap();
-    let fname = args[4].as_slice();
+fn find_definition(args: &[String], format: OutputFormat, include_inaccessible: bool) {
+    let (linenum, charnum) = match parse_coords(args, format) {
+        Some(coords) => coords,
+        None => return
+    };
+    let fname = args[2].as_slice();
     let fpath = Path::new(fname);
-    let src = libracer::load_file(&fpath);
+    let src = match load_src(&fpath) {
+        Ok(src) => src,
+        Err(e) => return racer_error_fn(e, format)
+    };
     let pos = scopes::coords_to_point(&*src, linenum, charnum);
 
-    libracer::find_definition(&*src, &fpath, pos).map(match_fn);
+    let def = if include_inaccessible {
+        libracer::find_definition_including_inaccessible(&*src, &fpath, pos)
+    } else {
+        libracer::find_definition(&*src, &fpath, pos)
+    };
+    match def {
+        Ok(m) => match_fn(m, format),
+        Err(e) => racer_error_fn(e, format)
+    }
+}
+
+#[cfg(not(test))]
+fn find_references(args: &[String], format: OutputFormat) {
+    let (linenum, charnum) = match parse_coords(args, format) {
+        Some(coords) => coords,
+        None => return
+    };
+    let fname = args[2].as_slice();
+    let fpath = Path::new(fname);
+    let src = match load_src(&fpath) {
+        Ok(src) => src,
+        Err(e) => return racer_error_fn(e, format)
+    };
+    let pos = scopes::coords_to_point(&*src, linenum, charnum);
+
+    match libracer::find_references(&*src, &fpath, pos) {
+        Ok(matches) => for m in matches { match_fn(m, format); },
+        Err(e) => racer_error_fn(e, format)
+    }
+}
+
+#[cfg(not(test))]
+fn edit_fn(e: &TextEdit, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => println!("EDIT {},{},{},{}", e.start, e.end,
+                                       e.filepath.as_str().unwrap(), e.text),
+        OutputFormat::Json =>
+            println!("{{\"type\":\"edit\",\"filepath\":{},\"start\":{},\"end\":{},\"text\":{}}}",
+                     quote(e.filepath.as_str().unwrap()), e.start, e.end, quote(e.text.as_slice()))
+    }
+}
+
+// Builds a unified diff (without context lines) of the edits to one file
+#[cfg(not(test))]
+fn diff_edits(filepath: &Path, edits: &[&TextEdit]) -> String {
+    let src = libracer::load_file(filepath);
+    let mut newsrc = String::new();
+    let mut prev = 0;
+    let mut sorted = edits.to_vec();
+    sorted.sort_by(|a, b| a.start.cmp(&b.start));
+    for e in sorted.iter() {
+        newsrc.push_str(src.as_slice().slice(prev, e.start));
+        newsrc.push_str(e.text.as_slice());
+        prev = e.end;
+    }
+    newsrc.push_str(src.as_slice().slice_from(prev));
+
+    let fname = filepath.as_str().unwrap();
+    let mut out = format!("--- a/{}\n+++ b/{}\n", fname, fname);
+    // identifiers don't span lines, so the old and new lines correspond 1:1
+    for (i, (old, new)) in src.as_slice().lines().zip(newsrc.as_slice().lines()).enumerate() {
+        if old != new {
+            out.push_str(format!("@@ -{},1 +{},1 @@\n-{}\n+{}\n", i + 1, i + 1, old, new).as_slice());
+        }
+    }
+    return out;
+}
+
+#[cfg(not(test))]
+fn rename(args: &[String], format: OutputFormat) {
+    let (linenum, charnum) = match parse_coords(args, format) {
+        Some(coords) => coords,
+        None => return
+    };
+    let fname = args[2].as_slice();
+    let newname = args[3].as_slice();
+    let fpath = Path::new(fname);
+    let src = match load_src(&fpath) {
+        Ok(src) => src,
+        Err(e) => return racer_error_fn(e, format)
+    };
+    let pos = scopes::coords_to_point(&*src, linenum, charnum);
+
+    let edits = match libracer::rename(&*src, &fpath, pos, newname) {
+        Ok(edits) => edits,
+        Err(e) => return racer_error_fn(e, format)
+    };
+
+    let mut files: Vec<Path> = Vec::new();
+    for e in edits.iter() {
+        edit_fn(e, format);
+        if !files.contains(&e.filepath) {
+            files.push(e.filepath.clone());
+        }
+    }
+
+    // preview
+    for filepath in files.iter() {
+        let fileedits = edits.iter().filter(|e| e.filepath == *filepath).collect::<Vec<_>>();
+        let diff = diff_edits(filepath, fileedits.as_slice());
+        match format {
+            OutputFormat::Text => print!("{}", diff),
+            OutputFormat::Json =>
+                println!("{{\"type\":\"diff\",\"filepath\":{},\"diff\":{}}}",
+                         quote(filepath.as_str().unwrap()), quote(diff.as_slice()))
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn describe(args: &[String], format: OutputFormat) {
+    let (linenum, charnum) = match parse_coords(args, format) {
+        Some(coords) => coords,
+        None => return
+    };
+    let fname = args[2].as_slice();
+    let fpath = Path::new(fname);
+    let src = match load_src(&fpath) {
+        Ok(src) => src,
+        Err(e) => return racer_error_fn(e, format)
+    };
+    let pos = scopes::coords_to_point(&*src, linenum, charnum);
+
+    let d = match libracer::describe(&*src, &fpath, pos) {
+        Ok(d) => d,
+        Err(e) => return racer_error_fn(e, format)
+    };
+    match_fn(d.m, format);
+    match format {
+        OutputFormat::Text => {
+            for line in d.signature.as_slice().lines() {
+                println!("SIGNATURE {}", line);
+            }
+            for line in d.docs.as_slice().lines() {
+                println!("DOC {}", line);
+            }
+            d.active_arg.map(|n| println!("ARG {}", n));
+        }
+        OutputFormat::Json => {
+            let active_arg = d.active_arg.map_or("null".to_string(), |n| n.to_string());
+            println!("{{\"type\":\"description\",\"signature\":{},\"docs\":{},\"active_arg\":{}}}",
+                     quote(d.signature.as_slice()),
+                     quote(d.docs.as_slice()),
+                     active_arg);
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn symbols(args: &[String], format: OutputFormat) {
+    let query = args[0].as_slice();
+    // search the crate containing the given file, or the current directory
+    let currentdir = if args.len() > 1 {
+        Path::new(args[1].as_slice()).dir_path()
+    } else {
+        Path::new(".")
+    };
+
+    for symbol in libracer::symbols::search_symbols(query, &currentdir).into_iter() {
+        // report the fully qualified path rather than just the name
+        let mut m = symbol.m;
+        m.matchstr = symbol.path;
+        match_fn(m, format);
+    }
+}
+
+// Long running mode. Reads one request per line from stdin, using the same
+// syntax as the command line (e.g. 'complete 12 5 src/main.rs'), and writes
+// the results followed by an 'END' line so that the editor knows the
+// response is complete.
+//
+// Unsaved buffers are registered with 'buffer fname nbytes' followed by
+// exactly nbytes of file text, and dropped again with 'clear-buffer fname'.
+// 'chosen linenum charnum fname' reports the completion the user picked, so
+// that it ranks higher in later completions.
+#[cfg(not(test))]
+fn daemon(format: OutputFormat) {
+    let mut stdin = std::io::stdin();
+    loop {
+        let line = match stdin.read_line() {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let args = line.as_slice().words().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (args, format) = parse_format(args, format);
+        if args.is_empty() {
+            continue;
+        }
+
+        let command = args[0].as_slice();
+        match command {
+            "quit" => break,
+            "buffer" if args.len() == 3 => {
+                if let Some(nbytes) = parse_uint(args[2].as_slice(), "nbytes", format) {
+                    match stdin.read_exact(nbytes) {
+                        Ok(bytes) => match String::from_utf8(bytes) {
+                            Ok(text) => libracer::vfs::set_buffer(&Path::new(args[1].as_slice()), text),
+                            Err(_) => error_fn("utf8", format!("the buffer for {} is not valid utf-8",
+                                                               args[1]).as_slice(), format)
+                        },
+                        Err(e) => error_fn("io", format!("couldn't read the buffer for {}: {}",
+                                                         args[1], e).as_slice(), format)
+                    }
+                }
+            }
+            "clear-buffer" if args.len() == 2 => {
+                libracer::vfs::remove_buffer(&Path::new(args[1].as_slice()));
+            }
+            "chosen" if args.len() == 4 => {
+                // the line, column and file of the MATCH the user picked
+                let fpath = Path::new(args[3].as_slice());
+                if let Some((linenum, charnum)) = parse_coords(args.slice_from(1), format) {
+                    let src = libracer::load_file(&fpath);
+                    let point = scopes::coords_to_point(src.as_slice(), linenum, charnum);
+                    libracer::ranking::record_choice(&fpath, point);
+                }
+            }
+            "daemon" => println!("Sorry, I didn't understand command {}", command),
+            _ => {
+                if !run_command(command, args.slice_from(1), format) {
+                    println!("Sorry, I didn't understand command {}", command);
+                }
+            }
+        }
+        println!("END");
+        std::io::stdio::flush();
+    }
+}
+
+#[cfg(not(test))]
+fn has_enough_args(command: &str, args: &[String]) -> bool {
+    match command {
+        "complete" => args.len() == 1 || args.len() >= 3,
+        "prefix" | "find-definition" | "find-references" | "describe" => args.len() >= 3,
+        "rename" => args.len() >= 4,
+        "symbols" => args.len() >= 1,
+        _ => true
+    }
+}
+
+// Removes a '--format text|json' option from the args, returning the
+// remaining args and the chosen format
+#[cfg(not(test))]
+fn parse_format(args: Vec<String>, default: OutputFormat) -> (Vec<String>, OutputFormat) {
+    let mut format = default;
+    let mut out = Vec::new();
+    let mut it = args.into_iter();
+    loop {
+        match it.next() {
+            Some(ref arg) if arg.as_slice() == "--format" => {
+                match it.next() {
+                    Some(ref f) if f.as_slice() == "json" => format = OutputFormat::Json,
+                    _ => format = OutputFormat::Text
+                }
+            }
+            Some(arg) => out.push(arg),
+            None => break
+        }
+    }
+    return (out, format);
+}
+
+// Removes the '--cfg opt' options from the args, returning the remaining args
+// and the configuration they describe
+#[cfg(not(test))]
+fn parse_cfg(args: &[String]) -> Result<(Vec<String>, cfg::Config), String> {
+    let mut config = cfg::Config::host();
+    let mut out = Vec::new();
+    let mut it = args.iter();
+    loop {
+        match it.next() {
+            Some(arg) if arg.as_slice() == "--cfg" => {
+                match it.next() {
+                    Some(opt) => try!(config.set(opt.as_slice())),
+                    None => return Err("--cfg needs an option, e.g. --cfg test".to_string())
+                }
+            }
+            Some(arg) => out.push(arg.clone()),
+            None => break
+        }
+    }
+    return Ok((out, config));
+}
+
+// Runs a single command. Returns false if the command wasn't understood
+#[cfg(not(test))]
+fn run_command(command: &str, args: &[String], format: OutputFormat) -> bool {
+    let explain = args.iter().any(|a| a.as_slice() == "--explain");
+    let include_inaccessible = args.iter().any(|a| a.as_slice() == "--include-inaccessible");
+    let (argsvec, config) = match parse_cfg(args) {
+        Ok(res) => res,
+        Err(msg) => {
+            error_fn("cfg", msg.as_slice(), format);
+            return true;
+        }
+    };
+    // each command starts from the host configuration, so that options
+    // given to one daemon command don't stick
+    cfg::set_config(config);
+    let argsvec = argsvec.into_iter()
+        .filter(|a| a.as_slice() != "--explain" && a.as_slice() != "--include-inaccessible")
+        .collect::<Vec<_>>();
+    let args = argsvec.as_slice();
+    if !has_enough_args(command, args) {
+        return false;
+    }
+
+    // a substitute file holds the (unsaved) text of fname. This lets editors
+    // write their buffer to a temp file while racer still resolves modules
+    // relative to the real file. The text stands in for fname only until the
+    // command is done, so that later daemon commands see the file again.
+    let substitute = match command {
+        "complete" | "find-definition" | "find-references" | "describe" if args.len() > 3 => Some(3u),
+        "rename" if args.len() > 4 => Some(4u),
+        _ => None
+    };
+    return match substitute {
+        Some(i) => match libracer::try_load_file(&Path::new(args[i].as_slice())) {
+            Ok(text) => libracer::vfs::with_buffer(&Path::new(args[2].as_slice()), text, || {
+                dispatch(command, args, format, explain, include_inaccessible)
+            }),
+            Err(e) => {
+                racer_error_fn(e, format);
+                true
+            }
+        },
+        None => dispatch(command, args, format, explain, include_inaccessible)
+    };
+}
+
+#[cfg(not(test))]
+fn dispatch(command: &str, args: &[String], format: OutputFormat, explain: bool,
+            include_inaccessible: bool) -> bool {
+    match command {
+        "prefix" => prefix(args, format),
+        "complete" => with_explain(explain, format, || complete(args, format, include_inaccessible)),
+        "find-definition" => with_explain(explain, format, || find_definition(args, format, include_inaccessible)),
+        "find-references" => find_references(args, format),
+        "rename" => rename(args, format),
+        "describe" => describe(args, format),
+        "symbols" => symbols(args, format),
+        "daemon" => daemon(format),
+        "help" => print_usage(),
+        _ => return false
+    }
+    return true;
 }
 
 #[cfg(not(test))]
 fn print_usage() {
     let program = std::os::args().as_slice()[0].clone();
-    println!("usage: {} complete linenum charnum fname", program);
-    println!("or:    {} find-definition linenum charnum fname", program);
+    println!("usage: {} complete linenum charnum fname [substitute_file]", program);
+    println!("or:    {} find-definition linenum charnum fname [substitute_file]", program);
+    println!("or:    {} find-references linenum charnum fname [substitute_file]", program);
+    println!("or:    {} rename linenum charnum fname newname [substitute_file]", program);
+    println!("or:    {} describe linenum charnum fname [substitute_file]", program);
+    println!("or:    {} symbols query [fname]   (fuzzy search for items in the crate)", program);
     println!("or:    {} complete fullyqualifiedname   (e.g. std::io::)",program);
     println!("or:    {} prefix linenum charnum fname",program);
+    println!("or:    {} daemon   (reads commands from stdin, one per line)",program);
+    println!("options: --format text|json   (json prints one object per line)");
+    println!("         --explain   (complete and find-definition also print how the result was resolved)");
+    println!("         --include-inaccessible   (complete and find-definition also return private items of other modules)");
+    println!("         --cfg opt   (e.g. --cfg test or --cfg 'feature=\"foo\"', for items with #[cfg] attributes)");
 }
 
 
@@ -110,7 +638,7 @@ fn main() {
         return;
     }
 
-    let args_ = std::os::args();
+    let (args_, format) = parse_format(std::os::args(), OutputFormat::Text);
     let args = args_.as_slice();
 
     if args.len() == 1 {
@@ -120,16 +648,10 @@ fn main() {
     }
 
     let command = args[1].as_slice();
-    match command.as_slice() {
-        "prefix" => prefix(),
-        "complete" => complete(),
-        "find-definition" => find_definition(),
-        "help" => print_usage(),
-        _ => { 
-            println!("Sorry, I didn't understand command {}", command ); 
-            print_usage(); 
-            std::os::set_exit_status(1);
-            return;
-        }
+    if !run_command(command, args.slice_from(2), format) {
+        println!("Sorry, I didn't understand command {}", command );
+        print_usage();
+        std::os::set_exit_status(1);
+        return;
     }
 }
@@ -7,7 +7,7 @@ use syntax::parse::parser::Parser;
 use syntax::parse::token;
 use syntax::visit;
 use syntax::codemap;
-use std::task;
+use super::query;
 use super::Match;
 use super::nameres::{resolve_path_with_str};
 use super::typeinf;
@@ -16,6 +16,9 @@ use super::Ty::{TyTuple, TyPathSearch, TyMatch, TyUnsupported};
 use syntax::ptr::P;
 use syntax::visit::Visitor;
 use super::nameres;
+use super::trace;
+use super::macros;
+use syntax::print::pprust;
 
 // This code ripped from libsyntax::util::parser_testing
 pub fn string_to_parser<'a>(ps: &'a ParseSess, source_str: String) -> Parser<'a> {
@@ -89,7 +92,8 @@ impl<'v> visit::Visitor<'v> for ViewItemVisitor {
                                     newpath.segments.push(seg);
                                     self.paths.push(newpath);
                                 }
-                                ast::PathListMod{..} => (), // TODO
+                                // 'use foo::{self}' imports foo itself
+                                ast::PathListMod{..} => self.paths.push(basepath.clone()),
                             }
                         }
                     }
@@ -134,11 +138,12 @@ impl<'v> visit::Visitor<'v> for LetVisitor {
     }
 
     fn visit_expr(&mut self, ex: &'v ast::Expr) { 
-        // don't visit the RHS or block of an 'if let' stmt
-        if let ast::ExprIfLet(ref pattern, _,_,_) = ex.node {
-            self.visit_pat(&**pattern);
-        } else {
-            visit::walk_expr(self, ex) 
+        // don't visit the RHS or block of an 'if let', 'while let' or 'for' stmt
+        match ex.node {
+            ast::ExprIfLet(ref pattern, ..) | ast::ExprWhileLet(ref pattern, ..) |
+            ast::ExprForLoop(ref pattern, ..) =>
+                self.visit_pat(&**pattern),
+            _ => visit::walk_expr(self, ex)
         }
     }
 
@@ -194,10 +199,77 @@ fn to_racer_ty(ty: &ast::Ty, scope: &Scope) -> Option<Ty> {
         ast::TyPath(ref path, _) => {
             Some(TyPathSearch(to_racer_path(path), scope.clone()))
         }
+        ast::TyVec(ref ty) | ast::TyFixedLengthVec(ref ty, _) => {
+            let elemtypes = match to_racer_ty(&**ty, scope) {
+                Some(TyPathSearch(path, _)) => vec![path],
+                _ => Vec::new()
+            };
+            Some(TyPathSearch(slice_type_path(elemtypes), scope.clone()))
+        }
         _ => None
     }
 }
 
+// The builtin types, which have no definition to find. Slices and arrays
+// are all '[T]', with the element type as their generic type
+static BUILTIN_TYPES: &'static [&'static str] = &[
+    "str", "char", "bool", "int", "i8", "i16", "i32", "i64",
+    "uint", "u8", "u16", "u32", "u64", "f32", "f64", "[T]"];
+
+fn is_builtin_type(name: &str) -> bool {
+    return BUILTIN_TYPES.iter().any(|t| *t == name);
+}
+
+// The path of the slice type with the given element type (if known)
+fn slice_type_path(elemtypes: Vec<super::Path>) -> super::Path {
+    return super::Path{ global: false,
+                        segments: vec![super::PathSegment{ name: "[T]".to_string(),
+                                                           types: elemtypes }] };
+}
+
+fn builtin_type_match(name: &str, filepath: &Path) -> Match {
+    let generic_args = if name == "[T]" { vec!["T".to_string()] } else { Vec::new() };
+    return Match { matchstr: name.to_string(),
+                   filepath: filepath.clone(),
+                   point: 0,
+                   local: false,
+                   accessible: true,
+                   score: 0,
+                   mtype: MatchType::Builtin,
+                   contextstr: name.to_string(),
+                   generic_args: generic_args,
+                   generic_types: Vec::new() };
+}
+
+// The type of a literal, e.g. uint for 1u, or [u8] for b"abc"
+fn lit_type_path(lit: &ast::Lit_) -> super::Path {
+    let name = match *lit {
+        ast::LitStr(..) => "str",
+        ast::LitBinary(_) => return slice_type_path(vec![super::Path::from_str("u8")]),
+        ast::LitByte(_) => "u8",
+        ast::LitChar(_) => "char",
+        ast::LitInt(_, ast::SignedIntLit(t, _)) => match t {
+            ast::TyI => "int",
+            ast::TyI8 => "i8",
+            ast::TyI16 => "i16",
+            ast::TyI32 => "i32",
+            ast::TyI64 => "i64"
+        },
+        ast::LitInt(_, ast::UnsignedIntLit(t)) => match t {
+            ast::TyU => "uint",
+            ast::TyU8 => "u8",
+            ast::TyU16 => "u16",
+            ast::TyU32 => "u32",
+            ast::TyU64 => "u64"
+        },
+        ast::LitInt(_, ast::UnsuffixedIntLit(_)) => "int",
+        ast::LitFloat(_, ast::TyF32) => "f32",
+        ast::LitFloat(_, ast::TyF64) | ast::LitFloatUnsuffixed(_) => "f64",
+        ast::LitBool(_) => "bool"
+    };
+    return super::Path::from_str(name);
+}
+
 
 fn point_is_in_span(point: u32, span: &codemap::Span) -> bool {
     let codemap::BytePos(lo) = span.lo;
@@ -217,7 +289,8 @@ fn destructure_pattern_to_ty(pat: &ast::Pat,
                 debug!("destructure_pattern_to_ty matched an ident!");
                 return Some(ty.clone());
             } else {
-                panic!("Expecting the point to be in the patident span. pt: {}", point);
+                debug!("destructure_pattern_to_ty: point {} isn't in the patident span", point);
+                return None;
             }
         }
         ast::PatTup(ref tuple_elements) => {
@@ -235,8 +308,10 @@ fn destructure_pattern_to_ty(pat: &ast::Pat,
                     }
                     res
                 }
-                _ => panic!("Expecting TyTuple")
-                
+                _ => {
+                    debug!("destructure_pattern_to_ty: expected a tuple type, got {}", ty);
+                    None
+                }
             }
         }
         ast::PatEnum(ref path, ref children) => {
@@ -271,6 +346,31 @@ fn destructure_pattern_to_ty(pat: &ast::Pat,
                 None
             }
         }
+        ast::PatStruct(ref path, ref fields, _) => {
+            let m = resolve_ast_path(path, &scope.filepath, scope.point);
+            debug!("destructure_pattern_to_ty: struct pattern path resolved to {}", m);
+
+            let contextty = path_to_match(ty.clone());
+            match m {
+                Some(ref m) if m.mtype == MatchType::Struct => {
+                    let field = fields.iter()
+                        .find(|field| point_is_in_span(point as u32, &field.node.pat.span));
+                    field.and_then(|field| {
+                        let fieldname = token::get_ident(field.node.ident).get().to_string();
+                        typeinf::get_struct_field_type(fieldname.as_slice(), m)
+                            .and_then(|ty|
+                                // if context ty is a match, use its generics
+                                if let Some(Ty::TyMatch(ref contextmatch)) = contextty {
+                                    path_to_match_including_generics(ty, contextmatch)
+                                } else {
+                                    path_to_match(ty)
+                                })
+                            .and_then(|ty| destructure_pattern_to_ty(&*field.node.pat, point, &ty, scope))
+                    })
+                }
+                _ => None
+            }
+        }
         _ => {
             debug!("Could not destructure pattern {}", pat);
             None
@@ -288,20 +388,55 @@ struct LetTypeVisitor {
 impl<'v> visit::Visitor<'v> for LetTypeVisitor {
     
     fn visit_expr(&mut self, ex: &'v ast::Expr) { 
-        if let ast::ExprIfLet(ref pattern, ref expr, _, _) = ex.node {
-            debug!("PHIL lettypevisitor - iflet pattern");
-            let mut v = ExprTypeVisitor{ scope: self.scope.clone(),
-                                         result: None};
-            v.visit_expr(&**expr);
-
-            debug!("PHIL lettypevisitor: expr is {}", v.result);
-
-            self.result = v.result.and_then(|ty|
-                   destructure_pattern_to_ty(&**pattern, self.pos, 
-                                             &ty, &self.scope))
-                .and_then(|ty| path_to_match(ty));
-        } else {
-            visit::walk_expr(self, ex) 
+        match ex.node {
+            ast::ExprIfLet(ref pattern, ref expr, ..) |
+            ast::ExprWhileLet(ref pattern, ref expr, ..) => {
+                debug!("PHIL lettypevisitor - iflet pattern");
+                let mut v = ExprTypeVisitor{ scope: self.scope.clone(),
+                                             result: None};
+                v.visit_expr(&**expr);
+
+                debug!("PHIL lettypevisitor: expr is {}", v.result);
+
+                self.result = v.result.and_then(|ty|
+                       destructure_pattern_to_ty(&**pattern, self.pos, 
+                                                 &ty, &self.scope))
+                    .and_then(|ty| path_to_match(ty));
+            }
+            ast::ExprForLoop(ref pattern, ref expr, ..) => {
+                let mut v = ExprTypeVisitor{ scope: self.scope.clone(),
+                                             result: None};
+                v.visit_expr(&**expr);
+                debug!("lettypevisitor: for loop expr is {}", v.result);
+
+                // the pattern binds the items the expr iterates over
+                self.result = v.result.and_then(|ty| match ty {
+                        TyMatch(ref m) => typeinf::get_iterator_item_type(m).map(TyMatch),
+                        _ => None
+                    })
+                    .and_then(|ty| destructure_pattern_to_ty(&**pattern, self.pos,
+                                                             &ty, &self.scope))
+                    .and_then(|ty| path_to_match(ty));
+            }
+            ast::ExprMatch(ref expr, ref arms, _) => {
+                // the arm pattern containing pos, typed from the matched expr
+                let pattern = arms.iter().flat_map(|arm| arm.pats.iter())
+                    .find(|pat| point_is_in_span(self.pos as u32, &pat.span));
+                if let Some(pattern) = pattern {
+                    let mut v = ExprTypeVisitor{ scope: self.scope.clone(),
+                                                 result: None};
+                    v.visit_expr(&**expr);
+                    debug!("lettypevisitor: match expr is {}", v.result);
+
+                    self.result = v.result.and_then(|ty|
+                           destructure_pattern_to_ty(&**pattern, self.pos,
+                                                     &ty, &self.scope))
+                        .and_then(|ty| path_to_match(ty));
+                } else {
+                    visit::walk_expr(self, ex)
+                }
+            }
+            _ => visit::walk_expr(self, ex)
         }
     }
 
@@ -344,6 +479,13 @@ fn to_racer_path(pth: &ast::Path) -> super::Path {
                 ast::TyPath(ref path, _) => {
                     types.push(to_racer_path(path));
                 }
+                // treat refs the same as the types they refer to, as
+                // to_racer_ty does. e.g. Iterator<&'a T>
+                ast::TyRptr(_, ref mt) => {
+                    if let ast::TyPath(ref path, _) = mt.ty.node {
+                        types.push(to_racer_path(path));
+                    }
+                }
                 _ => {}
             }
         }
@@ -352,7 +494,7 @@ fn to_racer_path(pth: &ast::Path) -> super::Path {
     return super::Path{ global: pth.global, segments: v} ;
 }
 
-fn path_to_match(ty: Ty) -> Option<Ty> {
+pub fn path_to_match(ty: Ty) -> Option<Ty> {
     return match ty {
         TyPathSearch(ref path, ref scope) => 
             find_type_match(path, &scope.filepath, scope.point),
@@ -362,6 +504,10 @@ fn path_to_match(ty: Ty) -> Option<Ty> {
 
 fn find_type_match(path: &super::Path, fpath: &Path, pos: uint) -> Option<Ty> {
     debug!("find_type_match {}",path);
+    if !path.global && path.segments.len() == 1 && is_builtin_type(path.segments[0].name.as_slice()) {
+        let m = builtin_type_match(path.segments[0].name.as_slice(), fpath);
+        return Some(add_generic_types(m, path, fpath, pos));
+    }
     let res = resolve_path_with_str(path, fpath, pos, super::SearchType::ExactMatch,
                super::Namespace::TypeNamespace).nth(0).and_then(|m| {
                    match m.mtype {
@@ -370,22 +516,24 @@ fn find_type_match(path: &super::Path, fpath: &Path, pos: uint) -> Option<Ty> {
                    }
                });
 
-    return res.and_then(|m|{
-        // add generic types to match (if any)
-        let types: Vec<super::PathSearch> = path.generic_types()
-            .map(|typepath| 
-                 super::PathSearch{ 
-                     path: typepath.clone(),
-                     filepath: fpath.clone(),
-                     point: pos
-                 }).collect();
-
-        if types.is_empty() {
-            return Some(TyMatch(m));
-        } else {
-            return Some(TyMatch(m.with_generic_types(types.clone())));
-        }
-    });
+    return res.map(|m| add_generic_types(m, path, fpath, pos));
+}
+
+// The type in m with the generic types given in path (if any)
+fn add_generic_types(m: Match, path: &super::Path, fpath: &Path, pos: uint) -> Ty {
+    let types: Vec<super::PathSearch> = path.generic_types()
+        .map(|typepath| 
+             super::PathSearch{ 
+                 path: typepath.clone(),
+                 filepath: fpath.clone(),
+                 point: pos
+             }).collect();
+
+    if types.is_empty() {
+        return TyMatch(m);
+    } else {
+        return TyMatch(m.with_generic_types(types));
+    }
 }
 
 fn get_type_of_typedef(m: Match) -> Option<Match> {
@@ -406,6 +554,16 @@ fn get_type_of_typedef(m: Match) -> Option<Match> {
 }
 
 
+// A short description of the type, for explain output
+fn ty_to_string(ty: &Ty) -> String {
+    match *ty {
+        TyMatch(ref m) => format!("{} {} ({}:{})", m.mtype, m.matchstr, m.filepath.display(), m.point),
+        TyPathSearch(ref path, _) => format!("{}", path),
+        TyTuple(ref tys) => format!("({})", tys.iter().map(ty_to_string).collect::<Vec<_>>().connect(", ")),
+        TyUnsupported => "an unsupported type".to_string()
+    }
+}
+
 struct ExprTypeVisitor {
     scope: Scope,
     result: Option<Ty>
@@ -413,6 +571,87 @@ struct ExprTypeVisitor {
 
 impl<'v> visit::Visitor<'v> for ExprTypeVisitor {
     fn visit_expr(&mut self, expr: &ast::Expr) {
+        let span = trace::span(|| format!("infer the type of '{}'", pprust::expr_to_string(expr)));
+        self.visit_expr_(expr);
+        match self.result {
+            Some(ref ty) => span.succeed(ty_to_string(ty)),
+            None => span.fail("unknown type".to_string())
+        }
+    }
+
+    // a bare invocation parses as a macro stmt rather than an expression
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        self.result = self.type_of_macro(mac);
+    }
+}
+
+impl ExprTypeVisitor {
+    // The type of a slice or array literal, from the type of its first element
+    fn slice_of(&mut self, elem: Option<&ast::Expr>) -> Option<Ty> {
+        let elemtype = elem.and_then(|elem| {
+            self.visit_expr(elem);
+            match self.result {
+                Some(TyMatch(ref m)) => Some(super::PathSearch{
+                    path: super::Path::from_str(m.matchstr.as_slice()),
+                    filepath: m.filepath.clone(),
+                    point: m.point
+                }),
+                _ => None
+            }
+        });
+        let m = builtin_type_match("[T]", &self.scope.filepath);
+        return Some(TyMatch(m.with_generic_types(elemtype.into_iter().collect())));
+    }
+
+    // The type of a macro invocation, from its expansion if the macro is
+    // defined in the crate. std's vec! and try! expand to code that can't be
+    // typed outside of std, so are special cased
+    fn type_of_macro(&mut self, mac: &ast::Mac) -> Option<Ty> {
+        let ast::MacInvocTT(ref path, ref tts, _) = mac.node;
+        let name = match path.segments.last() {
+            Some(segment) => token::get_ident(segment.identifier).get().to_string(),
+            None => return None
+        };
+        let args = pprust::tts_to_string(tts.as_slice());
+
+        if let Some(expansion) = macros::expand(name.as_slice(), args.as_slice(),
+                                                &self.scope.filepath) {
+            trace::note(|| format!("{}! expands to '{}'", name, expansion));
+            self.visit_expr(&*string_to_expr(expansion));
+            return self.result.clone();
+        }
+
+        match name.as_slice() {
+            "vec" => {
+                // the same as the slice literal, but in a Vec
+                self.visit_expr(&*string_to_expr(format!("[{}]", args)));
+                let elemtypes = match self.result {
+                    Some(TyMatch(ref m)) => m.generic_types.clone(),
+                    _ => Vec::new()
+                };
+                find_type_match(&super::Path::from_str("Vec"),
+                                &self.scope.filepath,
+                                self.scope.point).map(|ty| match ty {
+                    TyMatch(m) => TyMatch(m.with_generic_types(elemtypes)),
+                    ty => ty
+                })
+            }
+            "try" => {
+                // the Ok type of the result
+                self.visit_expr(&*string_to_expr(args));
+                match self.result {
+                    Some(TyMatch(ref m)) => typeinf::get_first_generic_type(m).map(TyMatch),
+                    _ => None
+                }
+            }
+            _ => {
+                debug!("ExprTypeVisitor: can't expand macro {}!", name);
+                None
+            }
+        }
+    }
+
+    fn visit_expr_(&mut self, expr: &ast::Expr) {
         debug!("visit_expr {}",expr);
         //walk_expr(self, ex, e) 
         match expr.node {
@@ -467,17 +706,21 @@ impl<'v> visit::Visitor<'v> for ExprTypeVisitor {
                 self.result = self.result.as_ref().and_then(|contextm|{
                     match contextm {
                         &TyMatch(ref contextm) => {
-                            let omethod = nameres::search_for_impl_methods(
-                                contextm.matchstr.as_slice(),
-                                methodname.as_slice(), 
-                                contextm.point, 
-                                &contextm.filepath,
-                                contextm.local,
-                                super::SearchType::ExactMatch).nth(0);
-                            omethod
-                                .and_then(|method| 
-                                          super::typeinf::get_return_type_of_function(&method))
-                                .and_then(|ty| path_to_match_including_generics(ty, contextm))
+                            // autoderef: the method of the first type in the chain that has it
+                            for contextm in typeinf::deref_chain(contextm.clone()).iter() {
+                                let omethod = nameres::search_for_impl_methods(
+                                    contextm.matchstr.as_slice(),
+                                    methodname.as_slice(), 
+                                    contextm.point, 
+                                    &contextm.filepath,
+                                    contextm.local,
+                                    super::SearchType::ExactMatch).nth(0);
+                                if let Some(method) = omethod {
+                                    return super::typeinf::get_return_type_of_function(&method)
+                                        .and_then(|ty| path_to_match_including_generics(ty, contextm));
+                                }
+                            }
+                            None
                         }
                         _ => None
                     }
@@ -492,12 +735,20 @@ impl<'v> visit::Visitor<'v> for ExprTypeVisitor {
                       .and_then(|structm| 
                                 match structm {
                                     &TyMatch(ref structm) => {
-                                typeinf::get_struct_field_type(fieldname.as_slice(), structm)
-                                .and_then(|fieldtypepath| 
+                                // autoderef: the field of the first struct in the chain that has it
+                                for structm in typeinf::deref_chain(structm.clone()).iter()
+                                                   .filter(|m| m.mtype == MatchType::Struct) {
+                                    let fieldtype = typeinf::get_struct_field_type(fieldname.as_slice(), structm)
+                                        .and_then(|fieldtypepath| 
                                           find_type_match_including_generics(&fieldtypepath,
                                                                              &structm.filepath,
                                                                              structm.point,
-                                                                             structm))
+                                                                             structm));
+                                    if fieldtype.is_some() {
+                                        return fieldtype;
+                                    }
+                                }
+                                None
                                     },
                                     _ => None
                                 });
@@ -519,12 +770,92 @@ impl<'v> visit::Visitor<'v> for ExprTypeVisitor {
                 self.result = Some(TyTuple(v));
             }
 
-            ast::ExprLit(_) => {
-                self.result = Some(TyUnsupported);
+            ast::ExprLit(ref lit) => {
+                self.result = find_type_match(&lit_type_path(&lit.node),
+                                              &self.scope.filepath,
+                                              self.scope.point);
+            }
+
+            ast::ExprVec(ref exprs) => {
+                self.result = self.slice_of(exprs.iter().nth(0).map(|e| &**e));
+            }
+
+            ast::ExprRepeat(ref expr, _) => {
+                self.result = self.slice_of(Some(&**expr));
+            }
+
+            ast::ExprParen(ref expr) | ast::ExprAddrOf(_, ref expr) => {
+                // refs are treated the same as the types they refer to
+                self.visit_expr(&**expr);
+            }
+
+            ast::ExprCast(_, ref ty) => {
+                self.result = to_racer_ty(&**ty, &self.scope).and_then(path_to_match);
+            }
+
+            ast::ExprIndex(ref objexpr, _) => {
+                self.visit_expr(&**objexpr);
+                self.result = self.result.as_ref().and_then(|ty| match ty {
+                    &TyMatch(ref m) => typeinf::get_index_type(m).map(TyMatch),
+                    _ => None
+                });
+            }
+
+            ast::ExprBinary(op, ref lhs, _) => {
+                let traitname = match op {
+                    ast::BiAdd => "Add",
+                    ast::BiSub => "Sub",
+                    ast::BiMul => "Mul",
+                    ast::BiDiv => "Div",
+                    ast::BiRem => "Rem",
+                    ast::BiBitXor => "BitXor",
+                    ast::BiBitAnd => "BitAnd",
+                    ast::BiBitOr => "BitOr",
+                    ast::BiShl => "Shl",
+                    ast::BiShr => "Shr",
+                    // comparisons and '&&', '||'
+                    _ => {
+                        self.result = find_type_match(&super::Path::from_str("bool"),
+                                                      &self.scope.filepath,
+                                                      self.scope.point);
+                        return;
+                    }
+                };
+                self.visit_expr(&**lhs);
+                self.result = self.result.as_ref().and_then(|ty| match ty {
+                    &TyMatch(ref m) => typeinf::get_operator_type(m, traitname).map(TyMatch),
+                    _ => None
+                });
+            }
+
+            ast::ExprUnary(op, ref expr) => {
+                self.visit_expr(&**expr);
+                self.result = self.result.as_ref().and_then(|ty| match (op, ty) {
+                    // refs are transparent, so '*' only changes the type of
+                    // types that implement Deref
+                    (ast::UnDeref, &TyMatch(ref m)) =>
+                        Some(TyMatch(typeinf::get_deref_target(m).unwrap_or(m.clone()))),
+                    (ast::UnNeg, &TyMatch(ref m)) => typeinf::get_operator_type(m, "Neg").map(TyMatch),
+                    (ast::UnNot, &TyMatch(ref m)) => typeinf::get_operator_type(m, "Not").map(TyMatch),
+                    _ => None
+                });
+            }
+
+            ast::ExprBlock(ref block) => {
+                // the value of a block is its trailing expression
+                match block.expr {
+                    Some(ref expr) => self.visit_expr(&**expr),
+                    None => self.result = None
+                }
+            }
+
+            ast::ExprMac(ref mac) => {
+                self.result = self.type_of_macro(mac);
             }
 
             _ => {
-                println!("- Could not match expr node type: {}",expr.node);
+                debug!("- Could not match expr node type: {}",expr.node);
+                trace::note(|| "expressions of this kind aren't supported".to_string());
             }
         }
     }
@@ -532,7 +863,7 @@ impl<'v> visit::Visitor<'v> for ExprTypeVisitor {
 }
 
 // gets generics info from the context match
-fn path_to_match_including_generics(ty: Ty, contextm: &super::Match) -> Option<Ty> {
+pub fn path_to_match_including_generics(ty: Ty, contextm: &super::Match) -> Option<Ty> {
     return match ty {
         TyPathSearch(ref fieldtypepath, ref scope) => {
 
@@ -552,11 +883,37 @@ fn path_to_match_including_generics(ty: Ty, contextm: &super::Match) -> Option<T
             }
 
             find_type_match(fieldtypepath, &scope.filepath, scope.point)
+                .map(|ty| substitute_generic_types(ty, contextm))
         }
         _ => Some(ty)
     };
 }
 
+// Replaces those generic types of ty that are generic args of contextm with
+// contextm's types for them. e.g. the Items<T> returned by a method of
+// Vec<Foo> becomes Items<Foo>
+fn substitute_generic_types(ty: Ty, contextm: &super::Match) -> Ty {
+    return match ty {
+        TyMatch(m) => {
+            let types = m.generic_types.iter().map(|typesearch| {
+                if typesearch.path.segments.len() == 1 {
+                    let ref typename = typesearch.path.segments[0].name;
+                    let mut it = contextm.generic_args.iter()
+                        .zip(contextm.generic_types.iter());
+                    for (name, contexttype) in it {
+                        if name == typename {
+                            return contexttype.clone();
+                        }
+                    }
+                }
+                typesearch.clone()
+            }).collect();
+            TyMatch(m.with_generic_types(types))
+        }
+        _ => ty
+    };
+}
+
 
 fn find_type_match_including_generics(fieldtype: &super::Ty,
                                       filepath: &Path,
@@ -685,6 +1042,10 @@ impl<'v> visit::Visitor<'v> for ImplVisitor {
                     ast::TyPath(ref path, _) => {
                         self.name_path = Some(to_racer_path(path));
                     }
+                    // slices, which are all named '[T]' (see BUILTIN_TYPES)
+                    ast::TyVec(_) => {
+                        self.name_path = Some(slice_type_path(Vec::new()));
+                    }
                     ast::TyRptr(_, ref ty) => {
                         // HACK for now, treat refs the same as unboxed types 
                         // so that we can match '&str' to 'str'
@@ -692,6 +1053,9 @@ impl<'v> visit::Visitor<'v> for ImplVisitor {
                             ast::TyPath(ref path, _) => {
                                 self.name_path = Some(to_racer_path(path));
                             }
+                            ast::TyVec(_) => {
+                                self.name_path = Some(slice_type_path(Vec::new()));
+                            }
                             _ => {}
                         }
                     }
@@ -815,6 +1179,69 @@ impl<'v> visit::Visitor<'v> for FnVisitor {
 
 }
 
+// Finds the type of an argument of a closure passed as a parameter of the
+// visited fn, from the parameter's closure type or Fn/FnMut/FnOnce bound
+pub struct ClosureParamVisitor {
+    pub paramindex: uint,
+    pub argindex: uint,
+    pub scope: Scope,
+    pub result: Option<super::Ty>
+}
+
+impl ClosureParamVisitor {
+    // e.g. T in 'FnMut(T) -> bool'
+    fn fn_sugar_arg(&self, path: &ast::Path) -> Option<super::Ty> {
+        let seg = match path.segments.last() {
+            Some(seg) => seg,
+            None => return None
+        };
+        let name = token::get_ident(seg.identifier).get().to_string();
+        if name.as_slice() != "Fn" && name.as_slice() != "FnMut" && name.as_slice() != "FnOnce" {
+            return None;
+        }
+        return match seg.parameters {
+            ast::ParenthesizedParameters(ref data) =>
+                data.inputs.iter().nth(self.argindex).and_then(|ty| to_racer_ty(&**ty, &self.scope)),
+            _ => None
+        };
+    }
+}
+
+impl<'v> visit::Visitor<'v> for ClosureParamVisitor {
+    fn visit_fn(&mut self, fk: visit::FnKind, fd: &ast::FnDecl, _: &ast::Block, _: codemap::Span, _: ast::NodeId) {
+        let generics = match fk {
+            visit::FkItemFn(_, generics, _, _) | visit::FkMethod(_, generics, _) => generics,
+            visit::FkFnBlock(..) => return
+        };
+        let param = match fd.inputs.iter().nth(self.paramindex) {
+            Some(param) => param,
+            None => return
+        };
+        let result = match param.ty.node {
+            // e.g. 'f: F' where F: Fn(T)
+            ast::TyPath(ref path, _) if path.segments.len() == 1 => {
+                let typename = path.segments[0].identifier;
+                let mut result = None;
+                for typaram in generics.ty_params.iter().filter(|t| t.ident == typename) {
+                    for bound in typaram.bounds.iter() {
+                        if let ast::TraitTyParamBound(ref polytraitref, ..) = *bound {
+                            result = result.or_else(|| self.fn_sugar_arg(&polytraitref.trait_ref.path));
+                        }
+                    }
+                }
+                result
+            }
+            // e.g. 'f: |T| -> bool'
+            ast::TyClosure(ref closurety) =>
+                closurety.decl.inputs.iter().nth(self.argindex)
+                    .and_then(|arg| to_racer_ty(&*arg.ty, &self.scope)),
+            _ => None
+        };
+        debug!("ClosureParamVisitor result {}", result);
+        self.result = result;
+    }
+}
+
 pub struct ModVisitor {
     pub name: Option<String>
 }
@@ -832,13 +1259,40 @@ impl<'v> visit::Visitor<'v> for ModVisitor {
 }
 
 pub struct GenericsVisitor {
-    pub generic_args: Vec<String>
+    pub generic_args: Vec<String>,
+    // the traits each type parameter is bound by, in its declaration or a where clause
+    pub bounds: Vec<(String, Vec<super::Path>)>
+}
+
+impl GenericsVisitor {
+    fn add_bounds(&mut self, name: String, bounds: &[ast::TyParamBound]) {
+        let paths: Vec<super::Path> = bounds.iter().filter_map(|bound| {
+            match *bound {
+                ast::TraitTyParamBound(ref polytraitref, ..) =>
+                    Some(to_racer_path(&polytraitref.trait_ref.path)),
+                _ => None
+            }
+        }).collect();
+
+        match self.bounds.iter().position(|&(ref n, _)| *n == name) {
+            Some(i) => self.bounds[i].1.extend(paths.into_iter()),
+            None => self.bounds.push((name, paths))
+        }
+    }
 }
 
 impl<'v> visit::Visitor<'v> for GenericsVisitor {
     fn visit_generics(&mut self, g: &ast::Generics) {
         for ty in g.ty_params.iter() {
-            self.generic_args.push(String::from_str(token::get_ident(ty.ident).get()));
+            let name = String::from_str(token::get_ident(ty.ident).get());
+            self.generic_args.push(name.clone());
+            self.add_bounds(name, ty.bounds.as_slice());
+        }
+        for predicate in g.where_clause.predicates.iter() {
+            if let ast::BoundPredicate(ref predicate) = *predicate {
+                let name = String::from_str(token::get_ident(predicate.ident).get());
+                self.add_bounds(name, predicate.bounds.as_slice());
+            }
         }
     }
 }
@@ -892,7 +1346,7 @@ impl<'v> visit::Visitor<'v> for EnumVisitor {
 
 pub fn parse_view_item(s: String) -> ViewItemVisitor {
     // parser can panic!() so isolate it in another task
-    let result = task::try(move || { 
+    let result = query::try_in_task(move || { 
         let cr = string_to_crate(s);
         let mut v = ViewItemVisitor{ident: None, 
                                     paths: Vec::new(),
@@ -911,7 +1365,7 @@ pub fn parse_view_item(s: String) -> ViewItemVisitor {
 }
 
 pub fn parse_let(s: String) -> Vec<(uint, uint)> {
-    return task::try(move || { 
+    return query::try_in_task(move || { 
         let stmt = string_to_stmt(s);
         let mut v = LetVisitor{ ident_points: Vec::new() };
         visit::walk_stmt(&mut v, &*stmt);
@@ -920,7 +1374,7 @@ pub fn parse_let(s: String) -> Vec<(uint, uint)> {
 }
 
 pub fn parse_struct_fields(s: String, scope: Scope) -> Vec<(String, uint, Option<super::Ty>)> {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = StructVisitor{ scope: scope, fields: Vec::new() };
         visit::walk_stmt(&mut v, &*stmt);
@@ -929,52 +1383,52 @@ pub fn parse_struct_fields(s: String, scope: Scope) -> Vec<(String, uint, Option
 }
 
 pub fn parse_impl(s: String) -> ImplVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = ImplVisitor { name_path: None, trait_path: None };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();    
+    }).ok().unwrap_or(ImplVisitor { name_path: None, trait_path: None });
 }
 
 pub fn parse_trait(s: String) -> TraitVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = TraitVisitor { name: None };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();    
+    }).ok().unwrap_or(TraitVisitor { name: None });
 }
 
 pub fn parse_struct_def(s: String) -> StructDefVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = StructDefVisitor { name: None, generic_args: Vec::new() };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();
+    }).ok().unwrap_or(StructDefVisitor { name: None, generic_args: Vec::new() });
 }
 
 pub fn parse_generics(s: String) -> GenericsVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
-        let mut v = GenericsVisitor { generic_args: Vec::new() };
+        let mut v = GenericsVisitor { generic_args: Vec::new(), bounds: Vec::new() };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();
+    }).ok().unwrap_or(GenericsVisitor { generic_args: Vec::new(), bounds: Vec::new() });
 }
 
 pub fn parse_type(s: String) -> TypeVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = TypeVisitor { name: None, type_: None };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();    
+    }).ok().unwrap_or(TypeVisitor { name: None, type_: None });
 }
 
 pub fn parse_fn_args(s: String) -> Vec<(uint, uint)> {
-    return task::try(move || { 
+    return query::try_in_task(move || { 
         let stmt = string_to_stmt(s);
         debug!("parse_fn_args stmt is {}",stmt);
         let mut v = PatVisitor{ ident_points: Vec::new() };
@@ -985,50 +1439,63 @@ pub fn parse_fn_args(s: String) -> Vec<(uint, uint)> {
 }
 
 pub fn parse_fn_output(s: String, scope: Scope) -> Option<super::Ty> {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = FnVisitor { name: "".to_string(), args: Vec::new(), 
                                 output: None, is_method: false, scope: scope};
         visit::walk_stmt(&mut v, &*stmt);
         return v.output;
-    }).ok().unwrap();
+    }).ok().unwrap_or(None);
 }
 
 
 pub fn parse_fn(s: String, scope: Scope) -> FnVisitor {
     debug!("parse_fn |{}|",s);
-    return task::try(move || {
+    let fallback = FnVisitor { name: "".to_string(), args: Vec::new(),
+                               output: None, is_method: false, scope: scope.clone() };
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = FnVisitor { name: "".to_string(), args: Vec::new(), 
                                 output: None, is_method: false, scope: scope};
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();
+    }).ok().unwrap_or(fallback);
+}
+
+pub fn parse_fn_closure_param(s: String, paramindex: uint, argindex: uint,
+                              scope: Scope) -> Option<super::Ty> {
+    return query::try_in_task(move || {
+        let stmt = string_to_stmt(s);
+        let mut v = ClosureParamVisitor { paramindex: paramindex, argindex: argindex,
+                                          scope: scope, result: None };
+        visit::walk_stmt(&mut v, &*stmt);
+        return v.result;
+    }).ok().unwrap_or(None);
 }
 
 pub fn parse_mod(s: String) -> ModVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = ModVisitor { name: None };
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();    
+    }).ok().unwrap_or(ModVisitor { name: None });
 }
 
 pub fn parse_enum(s: String) -> EnumVisitor {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(s);
         let mut v = EnumVisitor { name: String::new(), values: Vec::new()};
         visit::walk_stmt(&mut v, &*stmt);
         return v;
-    }).ok().unwrap();
+    }).ok().unwrap_or(EnumVisitor { name: String::new(), values: Vec::new()});
 }
 
 
 pub fn get_type_of(exprstr: String, fpath: &Path, pos: uint) -> Option<Ty> {
     let myfpath = fpath.clone();
 
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(exprstr);
         let startscope = Scope {
             filepath: myfpath,
@@ -1039,12 +1506,12 @@ pub fn get_type_of(exprstr: String, fpath: &Path, pos: uint) -> Option<Ty> {
                                      result: None};
         visit::walk_stmt(&mut v, &*stmt);
         return v.result;
-    }).ok().unwrap();
+    }).ok().unwrap_or(None);
 }
 
 // pos points to an ident in the lhs of the stmtstr
 pub fn get_let_type(stmtstr: String, pos: uint, scope: Scope) -> Option<Ty> {
-    return task::try(move || {
+    return query::try_in_task(move || {
         let stmt = string_to_stmt(stmtstr.clone());
         let mut v = LetTypeVisitor {
             scope: scope,
@@ -1053,7 +1520,7 @@ pub fn get_let_type(stmtstr: String, pos: uint, scope: Scope) -> Option<Ty> {
         };
         visit::walk_stmt(&mut v, &*stmt);
         return v.result;
-    }).ok().unwrap();
+    }).ok().unwrap_or(None);
 }
 
 
@@ -1089,6 +1556,31 @@ impl<'v> visit::Visitor<'v> for FnArgTypeVisitor {
 //------------------------------------------------------------
 
 
+#[cfg(test)]
+fn type_name_of(expr: &str) -> String {
+    return match get_type_of(expr.to_string(), &Path::new("lib.rs"), 0) {
+        Some(TyMatch(m)) => m.matchstr,
+        _ => "".to_string()
+    };
+}
+
+#[test]
+fn gets_types_of_literals_and_operators() {
+    assert_eq!("str", type_name_of("\"abc\"").as_slice());
+    assert_eq!("char", type_name_of("'a'").as_slice());
+    assert_eq!("u8", type_name_of("b'a'").as_slice());
+    assert_eq!("uint", type_name_of("1u").as_slice());
+    assert_eq!("i64", type_name_of("1i64").as_slice());
+    assert_eq!("f64", type_name_of("1.5").as_slice());
+    assert_eq!("bool", type_name_of("true").as_slice());
+    assert_eq!("[T]", type_name_of("[1u, 2]").as_slice());
+    assert_eq!("[T]", type_name_of("b\"abc\"").as_slice());
+    assert_eq!("uint", type_name_of("[1u, 2][0]").as_slice());
+    assert_eq!("uint", type_name_of("(1u + 2) * 3").as_slice());
+    assert_eq!("bool", type_name_of("1u < 2").as_slice());
+    assert_eq!("u8", type_name_of("300u as u8").as_slice());
+}
+
 #[test]
 fn ast_sandbox() {
     //let src = "if let Foo(a) = b {}";
@@ -0,0 +1,359 @@
+// Cargo awareness. Maps the crate names used in 'extern crate' to the source
+// of the packages declared as dependencies in the enclosing Cargo.toml, using
+// Cargo.lock to find the versions checked out from registries and git.
+
+use std::io::fs;
+use std::os;
+
+use super::vfs;
+
+#[deriving(Show, Clone)]
+pub enum TomlValue {
+    TomlStr(String),
+    TomlTable(Vec<(String, String)>),   // inline table, e.g. { path = "../foo" }
+    TomlOther(String)
+}
+
+#[deriving(Show, Clone)]
+pub struct TomlSection {
+    pub name: String,                   // e.g. 'dependencies', 'dependencies.foo', 'package'
+    pub entries: Vec<(String, TomlValue)>
+}
+
+impl TomlSection {
+    pub fn get_str(&self, key: &str) -> Option<String> {
+        for &(ref k, ref v) in self.entries.iter() {
+            if k.as_slice() == key {
+                if let &TomlValue::TomlStr(ref s) = v {
+                    return Some(s.clone());
+                }
+            }
+        }
+        return None;
+    }
+}
+
+#[deriving(Show, Clone)]
+pub enum DepSource {
+    PathDep(Path),        // local path dependency (already made absolute)
+    RegistryDep,          // from crates.io or another registry
+    GitDep(String)        // git url
+}
+
+#[deriving(Show, Clone)]
+pub struct Dependency {
+    pub name: String,
+    pub source: DepSource
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            in_str = !in_str;
+        } else if c == '#' && !in_str {
+            return line.slice_to(i);
+        }
+    }
+    return line;
+}
+
+fn parse_str(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with("\"") {
+        s.slice_from(1).find('"').map(|end| s.slice(1, end+1).to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_value(s: &str) -> TomlValue {
+    let s = s.trim();
+    if s.starts_with("{") && s.ends_with("}") {
+        let mut entries = Vec::new();
+        for kv in s.slice(1, s.len()-1).split(',') {
+            let mut it = kv.splitn(1, '=');
+            if let (Some(k), Some(v)) = (it.next(), it.next()) {
+                parse_str(v).map(|v| entries.push((k.trim().to_string(), v)));
+            }
+        }
+        return TomlValue::TomlTable(entries);
+    }
+    return match parse_str(s) {
+        Some(s) => TomlValue::TomlStr(s),
+        None => TomlValue::TomlOther(s.to_string())
+    };
+}
+
+/// A (very) cut down toml parser. Understands sections, arrays of tables,
+/// string values and single line inline tables, which is enough for the
+/// parts of Cargo.toml and Cargo.lock that racer is interested in.
+pub fn parse_toml(src: &str) -> Vec<TomlSection> {
+    let mut sections = vec![TomlSection{ name: "".to_string(), entries: Vec::new() }];
+    for line in src.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("[") {
+            let name = line.trim_chars(['[', ']'].as_slice()).trim();
+            sections.push(TomlSection{ name: name.to_string(), entries: Vec::new() });
+        } else {
+            let mut it = line.splitn(1, '=');
+            if let (Some(k), Some(v)) = (it.next(), it.next()) {
+                let key = k.trim().trim_chars('"').to_string();
+                sections.last_mut().unwrap().entries.push((key, parse_value(v)));
+            }
+        }
+    }
+    return sections;
+}
+
+fn find_file_upwards(dir: &Path, fname: &str) -> Option<Path> {
+    let filepath = dir.join(fname);
+    if vfs::exists(&filepath) {
+        return Some(filepath);
+    }
+    let parentdir = dir.dir_path();
+    if parentdir != *dir {
+        return find_file_upwards(&parentdir, fname);
+    }
+    return None;
+}
+
+/// Finds the Cargo.toml for the package containing the file
+pub fn find_manifest(filepath: &Path) -> Option<Path> {
+    let dir = os::make_absolute(&filepath.dir_path());
+    return find_file_upwards(&dir, "Cargo.toml");
+}
+
+fn is_dependency_section(name: &str) -> bool {
+    name == "dependencies" || name == "dev-dependencies" || name == "build-dependencies"
+}
+
+fn to_dep_source(manifestdir: &Path, path: Option<String>, git: Option<String>) -> DepSource {
+    match (path, git) {
+        (Some(path), _) => DepSource::PathDep(manifestdir.join(path)),
+        (None, Some(git)) => DepSource::GitDep(git),
+        (None, None) => DepSource::RegistryDep
+    }
+}
+
+/// Reads the [dependencies] (and dev/build dependencies) of a manifest
+pub fn get_dependencies(manifest: &Path) -> Vec<Dependency> {
+    let manifestdir = manifest.dir_path();
+    let mut out = Vec::new();
+    for section in parse_toml(super::load_file(manifest).as_slice()).into_iter() {
+        let name = section.name.as_slice();
+        if is_dependency_section(name) {
+            // e.g. foo = "0.1" or foo = { path = "../foo" }
+            for (depname, value) in section.entries.into_iter() {
+                let source = match value {
+                    TomlValue::TomlTable(entries) => {
+                        let t = TomlSection{ name: "".to_string(), entries: entries.into_iter()
+                            .map(|(k, v)| (k, TomlValue::TomlStr(v))).collect() };
+                        to_dep_source(&manifestdir, t.get_str("path"), t.get_str("git"))
+                    }
+                    _ => DepSource::RegistryDep
+                };
+                out.push(Dependency{ name: depname, source: source });
+            }
+        } else {
+            // e.g. [dependencies.foo] \n path = "../foo"
+            let mut it = name.splitn(1, '.');
+            if let (Some(kind), Some(depname)) = (it.next(), it.next()) {
+                if is_dependency_section(kind) {
+                    let source = to_dep_source(&manifestdir, section.get_str("path"),
+                                               section.get_str("git"));
+                    out.push(Dependency{ name: depname.trim_chars('"').to_string(),
+                                         source: source });
+                }
+            }
+        }
+    }
+    return out;
+}
+
+/// (name, version, source) of each package in the lockfile
+pub fn get_locked_packages(lockfile: &Path) -> Vec<(String, String, Option<String>)> {
+    let mut out = Vec::new();
+    for section in parse_toml(super::load_file(lockfile).as_slice()).into_iter() {
+        if section.name.as_slice() == "package" || section.name.as_slice() == "root" {
+            if let (Some(name), Some(version)) = (section.get_str("name"), section.get_str("version")) {
+                out.push((name, version, section.get_str("source")));
+            }
+        }
+    }
+    return out;
+}
+
+fn cargo_home() -> Option<Path> {
+    match os::getenv("CARGO_HOME") {
+        Some(home) => Some(Path::new(home)),
+        None => os::homedir().map(|home| home.join(".cargo"))
+    }
+}
+
+fn readdir(dir: &Path) -> Vec<Path> {
+    fs::readdir(dir).unwrap_or(Vec::new())
+}
+
+fn fname_of(path: &Path) -> String {
+    path.filename_str().unwrap_or("").to_string()
+}
+
+// Candidate directories for a registry package: the registry checkouts
+// under ~/.cargo, and vendored copies next to the manifest
+fn registry_package_dirs(manifestdir: &Path, name: &str, version: &str) -> Vec<Path> {
+    let dirname = format!("{}-{}", name, version);
+    let mut out = Vec::new();
+
+    for vendordir in [manifestdir.join("vendor"), manifestdir.join("third_party")].iter() {
+        out.push(vendordir.join(dirname.as_slice()));
+        out.push(vendordir.join(name));
+    }
+
+    cargo_home().map(|home| {
+        // e.g. ~/.cargo/registry/src/github.com-1ecc6299db9ec823/foo-0.1.0
+        for registry in readdir(&home.join_many(&["registry", "src"])).iter() {
+            out.push(registry.join(dirname.as_slice()));
+        }
+    });
+    return out;
+}
+
+// Candidate directories for a git package, e.g. ~/.cargo/git/checkouts/foo-1234abcd/master
+fn git_package_dirs(name: &str) -> Vec<Path> {
+    let mut out = Vec::new();
+    cargo_home().map(|home| {
+        for checkout in readdir(&home.join_many(&["git", "checkouts"])).iter() {
+            // the name followed by a hash, e.g. not foo-bar-1234abcd for foo
+            let dirname = fname_of(checkout);
+            let prefix = format!("{}-", name);
+            if dirname.as_slice().starts_with(prefix.as_slice()) &&
+                !dirname.as_slice().slice_from(prefix.len()).contains_char('-') {
+                for branch in readdir(checkout).into_iter() {
+                    out.push(branch);
+                }
+            }
+        }
+    });
+    return out;
+}
+
+/// The root source file of the library in a package directory
+pub fn get_package_lib_file(packagedir: &Path) -> Option<Path> {
+    let manifest = packagedir.join("Cargo.toml");
+    if vfs::exists(&manifest) {
+        for section in parse_toml(super::load_file(&manifest).as_slice()).iter() {
+            if section.name.as_slice() == "lib" {
+                if let Some(path) = section.get_str("path") {
+                    return Some(packagedir.join(path));
+                }
+            }
+        }
+    }
+    let filepath = packagedir.join_many(&["src", "lib.rs"]);
+    if vfs::exists(&filepath) {
+        return Some(filepath);
+    }
+    return None;
+}
+
+// crate names use underscores where package names often use dashes
+fn crate_name_matches(cratename: &str, packagename: &str) -> bool {
+    cratename == packagename || cratename == packagename.replace("-", "_").as_slice()
+}
+
+// The root source file of a dependency's library, if its source can be found
+fn get_dependency_lib_file(manifestdir: &Path, dep: Dependency) -> Option<Path> {
+    let candidates = match dep.source {
+        DepSource::PathDep(path) => vec![path],
+        _ => {
+            // need the lockfile to know which version is checked out
+            let mut candidates = Vec::new();
+            find_file_upwards(manifestdir, "Cargo.lock").map(|lockfile| {
+                for (name, version, source) in get_locked_packages(&lockfile).into_iter() {
+                    if name != dep.name {
+                        continue;
+                    }
+                    match source {
+                        Some(ref s) if s.as_slice().starts_with("git+") =>
+                            candidates.push_all(git_package_dirs(name.as_slice()).as_slice()),
+                        _ => candidates.push_all(
+                            registry_package_dirs(manifestdir, name.as_slice(),
+                                                  version.as_slice()).as_slice())
+                    }
+                }
+            });
+            candidates
+        }
+    };
+
+    for packagedir in candidates.iter() {
+        let libfile = get_package_lib_file(packagedir);
+        if libfile.is_some() {
+            return libfile;
+        }
+    }
+    return None;
+}
+
+/// (crate name, root source file) of each dependency of the package
+/// containing filepath whose source can be found
+pub fn get_dependency_crates(filepath: &Path) -> Vec<(String, Path)> {
+    let mut out = Vec::new();
+    find_manifest(filepath).map(|manifest| {
+        let manifestdir = manifest.dir_path();
+        for dep in get_dependencies(&manifest).into_iter() {
+            let cratename = dep.name.replace("-", "_");
+            get_dependency_lib_file(&manifestdir, dep).map(|libfile| {
+                out.push((cratename, libfile));
+            });
+        }
+    });
+    return out;
+}
+
+/// Finds the root source file for the crate, if it is a dependency of the
+/// package containing filepath
+pub fn get_crate_file(cratename: &str, filepath: &Path) -> Option<Path> {
+    let manifest = match find_manifest(filepath) {
+        Some(manifest) => manifest,
+        None => return None
+    };
+    let manifestdir = manifest.dir_path();
+    debug!("cargo::get_crate_file {} manifest {}", cratename, manifest.as_str());
+
+    for dep in get_dependencies(&manifest).into_iter() {
+        if crate_name_matches(cratename, dep.name.as_slice()) {
+            let libfile = get_dependency_lib_file(&manifestdir, dep);
+            if libfile.is_some() {
+                debug!("cargo::get_crate_file found {}", libfile);
+                return libfile;
+            }
+        }
+    }
+    return None;
+}
+
+#[test]
+fn parses_dependencies_from_manifest_sections() {
+    let src = "
+[package]
+name = \"foo\"  # the name
+
+[dependencies]
+bar = \"0.1.0\"
+baz = { path = \"../baz\", version = \"0.2\" }
+
+[dependencies.quux]
+git = \"https://example.com/quux\"
+";
+    let sections = parse_toml(src);
+    assert_eq!(Some("foo".to_string()), sections[1].get_str("name"));
+    assert_eq!("dependencies", sections[2].name.as_slice());
+    assert_eq!(2, sections[2].entries.len());
+    assert_eq!("dependencies.quux", sections[3].name.as_slice());
+    assert_eq!(Some("https://example.com/quux".to_string()), sections[3].get_str("git"));
+}
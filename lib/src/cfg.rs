@@ -0,0 +1,375 @@
+// Evaluation of #[cfg(...)] attributes, so that e.g. only one of a pair of
+// #[cfg(unix)] / #[cfg(windows)] items is found.
+//
+// The active configuration is part of the state of a query (see query.rs),
+// and defaults to the platform racer runs on, with no features and 'test'
+// off. Options racer doesn't know
+// the value of (e.g. target_arch, or a name that isn't set) are treated as
+// unknown rather than false, and items are only dropped when their cfg is
+// definitely false. That way an unusual cfg doesn't hide code that is
+// probably compiled.
+//
+// Test only code is searched when the query was made inside a #[cfg(test)]
+// module (see set_cursor), since completing in tests needs the items of the
+// test modules. Where the query was made is kept with the rest of its state
+// (see query.rs), so concurrent queries don't see each other's.
+
+use std::iter::range;
+use std::os;
+use std::sync::atomic::SeqCst;
+use super::{nameres, query, scopes, util};
+
+/// The cfg options items are checked against
+#[deriving(Clone, Show)]
+pub struct Config {
+    pub target_os: Option<String>,  // e.g. "linux", None if only the family is known
+    pub target_family: String,      // "unix" or "windows"
+    pub features: Vec<String>,
+    pub test: bool
+}
+
+impl Config {
+    /// The platform racer runs on, with no features and not testing
+    pub fn host() -> Config {
+        Config{ target_os: Some(os::consts::SYSNAME.to_string()),
+                target_family: os::consts::FAMILY.to_string(),
+                features: Vec::new(),
+                test: false }
+    }
+
+    /// Sets an option given in the form of rustc's --cfg, e.g. 'test',
+    /// 'unix', 'target_os="macos"' or 'feature="foo"'
+    pub fn set(&mut self, opt: &str) -> Result<(), String> {
+        match parse_cfg(opt) {
+            Some(Cfg::Name(ref name)) if name.as_slice() == "test" => self.test = true,
+            Some(Cfg::Name(ref name)) if name.as_slice() == "unix" ||
+                                         name.as_slice() == "windows" =>
+                self.set_family(name.as_slice()),
+            Some(Cfg::Value(ref key, ref value)) => match key.as_slice() {
+                "target_os" => {
+                    self.target_family = family_of(value.as_slice()).to_string();
+                    self.target_os = Some(value.clone());
+                }
+                "target_family" if value.as_slice() == "unix" ||
+                                   value.as_slice() == "windows" =>
+                    self.set_family(value.as_slice()),
+                "feature" => self.features.push(value.clone()),
+                _ => return Err(format!("unknown cfg option '{}'", opt))
+            },
+            _ => return Err(format!("unknown cfg option '{}'", opt))
+        }
+        return Ok(());
+    }
+
+    // The os is kept if it is of the family, and otherwise is only known
+    // for windows
+    fn set_family(&mut self, family: &str) {
+        if self.target_family.as_slice() != family {
+            self.target_os = if family == "windows" { Some(family.to_string()) } else { None };
+            self.target_family = family.to_string();
+        }
+    }
+
+    // Some(true/false) for the options this knows, None otherwise
+    fn eval(&self, cfg: &Cfg, cursor_in_test: bool) -> Option<bool> {
+        return match *cfg {
+            Cfg::Name(ref name) => match name.as_slice() {
+                "test" => Some(self.test || cursor_in_test),
+                "unix" | "windows" => Some(self.target_family == *name),
+                _ => None
+            },
+            Cfg::Value(ref key, ref value) => match key.as_slice() {
+                "target_os" => self.target_os.as_ref().map(|os| *os == *value),
+                "target_family" => Some(self.target_family == *value),
+                "feature" => Some(self.features.contains(value)),
+                _ => None
+            },
+            // false if any is false, true if all are true
+            Cfg::All(ref cfgs) => cfgs.iter().fold(Some(true), |acc, c| {
+                match (acc, self.eval(c, cursor_in_test)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None
+                }
+            }),
+            // true if any is true, false if all are false
+            Cfg::Any(ref cfgs) => cfgs.iter().fold(Some(false), |acc, c| {
+                match (acc, self.eval(c, cursor_in_test)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None
+                }
+            }),
+            Cfg::Not(ref cfg) => self.eval(&**cfg, cursor_in_test).map(|b| !b)
+        };
+    }
+}
+
+// The target_family of a target_os
+fn family_of(os: &str) -> &'static str {
+    if os == "windows" { "windows" } else { "unix" }
+}
+
+/// A parsed cfg predicate
+#[deriving(Clone, Show, PartialEq)]
+pub enum Cfg {
+    Name(String),             // unix
+    Value(String, String),    // target_os = "linux"
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>)
+}
+
+// how far up the module tree to look for a #[cfg(test)]
+const MAX_MODULE_DEPTH: uint = 32;
+
+/// Sets the configuration of the queries this task runs (see query::run)
+pub fn set_config(cfg: Config) {
+    *query::state().config.lock() = cfg;
+}
+
+pub fn get_config() -> Config {
+    query::state().config.lock().clone()
+}
+
+/// Tells the evaluator where the current query was made, so that test only
+/// items are enabled when it is in test code. Queries that aren't made at
+/// a point (e.g. symbol search) don't set it, and test only items are
+/// disabled for them.
+pub fn set_cursor(filepath: &Path, pos: uint) {
+    query::state().cursor_in_test.store(is_test_code(filepath, pos), SeqCst);
+}
+
+/// False if the attributes in front of the stmt at start rule it out in the
+/// active configuration. The attributes are found in msrc, but read from
+/// src (the text msrc was masked from), as masking blanks the values of
+/// e.g. feature = "foo".
+pub fn is_item_enabled(src: &str, msrc: &str, start: uint) -> bool {
+    let state = query::state();
+    let config = state.config.lock();
+    let cursor_in_test = state.cursor_in_test.load(SeqCst);
+    return attribute_spans(msrc, start).iter().all(|&(attrstart, attrend)| {
+        match parse_cfg_attribute(src.slice(attrstart, attrend)) {
+            Some(cfg) => config.eval(&cfg, cursor_in_test) != Some(false),
+            None => true
+        }
+    });
+}
+
+// Is pos in a #[cfg(test)] module, either inline or a file module (like
+// racer's own 'mod test;')?
+fn is_test_code(filepath: &Path, pos: uint) -> bool {
+    let mut filepath = filepath.clone();
+    let mut pos = pos;
+    for _ in range(0, MAX_MODULE_DEPTH) {
+        let msrc = super::load_file_and_mask_comments(&filepath);
+        let msrc = msrc.as_slice();
+        if pos > msrc.len() {
+            return false;
+        }
+        for &start in scopes::get_local_module_starts(msrc, pos).iter() {
+            if is_test_item(msrc, start) {
+                return true;
+            }
+        }
+        match nameres::find_module_decl(&filepath) {
+            Some((declfile, declpoint)) => {
+                let declsrc = super::load_file_and_mask_comments(&declfile);
+                let start = scopes::find_stmt_start(declsrc.as_slice(), declpoint)
+                    .unwrap_or(declpoint);
+                if is_test_item(declsrc.as_slice(), start) {
+                    return true;
+                }
+                filepath = declfile;
+                pos = declpoint;
+            }
+            None => return false
+        }
+    }
+    return false;
+}
+
+fn is_test_item(msrc: &str, start: uint) -> bool {
+    let test = Cfg::Name("test".to_string());
+    return attributes_before(msrc, start).iter().any(|attr| {
+        parse_cfg_attribute(*attr).map_or(false, |cfg| cfg == test)
+    });
+}
+
+// The outer attributes directly in front of the stmt at start, e.g.
+// ["#[cfg(unix)]", "#[inline]"]
+fn attributes_before(msrc: &str, start: uint) -> Vec<&str> {
+    attribute_spans(msrc, start).into_iter().map(|(s, e)| msrc.slice(s, e)).collect()
+}
+
+// The (start, end) of each of the attributes in front of the stmt at start
+fn attribute_spans(msrc: &str, start: uint) -> Vec<(uint, uint)> {
+    let mut out = Vec::new();
+    let mut end = msrc.slice_to(start).trim_right().len();
+    while end > 0 && msrc.char_at(end - 1) == ']' {
+        let mut depth = 0i;
+        let mut attrstart = None;
+        for (i, c) in msrc.slice_to(end).char_indices().rev() {
+            match c {
+                ']' => depth += 1,
+                '[' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                attrstart = Some(i);
+                break;
+            }
+        }
+        match attrstart {
+            Some(i) if i > 0 && msrc.char_at(i - 1) == '#' => {
+                out.insert(0, (i - 1, end));
+                end = msrc.slice_to(i - 1).trim_right().len();
+            }
+            _ => break    // the end of an array or index expression
+        }
+    }
+    return out;
+}
+
+/// The predicate of a '#[cfg(...)]' attribute. None for other attributes
+pub fn parse_cfg_attribute(attr: &str) -> Option<Cfg> {
+    if !attr.starts_with("#[") || !attr.ends_with("]") {
+        return None;
+    }
+    let inner = attr.slice(2, attr.len() - 1).trim();
+    if !inner.starts_with("cfg") {
+        return None;
+    }
+    let args = inner.slice_from(3).trim_left();
+    if !args.starts_with("(") || !args.ends_with(")") {
+        return None;
+    }
+    return parse_cfg(args.slice(1, args.len() - 1));
+}
+
+/// Parses a cfg predicate, e.g. 'all(unix, not(feature = "foo"))'
+pub fn parse_cfg(s: &str) -> Option<Cfg> {
+    let mut parser = Parser{ src: s, pos: 0 };
+    let cfg = parser.cfg();
+    parser.skip_whitespace();
+    return if parser.pos == s.len() { cfg } else { None };
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: uint
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = self.src.slice_from(self.pos);
+        self.pos += rest.len() - rest.trim_left().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.pos < self.src.len() && self.src.char_at(self.pos) == c {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let end = util::find_ident_end(self.src, self.pos);
+        if end == self.pos {
+            return None;
+        }
+        let ident = self.src.slice(self.pos, end).to_string();
+        self.pos = end;
+        return Some(ident);
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat('"') {
+            return None;
+        }
+        let rest = self.src.slice_from(self.pos);
+        return rest.find('"').map(|n| {
+            self.pos += n + 1;
+            rest.slice_to(n).to_string()
+        });
+    }
+
+    fn cfg(&mut self) -> Option<Cfg> {
+        let name = match self.ident() {
+            Some(name) => name,
+            None => return None
+        };
+        if self.eat('=') {
+            return self.string().map(|value| Cfg::Value(name, value));
+        }
+        if !self.eat('(') {
+            return Some(Cfg::Name(name));
+        }
+        let mut args = Vec::new();
+        while !self.eat(')') {
+            match self.cfg() {
+                Some(cfg) => args.push(cfg),
+                None => return None
+            }
+            // a ',' or the closing ')' must follow
+            if !self.eat(',') {
+                if self.eat(')') { break; }
+                return None;
+            }
+        }
+        return match name.as_slice() {
+            "all" => Some(Cfg::All(args)),
+            "any" => Some(Cfg::Any(args)),
+            "not" if args.len() == 1 => args.pop().map(|cfg| Cfg::Not(box cfg)),
+            _ => None
+        };
+    }
+}
+
+#[test]
+fn parses_cfg_predicates() {
+    assert_eq!(Some(Cfg::Name("unix".to_string())), parse_cfg_attribute("#[cfg(unix)]"));
+    assert_eq!(Some(Cfg::All(vec![Cfg::Value("target_os".to_string(), "linux".to_string()),
+                                  Cfg::Not(box Cfg::Name("test".to_string()))])),
+               parse_cfg("all(target_os = \"linux\", not(test))"));
+    assert_eq!(None, parse_cfg_attribute("#[inline]"));
+    assert_eq!(None, parse_cfg("not(unix, windows)"));
+}
+
+#[test]
+fn evaluates_cfgs_against_the_config() {
+    let config = Config{ target_os: Some("linux".to_string()), target_family: "unix".to_string(),
+                         features: vec!["foo".to_string()], test: false };
+    let eval = |s: &str| config.eval(&parse_cfg(s).unwrap(), false);
+    assert_eq!(Some(true), eval("unix"));
+    assert_eq!(Some(false), eval("windows"));
+    assert_eq!(Some(true), eval("all(feature = \"foo\", not(test))"));
+    assert_eq!(Some(false), eval("any(test, target_os = \"macos\")"));
+    assert_eq!(None, eval("target_arch = \"x86\""));
+    assert_eq!(Some(false), eval("all(windows, target_arch = \"x86\")"));
+}
+
+#[test]
+fn sets_the_os_and_family_together() {
+    let mut config = Config{ target_os: Some("linux".to_string()),
+                             target_family: "unix".to_string(), features: Vec::new(),
+                             test: false };
+    config.set("windows").unwrap();
+    assert_eq!(Some(true), config.eval(&parse_cfg("target_os = \"windows\"").unwrap(), false));
+    config.set("unix").unwrap();
+    assert_eq!(None, config.eval(&parse_cfg("target_os = \"linux\"").unwrap(), false));
+    config.set("target_os = \"macos\"").unwrap();
+    assert_eq!(Some(true), config.eval(&parse_cfg("unix").unwrap(), false));
+    assert!(config.set("target_family = \"beos\"").is_err());
+}
+
+#[test]
+fn finds_attributes_before_item() {
+    let src = "let a = b[0];\n#[cfg(unix)]\n#[inline] fn foo() {}";
+    let start = src.find_str("fn").unwrap();
+    assert_eq!(vec!["#[cfg(unix)]", "#[inline]"], attributes_before(src, start));
+    assert!(attributes_before(src, src.find_str("#[cfg").unwrap()).is_empty());
+}
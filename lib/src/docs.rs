@@ -0,0 +1,222 @@
+// Extracts the full declaration and doc comments of matched items, for
+// hover popups and parameter hints (see describe() in lib.rs).
+
+use super::Match;
+use super::MatchType::{Module, Function, Struct, Enum, Trait, Type, Const, Static, Impl};
+use super::scopes;
+use super::util;
+
+// The start of the line containing point
+fn line_start(src: &str, point: uint) -> uint {
+    src.slice_to(point).rfind('\n').map(|n| n + 1).unwrap_or(0)
+}
+
+// The point where the declaration header ends: the opening brace of the
+// body or the terminating semicolon, ignoring any inside parens or brackets
+fn find_decl_end(msrc: &str, point: uint) -> uint {
+    let mut level = 0i;
+    for (i, c) in msrc.slice_from(point).char_indices() {
+        match c {
+            '(' | '[' => level += 1,
+            ')' | ']' => level -= 1,
+            '{' | ';' if level == 0 => return point + i,
+            _ => {}
+        }
+    }
+    return msrc.len();
+}
+
+// The start of the statement declaring the item
+fn find_decl_start(msrc: &str, m: &Match) -> uint {
+    match m.mtype {
+        Function | Struct | Enum | Trait | Type | Const | Static | Module | Impl =>
+            scopes::find_stmt_start(msrc, m.point).unwrap_or(m.point),
+        // fields, variants, let bindings and args are declared on their own line
+        _ => line_start(msrc, m.point)
+    }
+}
+
+// The text with its comments blanked out. Unlike scopes::mask_comments,
+// string literals are kept.
+fn strip_comments(src: &str) -> String {
+    let mut out = String::new();
+    let mut chars = src.chars().peekable();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None => return out
+        };
+        match (c, chars.peek().map(|c| *c)) {
+            ('/', Some('/')) => {
+                // up to the end of the line
+                out.push(' ');
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push(c);
+                        break;
+                    }
+                    out.push(' ');
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                out.push_str("  ");
+                let (mut nesting, mut prev) = (0u, ' ');
+                for c in chars.by_ref() {
+                    out.push(if c == '\n' { c } else { ' ' });
+                    if prev == '/' && c == '*' {
+                        nesting += 1;
+                    } else if prev == '*' && c == '/' {
+                        if nesting == 0 {
+                            break;
+                        }
+                        nesting -= 1;
+                    }
+                    prev = c;
+                }
+            }
+            ('"', _) => {
+                out.push(c);
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' && !escaped {
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+            }
+            // a char literal of a double quote isn't the start of a string
+            ('\'', Some('"')) => {
+                out.push(c);
+                out.push(chars.next().unwrap());
+            }
+            _ => out.push(c)
+        }
+    }
+}
+
+/// The full declaration of the item, up to (but not including) its body.
+/// Unlike Match.contextstr this spans multiple lines, so includes where
+/// clauses and bounds that are split over lines.
+pub fn get_signature(m: &Match) -> String {
+    if m.mtype == Module && m.point == 0 {
+        // a whole file
+        return format!("mod {}", m.matchstr);
+    }
+
+    let src = super::load_file(&m.filepath);
+    let msrc = super::load_file_and_mask_comments(&m.filepath);
+    match m.mtype {
+        Function | Struct | Enum | Trait | Type | Const | Static | Module | Impl => {
+            let start = find_decl_start(msrc.as_slice(), m);
+            let end = find_decl_end(msrc.as_slice(), m.point);
+            // the extent comes from the masked text, where braces in comments and
+            // strings don't count, but the text keeps its string literals
+            let sig = strip_comments(src.as_slice().slice(start, end));
+            return sig.as_slice().lines().map(|l| l.trim_right()).filter(|l| !l.is_empty())
+                .collect::<Vec<_>>().connect("\n");
+        }
+        _ => return util::line_at_point(src.as_slice(), m.point).to_string()
+    }
+}
+
+fn strip_doc_prefix<'a>(line: &'a str, prefix: &str) -> &'a str {
+    let text = line.slice_from(prefix.len());
+    if text.starts_with(" ") { text.slice_from(1) } else { text }
+}
+
+fn is_outer_doc(line: &str) -> bool {
+    line.starts_with("///") && !line.starts_with("////")
+}
+
+// The '//!' lines at the start of a file or module body
+fn get_inner_docs(src: &str) -> Vec<String> {
+    src.lines()
+        .map(|l| l.trim())
+        .skip_while(|l| l.is_empty())
+        .take_while(|l| l.starts_with("//!"))
+        .map(|l| strip_doc_prefix(l, "//!").to_string())
+        .collect()
+}
+
+/// The doc comments attached to the item: the '///' comments before it, and
+/// for modules the '//!' comments at the top of the module
+pub fn get_docs(m: &Match) -> String {
+    let src = super::load_file(&m.filepath);
+
+    if m.mtype == Module && m.point == 0 {
+        return get_inner_docs(src.as_slice()).connect("\n");
+    }
+
+    let msrc = super::load_file_and_mask_comments(&m.filepath);
+    let start = line_start(src.as_slice(), find_decl_start(msrc.as_slice(), m));
+
+    let mut lines = Vec::new();
+    for line in src.as_slice().slice_to(start).lines().rev() {
+        let line = line.trim();
+        if is_outer_doc(line) {
+            lines.push(strip_doc_prefix(line, "///").to_string());
+        } else if !line.starts_with("#[") {   // attributes can sit between docs and item
+            break;
+        }
+    }
+    lines.reverse();
+
+    if m.mtype == Module {
+        // inline module: mod foo { //! docs ... }
+        let bodystart = m.point + msrc.as_slice().slice_from(m.point).find('{').map(|n| n + 1).unwrap_or(0);
+        lines.push_all(get_inner_docs(src.as_slice().slice_from(bodystart)).as_slice());
+    }
+    return lines.connect("\n");
+}
+
+#[cfg(test)]
+fn test_match(src: &str, name: &str, mtype: super::MatchType) -> Match {
+    let path = Path::new(format!("docs_test_{}.rs", name));
+    super::vfs::set_buffer(&path, src.to_string());
+    Match { matchstr: name.to_string(), filepath: path, point: src.find_str(name).unwrap(),
+            local: false, accessible: true, score: 0, mtype: mtype, contextstr: "".to_string(),
+            generic_args: Vec::new(), generic_types: Vec::new() }
+}
+
+#[test]
+fn gets_multiline_signature_and_docs() {
+    let src = "
+/// Does a thing.
+/// Really.
+#[inline]
+pub fn myfn<T>(a: T) -> uint   // trailing
+    where T: Clone {
+    0
+}
+";
+    let m = test_match(src, "myfn", Function);
+    assert_eq!("pub fn myfn<T>(a: T) -> uint\n    where T: Clone", get_signature(&m).as_slice());
+    assert_eq!("Does a thing.\nReally.", get_docs(&m).as_slice());
+    super::vfs::remove_buffer(&m.filepath);
+}
+
+#[test]
+fn keeps_string_literals_in_signature() {
+    let src = "
+pub const GREETING: &'static str = \"hello; {world}\"; /* trailing */
+";
+    let m = test_match(src, "GREETING", Const);
+    assert_eq!("pub const GREETING: &'static str = \"hello; {world}\"", get_signature(&m).as_slice());
+    super::vfs::remove_buffer(&m.filepath);
+}
+
+#[test]
+fn gets_inner_docs_of_module_file() {
+    let src = "
+//! The module
+//! docs
+
+fn foo() {}
+";
+    let mut m = test_match(src, "foo", Module);
+    m.point = 0;
+    assert_eq!("The module\ndocs", get_docs(&m).as_slice());
+    super::vfs::remove_buffer(&m.filepath);
+}
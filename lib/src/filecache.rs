@@ -0,0 +1,290 @@
+// Cache of loaded source files.
+//
+// A single completion can load the same file dozens of times, and each load
+// used to re-read the file and re-mask its comments. The cache holds the raw
+// text, the masked text and the index of top level statements for each file
+// (all worked out when the file is loaded), and is invalidated when the
+// file's modification time or size (or the version of its unsaved buffer,
+// see vfs.rs) changes. It is global so that it survives across requests in a
+// long running process (e.g. the daemon).
+//
+// Indexes built from all the files of a crate (e.g. its impls, or the
+// macros it defines) are cached per crate root, and rebuilt when any of the
+// files they were built from has been reloaded since. Checking that only
+// looks at the cache, so that a lookup doesn't stat every file of the crate.
+
+use std::any::{Any, AnyRefExt};
+use std::collections::HashMap;
+use std::io::{File, BufferedReader};
+use std::io::fs;
+use std::mem;
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use super::{codeiter, macros, nameres, scopes, vfs};
+use super::{RacerError, RacerResult};
+
+/// The version of a file, which changes whenever its text does
+#[deriving(PartialEq, Clone, Show)]
+pub enum Stamp {
+    Modified(u64, u64),   // file on disk, with modification time and size
+    Buffer(uint)          // unsaved buffer, with version
+}
+
+impl Copy for Stamp {}
+
+struct CachedFile {
+    stamp: Stamp,
+    src: String,
+    masked: String,
+    stmts: Vec<(uint, uint)>
+}
+
+static mut CACHE: *const Mutex<HashMap<Path, CachedFile>> = 0 as *const Mutex<HashMap<Path, CachedFile>>;
+static INIT: Once = ONCE_INIT;
+
+fn cache() -> &'static Mutex<HashMap<Path, CachedFile>> {
+    unsafe {
+        INIT.doit(|| {
+            let m: Box<Mutex<HashMap<Path, CachedFile>>> = box Mutex::new(HashMap::new());
+            CACHE = mem::transmute(m);
+        });
+        return &*CACHE;
+    }
+}
+
+// An index built from the files of a crate, and the versions of the files
+struct CachedIndex {
+    stamps: Vec<(Path, Option<Stamp>)>,
+    index: Box<Any + Send>
+}
+
+static mut INDEXES: *const Mutex<HashMap<(Path, &'static str), CachedIndex>> =
+    0 as *const Mutex<HashMap<(Path, &'static str), CachedIndex>>;
+static INDEXES_INIT: Once = ONCE_INIT;
+
+fn indexes() -> &'static Mutex<HashMap<(Path, &'static str), CachedIndex>> {
+    unsafe {
+        INDEXES_INIT.doit(|| {
+            let m: Box<Mutex<HashMap<(Path, &'static str), CachedIndex>>> = box Mutex::new(HashMap::new());
+            INDEXES = mem::transmute(m);
+        });
+        return &*INDEXES;
+    }
+}
+
+/// The version of the file as it is now. None for a file that doesn't
+/// exist, or an expanded copy (see macros.rs).
+pub fn current_stamp(filepath: &Path) -> Option<Stamp> {
+    // expanded copies of files are cached by macros.rs
+    if macros::is_expanded_file_path(filepath) {
+        return None;
+    }
+    match vfs::get_buffer_version(filepath) {
+        Some(version) => Some(Stamp::Buffer(version)),
+        None => fs::stat(filepath).ok().map(|stat| Stamp::Modified(stat.modified, stat.size))
+    }
+}
+
+fn read_file(filepath: &Path) -> RacerResult<String> {
+    // unsaved editor buffers take precedence over the file on disk
+    if let Some(text) = vfs::get_buffer(filepath) {
+        return Ok(text);
+    }
+    if macros::is_expanded_file_path(filepath) {
+        return macros::load_expanded_file(filepath).ok_or(
+            RacerError::IoError(filepath.clone(), "the macro expansion is stale".to_string()));
+    }
+
+    let mut rawbytes = match BufferedReader::new(File::open(filepath)).read_to_end() {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(RacerError::IoError(filepath.clone(), e.to_string()))
+    };
+
+    // skip BOF bytes, if present
+    if rawbytes.len() >= 3 && rawbytes.slice(0,3) == [0xEF, 0xBB, 0xBF] {
+        rawbytes = rawbytes.slice_from(3).to_vec();
+    }
+    return String::from_utf8(rawbytes).map_err(|_| RacerError::InvalidUtf8(filepath.clone()));
+}
+
+// Reads the file and works out its masked text and stmt index
+fn load(filepath: &Path, stamp: Stamp) -> RacerResult<CachedFile> {
+    let src = try!(read_file(filepath));
+    let masked = scopes::mask_comments(src.as_slice());
+    let stmts = codeiter::iter_stmts(src.as_slice()).collect();
+    return Ok(CachedFile{ stamp: stamp, src: src, masked: masked, stmts: stmts });
+}
+
+// Runs f on the cache entry for filepath, (re)loading the file if the entry
+// is missing or stale. The file is loaded without holding the lock, so that
+// other tasks can use the cache meanwhile.
+fn with_cached_file<T>(filepath: &Path, f: |&CachedFile| -> T) -> RacerResult<T> {
+    let stamp = current_stamp(filepath);
+    if let Some(stamp) = stamp {
+        let cache = cache().lock();
+        match cache.get(filepath) {
+            Some(cached) if cached.stamp == stamp => return Ok(f(cached)),
+            _ => {}
+        }
+    }
+
+    let loaded = try!(load(filepath, stamp.unwrap_or(Stamp::Modified(0, 0))));
+    let res = f(&loaded);
+    // can't tell when a file without a stamp changes, so it isn't cached
+    if stamp.is_some() {
+        cache().lock().insert(filepath.clone(), loaded);
+    }
+    return Ok(res);
+}
+
+pub fn try_load_file(filepath: &Path) -> RacerResult<String> {
+    with_cached_file(filepath, |cached| cached.src.clone())
+}
+
+// The searches load lots of files, and one that can't be read (or isn't
+// utf-8) shouldn't stop the rest, so it is treated as empty
+fn log_failure<T>(res: RacerResult<T>, default: T) -> T {
+    match res {
+        Ok(t) => t,
+        Err(e) => {
+            debug!("filecache: {}", e);
+            default
+        }
+    }
+}
+
+pub fn load_file(filepath: &Path) -> String {
+    log_failure(try_load_file(filepath), String::new())
+}
+
+pub fn load_file_and_mask_comments(filepath: &Path) -> String {
+    log_failure(with_cached_file(filepath, |cached| cached.masked.clone()), String::new())
+}
+
+/// The (start, end) indices of the top level statements in the file
+pub fn load_stmts(filepath: &Path) -> Vec<(uint, uint)> {
+    log_failure(with_cached_file(filepath, |cached| cached.stmts.clone()), Vec::new())
+}
+
+/// The stmt index of the file (see load_stmts), if src is the file's text
+/// or the text with its comments masked. None if src is some other text,
+/// e.g. an unsaved version the caller was given.
+pub fn load_stmts_for_src(filepath: &Path, src: &str) -> Option<Vec<(uint, uint)>> {
+    log_failure(with_cached_file(filepath, |cached| {
+        if cached.src.as_slice() == src || cached.masked.as_slice() == src {
+            Some(cached.stmts.clone())
+        } else {
+            None
+        }
+    }), None)
+}
+
+// The stamp of the file as far as the cache knows, i.e. without going to the
+// disk. A buffer that was added or dropped since the file was loaded counts.
+fn known_stamp(cache: &HashMap<Path, CachedFile>, filepath: &Path,
+               recorded: Option<Stamp>) -> Option<Stamp> {
+    match (vfs::get_buffer_version(filepath), recorded) {
+        (Some(version), _) => Some(Stamp::Buffer(version)),
+        (None, Some(Stamp::Buffer(_))) => None,
+        (None, _) => cache.get(filepath).map(|cached| cached.stamp)
+    }
+}
+
+/// The index called name of the crate containing filepath, built by
+/// build() from the crate's files (see nameres::get_crate_module_files)
+/// unless a cached one is still fresh. The index is shared by the files of
+/// the crate, and a file the crate root doesn't reach (e.g. a test or an
+/// example) gets one of its own.
+pub fn load_crate_index<T: Clone + Send + 'static>(filepath: &Path, name: &'static str,
+                                                   build: |&[Path]| -> T) -> T {
+    let crateroot = nameres::crate_root_file(filepath);
+    for key in [(crateroot.clone(), name), (filepath.clone(), name)].iter() {
+        let indexes = indexes().lock();
+        let cached = match indexes.get(key) {
+            Some(cached) => cached,
+            None => continue
+        };
+        let cache = cache().lock();
+        let is_fresh = cached.stamps.iter().any(|&(ref f, _)| f == filepath) &&
+            cached.stamps.iter().all(|&(ref f, stamp)| known_stamp(&*cache, f, stamp) == stamp);
+        if is_fresh {
+            if let Some(index) = cached.index.downcast_ref::<T>() {
+                return index.clone();
+            }
+        }
+    }
+
+    // built without holding the lock, as building can load other indexes
+    let rootfiles = nameres::get_crate_module_files(&crateroot);
+    let (key, files) = if rootfiles.contains(filepath) {
+        ((crateroot, name), rootfiles)
+    } else {
+        ((filepath.clone(), name), nameres::get_crate_module_files(filepath))
+    };
+    let index = build(files.as_slice());
+    let stamps = {
+        let cache = cache().lock();
+        files.iter().map(|f| (f.clone(), known_stamp(&*cache, f, None))).collect()
+    };
+    indexes().lock().insert(key, CachedIndex{ stamps: stamps,
+                                              index: box index.clone() as Box<Any + Send> });
+    return index;
+}
+
+pub fn invalidate(filepath: &Path) {
+    cache().lock().remove(filepath);
+}
+
+pub fn clear() {
+    cache().lock().clear();
+    indexes().lock().clear();
+}
+
+#[test]
+fn reloads_when_buffer_changes() {
+    let path = Path::new("filecache_test_buffer.rs");
+    vfs::set_buffer(&path, "/* c */ fn foo() {}".to_string());
+    assert_eq!("/* c */ fn foo() {}", load_file(&path).as_slice());
+    assert_eq!("        fn foo() {}", load_file_and_mask_comments(&path).as_slice());
+    assert_eq!(vec![(8, 19)], load_stmts(&path));
+
+    vfs::set_buffer(&path, "fn bar() {}".to_string());
+    assert_eq!("fn bar() {}", load_file(&path).as_slice());
+    vfs::remove_buffer(&path);
+    invalidate(&path);
+}
+
+#[test]
+fn reports_missing_and_non_utf8_files() {
+    let missing = Path::new("filecache_test_missing_file.rs");
+    match try_load_file(&missing) {
+        Err(RacerError::IoError(ref path, _)) => assert_eq!(missing, *path),
+        _ => panic!("expected an IoError")
+    }
+    assert_eq!("", load_file(&missing).as_slice());
+
+    let path = Path::new("filecache_test_latin1.rs");
+    File::create(&path).write(&[0x66u8, 0xE9, 0x0A]).unwrap();
+    assert_eq!(Err(RacerError::InvalidUtf8(path.clone())), try_load_file(&path));
+    fs::unlink(&path).unwrap();
+}
+
+#[test]
+fn shares_crate_indexes_between_the_files_of_a_crate() {
+    let dir = Path::new("filecache_test_crate");
+    fs::mkdir_recursive(&dir, ::std::io::USER_RWX).unwrap();
+    let (root, module) = (dir.join("lib.rs"), dir.join("foo.rs"));
+    File::create(&root).write(b"mod foo;\n").unwrap();
+    File::create(&module).write(b"fn bar() {}\n").unwrap();
+
+    let mut builds = 0u;
+    assert_eq!(2, load_crate_index(&root, "test", |files| { builds += 1; files.len() }));
+    assert_eq!(2, load_crate_index(&module, "test", |files| { builds += 1; files.len() }));
+    assert_eq!(1, builds);
+
+    vfs::set_buffer(&module, "fn baz() {}\n".to_string());
+    load_crate_index(&module, "test", |files| { builds += 1; files.len() });
+    assert_eq!(2, builds);
+    vfs::remove_buffer(&module);
+    fs::rmdir_recursive(&dir).unwrap();
+}
@@ -6,9 +6,9 @@ extern crate syntax;
 extern crate collections;
 extern crate core;
 
-use std::io::File;
-use std::io::BufferedReader;
-use std::{str,vec,fmt,path};
+use std::{vec,fmt,path};
+use std::any::AnyRefExt;
+use syntax::diagnostic::FatalError;
 
 pub mod scopes;
 pub mod ast;
@@ -19,6 +19,17 @@ pub mod codecleaner;
 pub mod testutils;
 pub mod util;
 pub mod matchers;
+pub mod vfs;
+pub mod filecache;
+pub mod cargo;
+pub mod docs;
+pub mod symbols;
+pub mod trace;
+pub mod macros;
+pub mod privacy;
+pub mod cfg;
+pub mod ranking;
+pub mod query;
 
 #[cfg(test)] pub mod test;
 
@@ -34,7 +45,11 @@ pub enum MatchType {
     Enum,
     EnumVariant,
     Type,
+    Builtin,
     FnArg,
+    ClosureArg,
+    MatchArm,
+    TypeParameter,
     Trait,
     Const,
     Static
@@ -62,7 +77,8 @@ impl Copy for Namespace {}
 #[deriving(Show)]
 pub enum CompletionType {
     CompleteField,
-    CompletePath
+    CompletePath,
+    CompleteStructField    // a field name in a struct literal or pattern, e.g. 'Foo { a: 1, b'
 }
 
 impl Copy for CompletionType {}
@@ -73,6 +89,8 @@ pub struct Match {
     pub filepath: path::Path,
     pub point: uint,
     pub local: bool,
+    pub accessible: bool,     // usable from the module the query was made in, see privacy.rs
+    pub score: int,           // relevance of a completion, see ranking.rs
     pub mtype: MatchType,
     pub contextstr: String,
     pub generic_args: Vec<String>,
@@ -87,6 +105,8 @@ impl Match {
             filepath: self.filepath.clone(),
             point: self.point,
             local: self.local,
+            accessible: self.accessible,
+            score: self.score,
             mtype: self.mtype,
             contextstr: self.contextstr.clone(),
             generic_args: self.generic_args.clone(),
@@ -146,6 +166,22 @@ pub struct Path {
 }
 
 impl Path {
+    /// Parses a path such as '::std::io::File' (without any generic types)
+    pub fn from_str(s: &str) -> Path {
+        let mut v = s.split_str("::").collect::<Vec<_>>();
+        let mut global = false;
+        if v[0] == "" {      // i.e. starts with '::' e.g. ::std::io::blah
+            v.remove(0);
+            global = true;
+        }
+
+        let segs = v
+            .iter()
+            .map(|x| PathSegment{ name: x.to_string(), types: Vec::new() })
+            .collect::<Vec<_>>();
+        return Path{ global: global, segments: segs };
+    }
+
     pub fn generic_types(&self) -> ::std::slice::Items<Path> {
         return self.segments[self.segments.len()-1].types.iter();
     }
@@ -203,65 +239,244 @@ impl fmt::Show for PathSearch {
     }
 }
 
-pub fn load_file(filepath: &path::Path) -> String {
-    let rawbytes = BufferedReader::new(File::open(filepath)).read_to_end().unwrap();
+/// The ways a query can fail
+#[deriving(Clone, PartialEq)]
+pub enum RacerError {
+    IoError(path::Path, String),   // the file, and what went wrong
+    InvalidUtf8(path::Path),
+    ParseError(String),
+    UnresolvedPath(String),
+    RecursionLimit(uint),
+    Inaccessible(String),          // the item is private to another module
+    InvalidRename(String),         // why the rename was refused
+    InternalError(String)          // a panic outside of the parser, i.e. a bug in racer
+}
 
-    // skip BOF bytes, if present
-    if rawbytes.slice(0,3) == [0xEF, 0xBB, 0xBF] {
-        let mut it = rawbytes.into_iter();
-        it.next(); it.next(); it.next();
-        return String::from_utf8(it.collect::<Vec<_>>()).unwrap();
-    } else {
-        return String::from_utf8(rawbytes).unwrap();
+impl RacerError {
+    /// A short name for the kind of error, for machine readable output
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            RacerError::IoError(..) => "io",
+            RacerError::InvalidUtf8(..) => "utf8",
+            RacerError::ParseError(..) => "parse",
+            RacerError::UnresolvedPath(..) => "unresolved",
+            RacerError::RecursionLimit(..) => "recursion",
+            RacerError::Inaccessible(..) => "inaccessible",
+            RacerError::InvalidRename(..) => "rename",
+            RacerError::InternalError(..) => "internal"
+        }
     }
 }
 
+impl fmt::Show for RacerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RacerError::IoError(ref path, ref msg) =>
+                write!(f, "couldn't read {}: {}", path.display(), msg),
+            RacerError::InvalidUtf8(ref path) =>
+                write!(f, "{} is not valid utf-8", path.display()),
+            RacerError::ParseError(ref msg) =>
+                write!(f, "parse failure: {}", msg),
+            RacerError::UnresolvedPath(ref path) =>
+                write!(f, "couldn't resolve '{}'", path),
+            RacerError::RecursionLimit(limit) =>
+                write!(f, "gave up after recursing {} levels deep", limit),
+            RacerError::Inaccessible(ref name) =>
+                write!(f, "'{}' is private to another module", name),
+            RacerError::InvalidRename(ref msg) =>
+                write!(f, "can't rename: {}", msg),
+            RacerError::InternalError(ref msg) =>
+                write!(f, "internal error: {}", msg)
+        }
+    }
+}
+
+pub type RacerResult<T> = Result<T, RacerError>;
+
+// Runs a query in its own task, so that a panic in the parser or resolver
+// becomes an error rather than taking down the caller (e.g. a daemon), and
+// with state of its own (see query.rs). The point of the query decides
+// whether test only code is searched (see cfg.rs).
+fn run_query<T: Send, F: FnOnce() -> RacerResult<T> + Send>(filepath: path::Path, pos: uint,
+                                                            f: F) -> RacerResult<T> {
+    match query::run(move || {
+        cfg::set_cursor(&filepath, pos);
+        f()
+    }) {
+        Ok(res) => res,
+        // libsyntax reports the details on stderr and panics with FatalError
+        Err(ref e) if e.is::<FatalError>() =>
+            Err(RacerError::ParseError("couldn't parse the source".to_string())),
+        Err(e) => {
+            let msg = match e.downcast_ref::<&'static str>() {
+                Some(s) => s.to_string(),
+                None => e.downcast_ref::<String>().map_or("unknown panic".to_string(), |s| s.clone())
+            };
+            Err(RacerError::InternalError(msg))
+        }
+    }
+}
+
+/// Loads the file (or the unsaved buffer registered for it, see vfs.rs)
+pub fn try_load_file(filepath: &path::Path) -> RacerResult<String> {
+    return filecache::try_load_file(filepath);
+}
+
+/// Like try_load_file, but a file that can't be loaded is treated as empty
+/// so that one bad file doesn't stop a search
+pub fn load_file(filepath: &path::Path) -> String {
+    return filecache::load_file(filepath);
+}
+
 pub fn load_file_and_mask_comments(filepath: &path::Path) -> String {
-    let filetxt = BufferedReader::new(File::open(filepath)).read_to_end().unwrap();
-    let src = str::from_utf8(filetxt.as_slice()).unwrap();
-    let msrc = scopes::mask_comments(src);
-    return msrc;
+    return filecache::load_file_and_mask_comments(filepath);
+}
+
+/// The completions at pos, best first (see ranking.rs), leaving out items
+/// that can't be used there (e.g. private functions of other modules)
+pub fn complete_from_file(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<vec::MoveItems<Match>> {
+    return complete(src, filepath, pos, false);
+}
+
+/// Like complete_from_file, but includes the inaccessible items, flagged
+/// with accessible = false
+pub fn complete_from_file_including_inaccessible(src: &str, filepath: &path::Path,
+                                                 pos: uint) -> RacerResult<vec::MoveItems<Match>> {
+    return complete(src, filepath, pos, true);
+}
+
+fn complete(src: &str, filepath: &path::Path, pos: uint,
+            include_inaccessible: bool) -> RacerResult<vec::MoveItems<Match>> {
+    let (src, filepath) = (src.to_string(), filepath.clone());
+    return run_query(filepath.clone(), pos, move || {
+        let out = check_accessibility(complete_from_file_(src.as_slice(), &filepath, pos),
+                                      &filepath, pos, include_inaccessible);
+        if out.is_empty() && nameres::recursion_limit_hit() {
+            return Err(RacerError::RecursionLimit(nameres::RECURSION_LIMIT));
+        }
+        Ok(out.into_iter().map(macros::unexpand_match).collect::<Vec<_>>().into_iter())
+    });
+}
+
+// The path expression at start, taking the use statement it is in (if any)
+// into account, e.g. 'Ba' in 'use foo::{Bar, Ba' is really '::foo::Ba'
+fn path_at(msrc: &str, start: uint, expr: &str) -> Path {
+    return match scopes::get_use_path_prefix(msrc, start) {
+        Some(prefix) => nameres::to_use_path(Path::from_str(format!("{}{}", prefix, expr).as_slice())),
+        None => Path::from_str(expr)
+    };
+}
+
+// scopes::split_into_context_and_completion, except that a name where a field
+// goes in a struct literal or pattern has the struct's path as its context
+fn completion_context<'a>(msrc: &'a str, start: uint,
+                          expr: &'a str) -> (&'a str, &'a str, CompletionType) {
+    let (contextstr, searchstr, completetype) = scopes::split_into_context_and_completion(expr);
+    if contextstr.is_empty() {
+        if let Some((pathstart, pathend, _)) = scopes::get_struct_field_context(msrc, start) {
+            return (msrc.slice(pathstart, pathend), searchstr, CompletionType::CompleteStructField);
+        }
+    }
+    return (contextstr, searchstr, completetype);
+}
+
+// The struct named by the path in front of a struct literal or pattern
+fn resolve_struct(contextstr: &str, filepath: &path::Path, pos: uint) -> Option<Match> {
+    let path = Path::from_str(contextstr);
+    return nameres::resolve_path_(&path, filepath, pos, SearchType::ExactMatch,
+                                  Namespace::TypeNamespace).nth(0)
+        .and_then(|m| if m.mtype == MatchType::Struct { Some(m) } else { None });
+}
+
+// The enum being matched on, if start is in the pattern of a match arm.
+// msrc is src with its comments masked.
+fn matched_enum(src: &str, msrc: &str, filepath: &path::Path, start: uint) -> Option<Match> {
+    let bodystart = scopes::scope_start(msrc, start);
+    let exprstart = match scopes::find_match_discriminant(msrc, bodystart) {
+        Some(n) => n,
+        None => return None
+    };
+    let armstart = scopes::find_match_arm_start(msrc, bodystart, start);
+    let pattern = msrc.slice(armstart, start);
+    // not in the pattern, or nested in it (e.g. 'Some(Fo')
+    if pattern.contains("=>") || pattern.contains_char('(') || pattern.contains_char('[') {
+        return None;
+    }
+    let expr = src.slice(exprstart, bodystart - 1).trim();
+    return match ast::get_type_of(expr.to_string(), filepath, exprstart) {
+        Some(Ty::TyMatch(m)) if m.mtype == MatchType::Enum => Some(m),
+        _ => None
+    };
 }
 
-pub fn complete_from_file(src: &str, filepath: &path::Path, pos: uint) -> vec::MoveItems<Match> {
+fn complete_from_file_(src: &str, filepath: &path::Path, pos: uint) -> Vec<Match> {
 
     let start = scopes::get_start_of_search_expr(src, pos);
     let expr = src.slice(start,pos);
+    // the scope functions need braces in comments and strings masked
+    let msrc = scopes::mask_comments(src);
+    let msrc = msrc.as_slice();
 
-    let (contextstr, searchstr, completetype) = scopes::split_into_context_and_completion(expr);
+    let (contextstr, searchstr, completetype) = completion_context(msrc, start, expr);
 
     debug!("{}: contextstr is |{}|, searchstr is |{}|",
            completetype, contextstr, searchstr);
+    let span = trace::span(|| format!("complete '{}' ({}, context '{}', search '{}') in {}",
+                                      expr, completetype, contextstr, searchstr, filepath.display()));
 
     let mut out = Vec::new();
+    let mut preferred = Vec::new();
 
     match completetype {
         CompletionType::CompletePath => {
-            let mut v = expr.split_str("::").collect::<Vec<_>>();
-            let mut global = false;
-            if v[0] == "" {      // i.e. starts with '::' e.g. ::std::io::blah
-                v.remove(0);
-                global = true;
-            }
+            let path = path_at(msrc, start, expr);
 
-            let segs = v
-                .iter()
-                .map(|x| PathSegment{name:x.to_string(), types: Vec::new()})
-                .collect::<Vec<_>>();
-            let path = Path{ global: global, segments: segs };
-
-            for m in nameres::resolve_path(&path, filepath, pos, 
+            for m in nameres::resolve_path_(&path, filepath, pos, 
                                          SearchType::StartsWith, Namespace::BothNamespaces) {
                 out.push(m);
             }
+
+            // in a match arm, the variants of the enum being matched come first
+            if let Some(enumm) = matched_enum(src, msrc, filepath, start) {
+                preferred = nameres::search_enum_variants(searchstr, &enumm,
+                                                          SearchType::StartsWith).collect();
+            }
         },
+        CompletionType::CompleteStructField => {
+            match resolve_struct(contextstr, filepath, pos) {
+                Some(structm) => {
+                    // leave out the fields that are already given
+                    let given = scopes::get_struct_field_context(msrc, start)
+                        .map_or(Vec::new(), |(_, _, given)| given);
+                    for m in nameres::search_struct_fields(searchstr, &structm,
+                                                           SearchType::StartsWith) {
+                        if !given.contains(&m.matchstr) {
+                            out.push(m);
+                        }
+                    }
+                }
+                None => {
+                    // e.g. the body of 'trait Foo: Bar {', complete it as a path
+                    trace::note(|| format!("'{}' isn't a struct", contextstr));
+                    for m in nameres::resolve_path_(&Path::from_str(searchstr), filepath, pos,
+                                                    SearchType::StartsWith,
+                                                    Namespace::BothNamespaces) {
+                        out.push(m);
+                    }
+                }
+            }
+        }
         CompletionType::CompleteField => {
             let context = ast::get_type_of(contextstr.to_string(), filepath, pos);
             debug!("complete_from_file context is {}", context);
+            if context.is_none() {
+                trace::note(|| format!("couldn't infer the type of '{}'", contextstr));
+            }
             context.map(|ty| {
                 match ty {
                     Ty::TyMatch(m) => {
-                        for m in nameres::search_for_field_or_method(m, searchstr, SearchType::StartsWith) {
+                        for m in nameres::search_for_field_or_method_with_derefs(m, searchstr,
+                                                                                 SearchType::StartsWith) {
                             out.push(m)
                         }
                     }
@@ -270,53 +485,505 @@ pub fn complete_from_file(src: &str, filepath: &path::Path, pos: uint) -> vec::M
             });
         }
     }
-    return out.into_iter();
+    let expected = match completetype {
+        CompletionType::CompletePath => ranking::expected_kind(src, start),
+        _ => ranking::Expected::AnyExpected
+    };
+    let query = ranking::Query{ filepath: filepath, searchstr: searchstr, expected: expected,
+                                preferred: preferred };
+    let out = ranking::rank(out, &query);
+    span.found(out.len());
+    return out;
+}
+
+// Sets the accessible flag of the matches (relative to pos), and drops the
+// inaccessible ones unless include_inaccessible
+fn check_accessibility(matches: Vec<Match>, filepath: &path::Path, pos: uint,
+                       include_inaccessible: bool) -> Vec<Match> {
+    let mut checker = privacy::AccessChecker::new(filepath, pos);
+    return matches.into_iter().filter_map(|mut m| {
+        m.accessible = checker.is_accessible(&m);
+        if !m.accessible {
+            trace::note(|| format!("{} {} in {} is private to its module",
+                                   m.mtype, m.matchstr, m.filepath.display()));
+        }
+        if m.accessible || include_inaccessible { Some(m) } else { None }
+    }).collect();
 }
 
+/// Finds the definition of the item at pos. It is an error if the item
+/// can't be used there (e.g. a private function of another module).
+pub fn find_definition(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Match> {
+    return definition(src, filepath, pos, false);
+}
 
-pub fn find_definition(src: &str, filepath: &path::Path, pos: uint) -> Option<Match> {
-    return find_definition_(src, filepath, pos);
+/// Like find_definition, but returns inaccessible items too, flagged with
+/// accessible = false
+pub fn find_definition_including_inaccessible(src: &str, filepath: &path::Path,
+                                              pos: uint) -> RacerResult<Match> {
+    return definition(src, filepath, pos, true);
+}
+
+fn definition(src: &str, filepath: &path::Path, pos: uint,
+              include_inaccessible: bool) -> RacerResult<Match> {
+    let (src, filepath) = (src.to_string(), filepath.clone());
+    return run_query(filepath.clone(), pos, move || {
+        let def = try!(definition_or_error(src.as_slice(), &filepath, pos));
+        let name = def.matchstr.clone();
+        match check_accessibility(vec![def], &filepath, pos, include_inaccessible).pop() {
+            Some(def) => Ok(macros::unexpand_match(def)),
+            None => Err(RacerError::Inaccessible(name))
+        }
+    });
+}
+
+// find_definition_, with the reason it failed
+fn definition_or_error(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Match> {
+    match find_definition_(src, filepath, pos) {
+        Some(m) => Ok(m),
+        None if nameres::recursion_limit_hit() =>
+            Err(RacerError::RecursionLimit(nameres::RECURSION_LIMIT)),
+        None => {
+            let (start, end) = scopes::expand_search_expr(src, pos);
+            Err(RacerError::UnresolvedPath(src.slice(start, end).to_string()))
+        }
+    }
 }
 
 pub fn find_definition_(src: &str, filepath: &path::Path, pos: uint) -> Option<Match> {
+    return find_definition_in_namespace(src, filepath, pos, Namespace::BothNamespaces);
+}
+
+fn find_definition_in_namespace(src: &str, filepath: &path::Path, pos: uint,
+                                namespace: Namespace) -> Option<Match> {
     let (start, end) = scopes::expand_search_expr(src, pos);
     let expr = src.slice(start,end);
+    // the scope functions need braces in comments and strings masked
+    let msrc = scopes::mask_comments(src);
+    let msrc = msrc.as_slice();
 
-    let (contextstr, searchstr, completetype) = scopes::split_into_context_and_completion(expr);
+    let (contextstr, searchstr, completetype) = completion_context(msrc, start, expr);
 
     debug!("find_definition_ for |{}| |{}| {}",contextstr, searchstr, completetype);
+    let span = trace::span(|| format!("find definition of '{}' ({}, context '{}', search '{}') in {}",
+                                      expr, completetype, contextstr, searchstr, filepath.display()));
 
-    return match completetype {
+    let res = match completetype {
         CompletionType::CompletePath => {
-            let mut v = expr.split_str("::").collect::<Vec<_>>();
-            let mut global = false;
-            if v[0] == "" {      // i.e. starts with '::' e.g. ::std::io::blah
-                v.remove(0);
-                global = true;
-            }
-
-            let segs = v
-                .iter()
-                .map(|x| PathSegment{ name: x.to_string(), types: Vec::new() })
-                .collect::<Vec<_>>();
-            let path = Path{ global: global, segments: segs };
+            let path = path_at(msrc, start, expr);
 
-            return nameres::resolve_path(&path, filepath, pos, 
-                                         SearchType::ExactMatch, Namespace::BothNamespaces).nth(0);
+            nameres::resolve_path_(&path, filepath, pos,
+                                   SearchType::ExactMatch, namespace).nth(0)
         },
+        CompletionType::CompleteStructField => {
+            match resolve_struct(contextstr, filepath, pos) {
+                Some(structm) => nameres::search_struct_fields(searchstr, &structm,
+                                                               SearchType::ExactMatch).nth(0),
+                None => nameres::resolve_path_(&Path::from_str(searchstr), filepath, pos,
+                                               SearchType::ExactMatch, namespace).nth(0)
+            }
+        }
         CompletionType::CompleteField => {
             let context = ast::get_type_of(contextstr.to_string(), filepath, pos);
             debug!("context is {}",context);
+            if context.is_none() {
+                trace::note(|| format!("couldn't infer the type of '{}'", contextstr));
+            }
 
-            return context.and_then(|ty| {
+            context.and_then(|ty| {
                 // for now, just handle matches
                 match ty {
                     Ty::TyMatch(m) => {
-                        return nameres::search_for_field_or_method(m, searchstr, SearchType::ExactMatch).nth(0);
+                        return nameres::search_for_field_or_method_with_derefs(m, searchstr,
+                                                                               SearchType::ExactMatch).nth(0);
                     }
                     _ => None
                 }
-            });
+            })
+        }
+    };
+    span.found(res.iter().count());
+    return res;
+}
+
+// The namespace that references to an item of this type are resolved in.
+// e.g. a struct and a function can share a name without clashing
+fn namespace_of(mtype: MatchType) -> Namespace {
+    match mtype {
+        MatchType::Struct | MatchType::Module | MatchType::Enum |
+        MatchType::Type | MatchType::Builtin | MatchType::TypeParameter |
+        MatchType::Trait => Namespace::TypeNamespace,
+        MatchType::Function | MatchType::Let | MatchType::FnArg | MatchType::ClosureArg |
+        MatchType::MatchArm | MatchType::Const | MatchType::Static => Namespace::ValueNamespace,
+        _ => Namespace::BothNamespaces
+    }
+}
+
+// Guesses which namespace an occurrence of a name is in from the tokens
+// around it. None if it could be either.
+fn namespace_of_occurrence(msrc: &str, start: uint, end: uint) -> Option<Namespace> {
+    let before = msrc.slice_to(start).trim_right();
+    let after = msrc.slice_from(end).trim_left();
+    if after.starts_with("(") {
+        Some(Namespace::ValueNamespace)     // e.g. foo(a, b)
+    } else if after.starts_with("{") && !is_condition_end(before) {
+        Some(Namespace::TypeNamespace)      // struct literal, e.g. Foo { a: 1 }
+    } else if after.starts_with("::") || before.ends_with("->") ||
+              (before.ends_with(":") && !before.ends_with("::")) ||
+              before.ends_with(" impl") || before.ends_with(" for") {
+        Some(Namespace::TypeNamespace)      // e.g. Foo::new(), a: Foo, -> Foo, impl Foo
+    } else {
+        None
+    }
+}
+
+// True if the text before 'name {' ends in a way that makes the '{' the
+// start of a block rather than a struct literal, e.g. 'if a == name {'
+fn is_condition_end(before: &str) -> bool {
+    let keywords = ["match", "if", "while", "in"];
+    let operators = ["==", "!=", "&&", "||", "<", ">"];
+    let lastword = before.words().last().unwrap_or("");
+    keywords.contains(&lastword) || operators.iter().any(|op| before.ends_with(*op))
+}
+
+// True if the occurrence is the name in the definition of an item or binding
+// other than a function (see is_fn_definition_site)
+fn is_definition_site(msrc: &str, start: uint) -> bool {
+    let before = msrc.slice_to(start).trim_right();
+    let keywords = ["struct", "enum", "trait", "type", "mod", "const", "static", "let"];
+    let lastword = before.words().last().unwrap_or("");
+    keywords.contains(&lastword) || before.ends_with("let mut")
+}
+
+fn is_fn_definition_site(msrc: &str, start: uint) -> bool {
+    msrc.slice_to(start).trim_right().words().last() == Some("fn")
+}
+
+// The function whose name is at src[start..end], in its definition
+fn function_at(src: &str, filepath: &path::Path, start: uint, end: uint) -> Match {
+    Match{ matchstr: src.slice(start, end).to_string(),
+           filepath: filepath.clone(),
+           point: start,
+           local: false,
+           accessible: true,
+           score: 0,
+           mtype: MatchType::Function,
+           contextstr: util::line_at_point(src, start).to_string(),
+           generic_args: Vec::new(),
+           generic_types: Vec::new() }
+}
+
+// The item to find the references of (or rename) at pos. A function's name
+// in its definition is the function itself, which can't always be reached
+// by path (e.g. a method).
+fn item_at(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Match> {
+    let msrc = scopes::mask_comments(src);
+    let (start, end) = scopes::expand_search_expr(msrc.as_slice(), pos);
+    if start < end && is_fn_definition_site(msrc.as_slice(), start) {
+        return Ok(function_at(src, filepath, start, end));
+    }
+    return definition_or_error(src, filepath, pos);
+}
+
+fn is_same_item(a: &Match, b: &Match) -> bool {
+    a.filepath == b.filepath && a.point == b.point
+}
+
+// The trait method that the method m declares or implements, if it is in a
+// trait or in an impl of one. Err with the trait's path if it is in an impl
+// of a trait that can't be resolved.
+fn trait_method_of(m: &Match) -> Result<Option<Match>, String> {
+    if m.mtype != MatchType::Function {
+        return Ok(None);
+    }
+    let msrc = load_file_and_mask_comments(&m.filepath);
+    let msrc = msrc.as_slice();
+    let bodystart = scopes::scope_start(msrc, m.point);
+    if bodystart == 0 {
+        return Ok(None);
+    }
+    let headerstart = match scopes::find_stmt_start(msrc, bodystart - 1) {
+        Some(n) => n,
+        None => return Ok(None)
+    };
+    // e.g. 'impl<T> Foo for Bar<T>'
+    let header = msrc.slice(headerstart, bodystart - 1).trim();
+    if header.starts_with("trait ") || header.starts_with("pub trait ") {
+        return Ok(Some(m.clone()));
+    }
+    if !header.starts_with("impl") {
+        return Ok(None);
+    }
+    let traitpath = match ast::parse_impl(format!("{} {{}}", header)).trait_path {
+        Some(path) => path,
+        None => return Ok(None)    // an inherent impl
+    };
+    return match nameres::resolve_path_(&traitpath, &m.filepath, headerstart,
+                                        SearchType::ExactMatch, Namespace::TypeNamespace).nth(0) {
+        Some(traitm) => Ok(nameres::search_trait_method(m.matchstr.as_slice(), &traitm)),
+        None => Err(format!("{}", traitpath))
+    };
+}
+
+// True if m is def or, when def is a method of a trait (def_method),
+// declares or implements the same trait method. Err if that depends on a
+// trait that can't be resolved.
+fn refers_to(m: &Match, def: &Match, def_method: &Option<Match>) -> Result<bool, String> {
+    if is_same_item(m, def) {
+        return Ok(true);
+    }
+    return match *def_method {
+        Some(ref dm) if m.mtype == MatchType::Function && m.matchstr == def.matchstr =>
+            trait_method_of(m).map(|tm| tm.map_or(false, |tm| is_same_item(&tm, dm))),
+        _ => Ok(false)
+    };
+}
+
+// What an occurrence of a name refers to
+enum Resolution {
+    Resolved(Match),
+    Ambiguous(Match, Match),   // different items in the type and value namespaces
+    Unresolved
+}
+
+fn resolve_occurrence(fsrc: &str, msrc: &str, filepath: &path::Path,
+                      start: uint, end: uint) -> Resolution {
+    match namespace_of_occurrence(msrc, start, end) {
+        Some(namespace) => {
+            match find_definition_in_namespace(fsrc, filepath, end, namespace) {
+                Some(m) => Resolution::Resolved(m),
+                None => Resolution::Unresolved
+            }
+        }
+        None => {
+            let t = find_definition_in_namespace(fsrc, filepath, end, Namespace::TypeNamespace);
+            let v = find_definition_in_namespace(fsrc, filepath, end, Namespace::ValueNamespace);
+            match (t, v) {
+                (Some(t), Some(v)) => {
+                    if is_same_item(&t, &v) {
+                        Resolution::Resolved(t)
+                    } else {
+                        Resolution::Ambiguous(t, v)
+                    }
+                }
+                (Some(m), None) | (None, Some(m)) => Resolution::Resolved(m),
+                (None, None) => Resolution::Unresolved
+            }
+        }
+    }
+}
+
+// Every whole-word occurrence of the definition's name in the files that
+// could refer to it, with what each occurrence resolves to. Skips the
+// definition itself, the definitions of other items with the same name
+// (except functions, which may declare or implement the same trait method,
+// see refers_to) and occurrences that can only be in the other namespace.
+fn resolve_occurrences(def: &Match, filepath: &path::Path) -> Vec<(path::Path, uint, Resolution)> {
+    let mut out = Vec::new();
+
+    // local bindings can only be referred to from their own file
+    let files = match def.mtype {
+        MatchType::Let | MatchType::FnArg | MatchType::ClosureArg |
+        MatchType::MatchArm => vec![def.filepath.clone()],
+        _ => nameres::get_crate_module_files(filepath)
+    };
+
+    let def_namespace = namespace_of(def.mtype);
+    for file in files.into_iter() {
+        let fsrc = load_file(&file);
+        let msrc = load_file_and_mask_comments(&file);
+        for point in util::find_ident_occurrences(msrc.as_slice(), def.matchstr.as_slice()).into_iter() {
+            if (file == def.filepath && point == def.point) || is_definition_site(msrc.as_slice(), point) {
+                continue;
+            }
+            let end = point + def.matchstr.len();
+            match (def_namespace, namespace_of_occurrence(msrc.as_slice(), point, end)) {
+                (Namespace::TypeNamespace, Some(Namespace::ValueNamespace)) |
+                (Namespace::ValueNamespace, Some(Namespace::TypeNamespace)) => continue,
+                _ => {}
+            }
+            let r = if is_fn_definition_site(msrc.as_slice(), point) {
+                Resolution::Resolved(function_at(fsrc.as_slice(), &file, point, end))
+            } else {
+                resolve_occurrence(fsrc.as_slice(), msrc.as_slice(), &file, point, end)
+            };
+            out.push((file.clone(), point, r));
+        }
+    }
+    return out;
+}
+
+/// Finds every reference to the item at pos: resolves it to its definition,
+/// then looks for identifiers in the crate's modules that resolve back to
+/// the same place. The definition itself is the first match.
+pub fn find_references(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<vec::MoveItems<Match>> {
+    let (src, filepath) = (src.to_string(), filepath.clone());
+    return run_query(filepath.clone(), pos, move || {
+        find_references_(src.as_slice(), &filepath, pos).map(|v| {
+            v.into_iter().map(macros::unexpand_match).collect::<Vec<_>>().into_iter()
+        })
+    });
+}
+
+fn find_references_(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Vec<Match>> {
+    let mut out = Vec::new();
+    let def = try!(item_at(src, filepath, pos));
+    let def_method = trait_method_of(&def).unwrap_or(None);
+
+    for (file, point, r) in resolve_occurrences(&def, filepath).into_iter() {
+        if let Resolution::Resolved(ref m) = r {
+            if refers_to(m, &def, &def_method) == Ok(true) {
+                debug!("find_references found {} {}", file.as_str(), point);
+                let fsrc = load_file(&file);
+                out.push(Match{ matchstr: def.matchstr.clone(),
+                                filepath: file.clone(),
+                                point: point,
+                                local: def.local,
+                                accessible: true,
+                                score: 0,
+                                mtype: def.mtype,
+                                contextstr: util::line_at_point(fsrc.as_slice(), point).to_string(),
+                                generic_args: Vec::new(),
+                                generic_types: Vec::new()
+                });
+            }
+        }
+    }
+    out.insert(0, def);
+    return Ok(out);
+}
+
+/// The full declaration and documentation of an item, see describe()
+#[deriving(Show, Clone)]
+pub struct Description {
+    pub m: Match,
+    pub signature: String,
+    pub docs: String,
+    // when describing the call enclosing the point, the argument the point is in
+    pub active_arg: Option<uint>
+}
+
+/// Describes the item at pos, for hover popups. If pos isn't on a
+/// resolvable identifier but is inside the arguments of a call, describes
+/// the function being called (for parameter hints while typing).
+pub fn describe(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Description> {
+    let (src, filepath) = (src.to_string(), filepath.clone());
+    return run_query(filepath.clone(), pos, move || describe_(src.as_slice(), &filepath, pos));
+}
+
+fn describe_(src: &str, filepath: &path::Path, pos: uint) -> RacerResult<Description> {
+    let on_ident = (pos < src.len() && util::is_ident_char(src.char_at(pos))) ||
+                   (pos > 0 && util::is_ident_char(src.char_at_reverse(pos)));
+    let found = if on_ident { definition_or_error(src, filepath, pos).map(|m| (m, None)) } else {
+        Err(RacerError::UnresolvedPath("".to_string()))
+    };
+
+    let found = found.or_else(|e| {
+        let msrc = scopes::mask_comments(src);
+        match scopes::find_enclosing_call(msrc.as_slice(), pos) {
+            Some((parenpos, argindex)) => {
+                let calleeend = msrc.as_slice().slice_to(parenpos).trim_right().len();
+                definition_or_error(src, filepath, calleeend).map(|m| (m, Some(argindex)))
+            }
+            None => Err(e)
+        }
+    });
+
+    return found.map(|(m, active_arg)| {
+        Description { signature: docs::get_signature(&m),
+                      docs: docs::get_docs(&m),
+                      m: macros::unexpand_match(m),
+                      active_arg: active_arg }
+    });
+}
+
+/// A replacement of the bytes [start, end) of a file with new text
+#[deriving(Show, Clone, PartialEq)]
+pub struct TextEdit {
+    pub filepath: path::Path,
+    pub start: uint,
+    pub end: uint,
+    pub text: String
+}
+
+fn is_valid_ident(s: &str) -> bool {
+    let keywords = ["as", "box", "break", "const", "continue", "crate", "else", "enum",
+                    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop",
+                    "match", "mod", "move", "mut", "priv", "proc", "pub", "ref", "return",
+                    "self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+                    "use", "where", "while"];
+    match s.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            s.chars().all(util::is_ident_char) && !keywords.contains(&s)
+        }
+        _ => false
+    }
+}
+
+/// Works out the edits needed to rename the item at pos (and every
+/// reference to it) to newname. Fails rather than risk a partial rename if
+/// any occurrence of the name can't be resolved.
+pub fn rename(src: &str, filepath: &path::Path, pos: uint, newname: &str) -> RacerResult<Vec<TextEdit>> {
+    let (src, filepath, newname) = (src.to_string(), filepath.clone(), newname.to_string());
+    return run_query(filepath.clone(), pos, move || {
+        rename_(src.as_slice(), &filepath, pos, newname.as_slice())
+    });
+}
+
+fn rename_(src: &str, filepath: &path::Path, pos: uint, newname: &str) -> RacerResult<Vec<TextEdit>> {
+    if !is_valid_ident(newname) {
+        return Err(RacerError::InvalidRename(format!("'{}' is not a valid identifier", newname)));
+    }
+
+    let def = try!(item_at(src, filepath, pos));
+    if macros::is_expanded_file_path(&def.filepath) {
+        return Err(RacerError::InvalidRename(format!("{} is generated by a macro", def.matchstr)));
+    }
+
+    let crate_files = nameres::get_crate_module_files(filepath);
+    if !crate_files.contains(&def.filepath) {
+        return Err(RacerError::InvalidRename(format!("{} is defined outside of this crate ({})",
+                                                     def.matchstr, def.filepath.display())));
+    }
+    // renaming a method of a trait renames its declaration and every impl of it
+    let def_method = match trait_method_of(&def) {
+        Ok(m) => m,
+        Err(traitpath) =>
+            return Err(RacerError::InvalidRename(format!("couldn't resolve the trait {} that {} implements",
+                                                         traitpath, def.matchstr)))
+    };
+    if let Some(ref dm) = def_method {
+        if !crate_files.contains(&dm.filepath) {
+            return Err(RacerError::InvalidRename(format!("{} is a method of a trait defined outside of this crate ({})",
+                                                         def.matchstr, dm.filepath.display())));
+        }
+    }
+
+    let mut edits = vec![TextEdit{ filepath: def.filepath.clone(), start: def.point,
+                                   end: def.point + def.matchstr.len(),
+                                   text: newname.to_string() }];
+
+    for (file, point, r) in resolve_occurrences(&def, filepath).into_iter() {
+        let is_ambiguous = match r {
+            Resolution::Resolved(ref m) => match refers_to(m, &def, &def_method) {
+                Ok(true) => {
+                    edits.push(TextEdit{ filepath: file.clone(), start: point,
+                                         end: point + def.matchstr.len(),
+                                         text: newname.to_string() });
+                    false
+                }
+                Ok(false) => false,  // a different item with the same name
+                Err(_) => true
+            },
+            Resolution::Ambiguous(ref t, ref v) => is_same_item(t, &def) || is_same_item(v, &def),
+            Resolution::Unresolved => true
+        };
+        if is_ambiguous {
+            let (line, col) = scopes::point_to_coords(load_file(&file).as_slice(), point);
+            return Err(RacerError::InvalidRename(
+                format!("couldn't resolve '{}' at {}:{}:{} unambiguously",
+                        def.matchstr, file.display(), line, col)));
         }
     }
+    return Ok(edits);
 }
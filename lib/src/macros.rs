@@ -0,0 +1,735 @@
+// A lightweight expander for simple macro_rules! macros, so that items
+// generated by macros defined in the crate can be found, and invocations in
+// expressions can be typed.
+//
+// This is nowhere near a full macro_rules implementation. Patterns can contain
+// literal tokens, '$name:fragment' captures and (non-nested) '$(...) sep*' or
+// '$(...) sep+' repetitions. The ident, tt, lifetime, literal and block
+// fragments capture a single token tree, the others (expr, ty, pat, ...)
+// capture everything up to the token that follows them in the pattern. The
+// first rule whose pattern matches is used, and the expansion isn't itself
+// expanded any further.
+//
+// Macros are read from the text of the files rather than the masked text
+// (see scopes::mask_comments), as masking blanks their string literals, so
+// the lexer skips comments itself.
+//
+// Items generated by an invocation are found in a copy of the file with the
+// invocation expanded (see expand_item_invocation). The copies are kept in a
+// cache private to this module, and dropped when the file they were made
+// from changes (see filecache::current_stamp), so a long running process
+// doesn't collect them. Matches in a
+// copy are mapped back to the invocation (see unexpand_match) before they
+// are returned to clients.
+
+use std::collections::HashMap;
+use std::iter::range;
+use std::mem;
+use std::str::from_str;
+use std::sync::{Mutex, Once, ONCE_INIT};
+use super::Match;
+use super::{filecache, scopes, util};
+use super::filecache::Stamp;
+
+#[deriving(Clone,PartialEq,Show)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Punct(char),
+    Group(char, Vec<Tt>)   // open delimiter, contents
+}
+
+#[deriving(Clone,Show)]
+struct Tt {
+    token: Token,
+    spaced: bool           // preceded by whitespace in the source
+}
+
+// spacing doesn't change the meaning of a token
+impl PartialEq for Tt {
+    fn eq(&self, other: &Tt) -> bool {
+        self.token == other.token
+    }
+}
+
+fn close_delim(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}'
+    }
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: uint
+}
+
+impl Lexer {
+    fn peek(&self, n: uint) -> Option<char> {
+        self.chars.get(self.pos + n).map(|c| *c)
+    }
+
+    fn read_while(&mut self, f: |char| -> bool) -> String {
+        let mut s = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if f(c) => s.push(c),
+                _ => return s
+            }
+            self.pos += 1;
+        }
+    }
+
+    // reads up to and including the (unescaped) closing quote
+    fn read_quoted(&mut self, quote: char) -> String {
+        let mut s = quote.to_string();
+        loop {
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None => break
+            };
+            s.push(c);
+            self.pos += 1;
+            if c == '\\' {
+                if let Some(escaped) = self.peek(0) {
+                    s.push(escaped);
+                    self.pos += 1;
+                }
+            } else if c == quote {
+                break;
+            }
+        }
+        return s;
+    }
+
+    // Skips whitespace and comments, returning whether there were any
+    fn skip_space(&mut self) -> bool {
+        let start = self.pos;
+        loop {
+            self.read_while(|c| c.is_whitespace());
+            match (self.peek(0), self.peek(1)) {
+                (Some('/'), Some('/')) => {
+                    self.read_while(|c| c != '\n');
+                }
+                (Some('/'), Some('*')) => {
+                    self.pos += 2;
+                    let mut depth = 1u;
+                    while depth > 0 && self.pos < self.chars.len() {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('/'), Some('*')) => { depth += 1; self.pos += 2; }
+                            (Some('*'), Some('/')) => { depth -= 1; self.pos += 2; }
+                            _ => self.pos += 1
+                        }
+                    }
+                }
+                _ => return self.pos > start
+            }
+        }
+    }
+
+    // Reads token trees up to the close delimiter (or the end of the text)
+    fn read_tts(&mut self, close: Option<char>) -> Vec<Tt> {
+        let mut out = Vec::new();
+        loop {
+            let spaced = self.skip_space();
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None => return out
+            };
+            self.pos += 1;
+            if Some(c) == close {
+                return out;
+            }
+            let token = match c {
+                '(' | '[' | '{' => Token::Group(c, self.read_tts(Some(close_delim(c)))),
+                '"' => Token::Literal(self.read_quoted('"')),
+                '\'' if self.peek(0) == Some('\\') || self.peek(1) == Some('\'') =>
+                    Token::Literal(self.read_quoted('\'')),
+                '\'' => Token::Literal(format!("'{}", self.read_while(|c| util::is_ident_char(c)))),
+                '0'...'9' => {
+                    let mut s = c.to_string();
+                    loop {
+                        s.push_str(self.read_while(|c| util::is_ident_char(c)).as_slice());
+                        // a decimal point, but not a range
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('.'), Some('0'...'9')) => {
+                                s.push('.');
+                                self.pos += 1;
+                            }
+                            _ => break
+                        }
+                    }
+                    Token::Literal(s)
+                }
+                c if util::is_ident_char(c) => {
+                    let mut s = c.to_string();
+                    s.push_str(self.read_while(|c| util::is_ident_char(c)).as_slice());
+                    Token::Ident(s)
+                }
+                _ => Token::Punct(c)
+            };
+            out.push(Tt{ token: token, spaced: spaced });
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Vec<Tt> {
+    let mut lexer = Lexer{ chars: src.chars().collect(), pos: 0 };
+    return lexer.read_tts(None);
+}
+
+fn tts_to_string(tts: &[Tt]) -> String {
+    let mut s = String::new();
+    for tt in tts.iter() {
+        if tt.spaced && !s.is_empty() {
+            s.push(' ');
+        }
+        match tt.token {
+            Token::Ident(ref t) | Token::Literal(ref t) => s.push_str(t.as_slice()),
+            Token::Punct(c) => s.push(c),
+            Token::Group(open, ref inner) => {
+                s.push(open);
+                s.push_str(tts_to_string(inner.as_slice()).as_slice());
+                // '{ ... }' is usually spaced on both sides
+                if open == '{' && inner.get(0).map_or(false, |tt| tt.spaced) {
+                    s.push(' ');
+                }
+                s.push(close_delim(open));
+            }
+        }
+    }
+    return s;
+}
+
+#[deriving(Show)]
+enum Matcher {
+    Tok(Tt),
+    Group(char, Vec<Matcher>),
+    Var(String, String),                   // name, fragment
+    Repeat(Vec<Matcher>, Option<Tt>, char) // matchers, separator, '*' or '+'
+}
+
+#[deriving(Clone,Show)]
+enum Binding {
+    One(Vec<Tt>),
+    Many(Vec<Vec<Tt>>)
+}
+
+type Bindings = HashMap<String, Binding>;
+
+struct MacroRule {
+    pattern: Vec<Matcher>,
+    body: Vec<Tt>
+}
+
+// The separator and operator following the '$(...)' of a repetition at
+// tts[i], and the index after them
+fn parse_repetition_suffix(tts: &[Tt], i: uint) -> (Option<Tt>, char, uint) {
+    let op_at = |j: uint| match tts.get(j).map(|tt| &tt.token) {
+        Some(&Token::Punct(c)) if c == '*' || c == '+' => Some(c),
+        _ => None
+    };
+    return match (op_at(i), op_at(i+1)) {
+        (Some(op), _) => (None, op, i+1),
+        (None, Some(op)) => (Some(tts[i].clone()), op, i+2),
+        _ => (None, '*', i)
+    };
+}
+
+fn compile_pattern(tts: &[Tt]) -> Vec<Matcher> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < tts.len() {
+        match (&tts[i].token, tts.get(i+1).map(|tt| &tt.token)) {
+            (&Token::Punct('$'), Some(&Token::Ident(ref name))) => {
+                match (tts.get(i+2).map(|tt| &tt.token), tts.get(i+3).map(|tt| &tt.token)) {
+                    (Some(&Token::Punct(':')), Some(&Token::Ident(ref frag))) => {
+                        out.push(Matcher::Var(name.clone(), frag.clone()));
+                        i += 4;
+                    }
+                    _ => {
+                        out.push(Matcher::Var(name.clone(), "tt".to_string()));
+                        i += 2;
+                    }
+                }
+            }
+            (&Token::Punct('$'), Some(&Token::Group('(', ref inner))) => {
+                let (sep, op, next) = parse_repetition_suffix(tts, i+2);
+                out.push(Matcher::Repeat(compile_pattern(inner.as_slice()), sep, op));
+                i = next;
+            }
+            (&Token::Group(open, ref inner), _) => {
+                out.push(Matcher::Group(open, compile_pattern(inner.as_slice())));
+                i += 1;
+            }
+            _ => {
+                out.push(Matcher::Tok(tts[i].clone()));
+                i += 1;
+            }
+        }
+    }
+    return out;
+}
+
+// rules look like '(pattern) => (body);' (with any delimiters)
+fn parse_rules(tts: &[Tt]) -> Vec<MacroRule> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i + 3 < tts.len() {
+        match (&tts[i].token, &tts[i+1].token, &tts[i+2].token, &tts[i+3].token) {
+            (&Token::Group(_, ref pattern), &Token::Punct('='),
+             &Token::Punct('>'), &Token::Group(_, ref body)) => {
+                out.push(MacroRule{ pattern: compile_pattern(pattern.as_slice()),
+                                    body: body.clone() });
+            }
+            _ => break
+        }
+        i += 4;
+        if tts.get(i).map_or(false, |tt| tt.token == Token::Punct(';')) {
+            i += 1;
+        }
+    }
+    return out;
+}
+
+// true if tt could be the start of what the matcher matches. Only literal
+// tokens and groups are considered, since captures can start with anything
+fn is_start_of(matcher: &Matcher, tt: &Tt) -> bool {
+    return match (matcher, &tt.token) {
+        (&Matcher::Tok(ref t), _) => t == tt,
+        (&Matcher::Group(open, _), &Token::Group(d, _)) => open == d,
+        _ => false
+    };
+}
+
+// Matches the matchers against a prefix of the input, returning the number
+// of token trees consumed. 'stop' is whatever follows the matchers in the
+// enclosing pattern, which ends a greedy capture at the end of them.
+fn match_prefix(matchers: &[Matcher], input: &[Tt], stop: Option<&Matcher>,
+                bindings: &mut Bindings) -> Option<uint> {
+    let mut pos = 0;
+    for (i, matcher) in matchers.iter().enumerate() {
+        let next = if i + 1 < matchers.len() { Some(&matchers[i+1]) } else { stop };
+        match *matcher {
+            Matcher::Tok(ref t) => {
+                if pos >= input.len() || input[pos] != *t {
+                    return None;
+                }
+                pos += 1;
+            }
+            Matcher::Group(open, ref inner) => {
+                match input.get(pos).map(|tt| &tt.token) {
+                    Some(&Token::Group(d, ref contents)) if d == open => {
+                        if match_prefix(inner.as_slice(), contents.as_slice(),
+                                        None, bindings) != Some(contents.len()) {
+                            return None;
+                        }
+                    }
+                    _ => return None
+                }
+                pos += 1;
+            }
+            Matcher::Var(ref name, ref frag) => {
+                let rest = input.slice_from(pos);
+                let n = match (frag.as_slice(), next) {
+                    ("ident", _) | ("tt", _) | ("lifetime", _) |
+                    ("literal", _) | ("block", _) => 1,
+                    (_, Some(m @ &Matcher::Tok(_))) | (_, Some(m @ &Matcher::Group(..))) =>
+                        rest.iter().position(|tt| is_start_of(m, tt)).unwrap_or(rest.len()),
+                    (_, Some(_)) => 1,
+                    (_, None) => rest.len()
+                };
+                // an expression can't contain a ',' or ';' outside of brackets
+                let n = match frag.as_slice() {
+                    "expr" => rest.slice_to(n).iter().position(|tt| {
+                        tt.token == Token::Punct(',') || tt.token == Token::Punct(';')
+                    }).unwrap_or(n),
+                    _ => n
+                };
+                if n == 0 || n > rest.len() {
+                    return None;
+                }
+                bindings.insert(name.clone(), Binding::One(rest.slice_to(n).to_vec()));
+                pos += n;
+            }
+            Matcher::Repeat(ref inner, ref sep, op) => {
+                let sepmatcher = sep.as_ref().map(|s| Matcher::Tok(s.clone()));
+                let innerstop = if sepmatcher.is_some() { sepmatcher.as_ref() } else { next };
+                let mut iterations: Vec<Bindings> = Vec::new();
+                loop {
+                    let rest = input.slice_from(pos);
+                    if rest.is_empty() || next.map_or(false, |m| is_start_of(m, &rest[0])) {
+                        break;
+                    }
+                    let mut skip = 0;
+                    if !iterations.is_empty() {
+                        if let Some(ref s) = *sep {
+                            if rest[0] != *s {
+                                break;
+                            }
+                            skip = 1;
+                        }
+                    }
+                    let mut b = HashMap::new();
+                    match match_prefix(inner.as_slice(), rest.slice_from(skip), innerstop, &mut b) {
+                        Some(n) if n > 0 => {
+                            pos += skip + n;
+                            iterations.push(b);
+                        }
+                        _ => break
+                    }
+                }
+                if op == '+' && iterations.is_empty() {
+                    return None;
+                }
+                let mut names = Vec::new();
+                vars_of_pattern(inner.as_slice(), &mut names);
+                for name in names.into_iter() {
+                    let captures = iterations.iter().map(|b| match b.get(&name) {
+                        Some(&Binding::One(ref tts)) => tts.clone(),
+                        _ => Vec::new()
+                    }).collect();
+                    bindings.insert(name, Binding::Many(captures));
+                }
+            }
+        }
+    }
+    return Some(pos);
+}
+
+fn vars_of_pattern(matchers: &[Matcher], out: &mut Vec<String>) {
+    for matcher in matchers.iter() {
+        match *matcher {
+            Matcher::Var(ref name, _) => out.push(name.clone()),
+            Matcher::Group(_, ref inner) | Matcher::Repeat(ref inner, _, _) =>
+                vars_of_pattern(inner.as_slice(), out),
+            Matcher::Tok(_) => {}
+        }
+    }
+}
+
+fn vars_of_body(tts: &[Tt], out: &mut Vec<String>) {
+    for (i, tt) in tts.iter().enumerate() {
+        match tt.token {
+            Token::Ident(ref name) if i > 0 && tts[i-1].token == Token::Punct('$') =>
+                out.push(name.clone()),
+            Token::Group(_, ref inner) => vars_of_body(inner.as_slice(), out),
+            _ => {}
+        }
+    }
+}
+
+fn transcribe(tts: &[Tt], bindings: &Bindings, out: &mut Vec<Tt>) {
+    let mut i = 0;
+    while i < tts.len() {
+        match (&tts[i].token, tts.get(i+1).map(|tt| &tt.token)) {
+            (&Token::Punct('$'), Some(&Token::Ident(ref name))) if bindings.contains_key(name) => {
+                if let Some(&Binding::One(ref captured)) = bindings.get(name) {
+                    // the capture takes the place (and the spacing) of the '$'
+                    for (n, tt) in captured.iter().enumerate() {
+                        let spaced = if n == 0 { tts[i].spaced } else { tt.spaced };
+                        out.push(Tt{ token: tt.token.clone(), spaced: spaced });
+                    }
+                }
+                i += 2;
+            }
+            (&Token::Punct('$'), Some(&Token::Group('(', ref inner))) => {
+                let (sep, _, next) = parse_repetition_suffix(tts, i+2);
+                let mut names = Vec::new();
+                vars_of_body(inner.as_slice(), &mut names);
+                // one copy for each iteration of the captures used inside
+                let count = names.iter().filter_map(|name| match bindings.get(name) {
+                    Some(&Binding::Many(ref captures)) => Some(captures.len()),
+                    _ => None
+                }).max().unwrap_or(0);
+                for n in range(0, count) {
+                    if n > 0 {
+                        if let Some(ref s) = sep {
+                            out.push(s.clone());
+                        }
+                    }
+                    let copystart = out.len();
+                    let mut b = bindings.clone();
+                    for (name, binding) in bindings.iter() {
+                        if let &Binding::Many(ref captures) = binding {
+                            if n < captures.len() {
+                                b.insert(name.clone(), Binding::One(captures[n].clone()));
+                            }
+                        }
+                    }
+                    transcribe(inner.as_slice(), &b, out);
+                    if n > 0 && copystart < out.len() {
+                        out[copystart].spaced = true;
+                    }
+                }
+                i = next;
+            }
+            (&Token::Group(open, ref inner), _) => {
+                let mut group = Vec::new();
+                transcribe(inner.as_slice(), bindings, &mut group);
+                out.push(Tt{ token: Token::Group(open, group), spaced: tts[i].spaced });
+                i += 1;
+            }
+            _ => {
+                out.push(tts[i].clone());
+                i += 1;
+            }
+        }
+    }
+}
+
+fn expand_rules(rules: &[MacroRule], args: &str) -> Option<String> {
+    let input = tokenize(args);
+    for rule in rules.iter() {
+        let mut bindings = HashMap::new();
+        if match_prefix(rule.pattern.as_slice(), input.as_slice(),
+                        None, &mut bindings) == Some(input.len()) {
+            let mut out = Vec::new();
+            transcribe(rule.body.as_slice(), &bindings, &mut out);
+            return Some(tts_to_string(out.as_slice()));
+        }
+    }
+    return None;
+}
+
+// 'name!(args)', 'name![args]' or 'name!{args}', optionally followed by ';'
+fn parse_invocation(tts: &[Tt]) -> Option<(String, &[Tt])> {
+    if tts.len() > 4 || (tts.len() == 4 && tts[3].token != Token::Punct(';')) {
+        return None;
+    }
+    return match (tts.get(0).map(|tt| &tt.token), tts.get(1).map(|tt| &tt.token),
+                  tts.get(2).map(|tt| &tt.token)) {
+        (Some(&Token::Ident(ref name)), Some(&Token::Punct('!')),
+         Some(&Token::Group(_, ref args))) if name.as_slice() != "macro_rules" =>
+            Some((name.clone(), args.as_slice())),
+        _ => None
+    };
+}
+
+// The macro_rules! definitions at the top level of the files: the macro
+// name, and the file and extent of the definition
+fn index_macro_rules(files: &[Path]) -> Vec<(String, Path, uint, uint)> {
+    let mut out = Vec::new();
+    for f in files.iter() {
+        let src = super::load_file(f);
+        for (start, end) in filecache::load_stmts(f).into_iter() {
+            let blob = src.slice(start, end);
+            if !blob.starts_with("macro_rules!") {
+                continue;
+            }
+            let namestart = blob.slice_from(12).find(|c: char| !c.is_whitespace())
+                .map_or(blob.len(), |n| n + 12);
+            let nameend = util::find_ident_end(blob, namestart);
+            if nameend > namestart {
+                out.push((blob.slice(namestart, nameend).to_string(), f.clone(), start, end));
+            }
+        }
+    }
+    return out;
+}
+
+fn crate_macro_rules(filepath: &Path) -> Vec<(String, Path, uint, uint)> {
+    filecache::load_crate_index(filepath, "macro_rules", |files| index_macro_rules(files))
+}
+
+// Only macros defined at the top level of the crate's files are found. A
+// definition in filepath itself wins over the others.
+fn find_macro_rules(name: &str, filepath: &Path) -> Vec<MacroRule> {
+    let index = crate_macro_rules(filepath);
+    let mut defs = index.iter().filter(|&&(ref n, _, _, _)| n.as_slice() == name)
+        .collect::<Vec<_>>();
+    defs.sort_by(|&&(_, ref a, _, _), &&(_, ref b, _, _)| (a != filepath).cmp(&(b != filepath)));
+
+    for &&(_, ref f, start, end) in defs.iter() {
+        let src = super::load_file(f);
+        let tts = tokenize(src.slice(start, end));
+        if let Some(&Token::Group(_, ref rules)) = tts.get(3).map(|tt| &tt.token) {
+            debug!("found macro_rules! {} in {}", name, f.display());
+            return parse_rules(rules.as_slice());
+        }
+    }
+    return Vec::new();
+}
+
+/// Expands an invocation of the macro called name, with the argument text
+/// args, using the macro_rules! definitions in filepath's crate. None if the
+/// macro isn't defined or none of its rules match.
+pub fn expand(name: &str, args: &str, filepath: &Path) -> Option<String> {
+    return expand_rules(find_macro_rules(name, filepath).as_slice(), args);
+}
+
+/// Splits a macro invocation statement into the macro name and the argument
+/// text, e.g. ("vec", "1u, 2") for 'vec![1u, 2]'
+pub fn split_invocation(blob: &str) -> Option<(String, String)> {
+    let tts = tokenize(blob);
+    return parse_invocation(tts.as_slice())
+        .map(|(name, args)| (name, tts_to_string(args)));
+}
+
+// An expanded copy of a file
+struct Expansion {
+    text: String,
+    start: uint,     // where the invocation is in the original
+    end: uint,
+    expend: uint     // where the expansion ends in the copy
+}
+
+// The version of a file, and the copies expanded from it keyed by the point
+// of the invocation
+struct FileExpansions {
+    stamp: Option<Stamp>,
+    copies: HashMap<uint, Expansion>
+}
+
+static mut EXPANSIONS: *const Mutex<HashMap<Path, FileExpansions>> =
+    0 as *const Mutex<HashMap<Path, FileExpansions>>;
+static INIT: Once = ONCE_INIT;
+
+fn expansions() -> &'static Mutex<HashMap<Path, FileExpansions>> {
+    unsafe {
+        INIT.doit(|| {
+            let m: Box<Mutex<HashMap<Path, FileExpansions>>> = box Mutex::new(HashMap::new());
+            EXPANSIONS = mem::transmute(m);
+        });
+        return &*EXPANSIONS;
+    }
+}
+
+/// The path that the copy of filepath with the macro invocation at point
+/// expanded is known by
+fn expanded_file_path(filepath: &Path, point: uint) -> Path {
+    let filename = filepath.filename_str().unwrap_or("");
+    return filepath.with_filename(format!("{}!{}", filename, point));
+}
+
+// The file an expanded copy was made from, and the point of the invocation
+fn split_expanded_file_path(filepath: &Path) -> Option<(Path, uint)> {
+    let filename = filepath.filename_str().unwrap_or("");
+    return filename.find('!').and_then(|n| {
+        from_str::<uint>(filename.slice_from(n + 1)).map(|point| {
+            (filepath.with_filename(filename.slice_to(n)), point)
+        })
+    });
+}
+
+pub fn is_expanded_file_path(filepath: &Path) -> bool {
+    split_expanded_file_path(filepath).is_some()
+}
+
+/// The file that an expanded copy (see expand_item_invocation) was made from
+pub fn unexpanded_file_path(filepath: &Path) -> Path {
+    return match split_expanded_file_path(filepath) {
+        Some((path, _)) => path,
+        None => filepath.clone()
+    };
+}
+
+/// The text of an expanded copy (see expand_item_invocation). None if the
+/// path isn't one, or the file it was made from has changed since.
+pub fn load_expanded_file(filepath: &Path) -> Option<String> {
+    let (path, point) = match split_expanded_file_path(filepath) {
+        Some(split) => split,
+        None => return None
+    };
+    let stamp = filecache::current_stamp(&path);
+    return expansions().lock().get(&path)
+        .and_then(|f| if f.stamp == stamp { f.copies.get(&point) } else { None })
+        .map(|e| e.text.clone());
+}
+
+/// Maps a match in an expanded copy back to the original file. Items
+/// generated by the macro are placed at its invocation.
+pub fn unexpand_match(mut m: Match) -> Match {
+    let (path, point) = match split_expanded_file_path(&m.filepath) {
+        Some(split) => split,
+        None => return m
+    };
+    m.point = match expansions().lock().get(&path).and_then(|f| f.copies.get(&point)) {
+        Some(e) if m.point < e.start => m.point,
+        Some(e) if m.point >= e.expend => m.point - e.expend + e.end,
+        _ => point
+    };
+    m.filepath = path;
+    return m;
+}
+
+/// If src[start..end] is an item-level invocation of a macro defined in the
+/// crate, makes a copy of the file with the invocation replaced by its
+/// expansion, so that the generated items can be resolved and typed like
+/// any other (filecache loads the copy by its path). src is the text of the
+/// file rather than the masked text. Returns the path and masked text of
+/// the copy, and where the expansion is in it.
+pub fn expand_item_invocation(src: &str, start: uint, end: uint,
+                              filepath: &Path) -> Option<(Path, String, uint, uint)> {
+    // most statements aren't invocations of the crate's macros, so check
+    // that before tokenizing
+    let blob = src.slice(start, end);
+    let nameend = blob.find(|c: char| !util::is_ident_char(c)).unwrap_or(blob.len());
+    if nameend == 0 || !blob.slice_from(nameend).trim_left().starts_with("!") {
+        return None;
+    }
+    let macroname = blob.slice_to(nameend);
+    if !crate_macro_rules(filepath).iter().any(|&(ref n, _, _, _)| n.as_slice() == macroname) {
+        return None;
+    }
+    let (name, args) = match split_invocation(blob) {
+        Some(invocation) => invocation,
+        None => return None
+    };
+    return expand(name.as_slice(), args.as_slice(), filepath).map(|expansion| {
+        let text = format!("{}{}{}", src.slice_to(start), expansion, src.slice_from(end));
+        let expend = start + expansion.len();
+        let stamp = filecache::current_stamp(filepath);
+        let mut expansions = expansions().lock();
+        // copies made from an older version of the file are stale
+        let is_stale = match expansions.get(filepath) {
+            Some(f) => f.stamp != stamp,
+            None => true
+        };
+        if is_stale {
+            expansions.insert(filepath.clone(), FileExpansions{ stamp: stamp,
+                                                               copies: HashMap::new() });
+        }
+        expansions.get_mut(filepath).unwrap().copies.insert(start, Expansion{
+            text: text.clone(), start: start, end: end, expend: expend
+        });
+        debug!("expanded {}! at {} in {} to {}", name, start, filepath.display(), expansion);
+        (expanded_file_path(filepath, start), scopes::mask_comments(text.as_slice()), start, expend)
+    });
+}
+
+#[cfg(test)]
+fn expand_with(def: &str, invocation: &str) -> Option<String> {
+    let tts = tokenize(def);
+    let rules = match tts.get(3).map(|tt| &tt.token) {
+        Some(&Token::Group(_, ref rules)) => parse_rules(rules.as_slice()),
+        _ => Vec::new()
+    };
+    let invtts = tokenize(invocation);
+    return parse_invocation(invtts.as_slice())
+        .and_then(|(_, args)| expand_rules(rules.as_slice(), tts_to_string(args).as_slice()));
+}
+
+#[test]
+fn expands_captures_and_repetitions() {
+    let def = "macro_rules! structs { ($($name:ident),+) => ($(pub struct $name { pub x: uint })+) }";
+    let got = expand_with(def, "structs!(Foo, Bar);").unwrap();
+    assert_eq!("pub struct Foo { pub x: uint } pub struct Bar { pub x: uint }", got.as_slice());
+}
+
+#[test]
+fn expands_first_matching_rule_with_greedy_fragments() {
+    let def = "macro_rules! add { ($a:expr) => ($a); ($a:expr, $b:expr) => ($a + $b) }";
+    assert_eq!("1u", expand_with(def, "add!(1u)").unwrap().as_slice());
+    assert_eq!("foo::bar(2) + 3", expand_with(def, "add!(foo::bar(2), 3)").unwrap().as_slice());
+    assert_eq!(None, expand_with(def, "add!()"));
+}
+
+#[test]
+fn skips_comments_and_keeps_string_literals() {
+    let def = "macro_rules! greet { // the only rule
+        ($n:ident) => (fn $n() -> &'static str { /* a /* nested */ comment */ \"hi\" }) }";
+    assert_eq!("fn foo() -> &'static str { \"hi\" }",
+               expand_with(def, "greet!(foo)").unwrap().as_slice());
+}
@@ -1,7 +1,7 @@
 use std::cell::{Cell};
 use std::{iter,option};
 use collections::vec;
-use super::nameres::resolve_path;
+use super::nameres::{resolve_path_, to_use_path};
 use super::scopes;
 use super::util::{symbol_matches, txt_matches, find_ident_end};
 use super::nameres::{get_module_file, get_crate_file};
@@ -12,6 +12,7 @@ use super::SearchType::{StartsWith, ExactMatch};
 use super::MatchType::{Let, Module, Function, Struct, Type, Trait, Enum, EnumVariant, Const, Static};
 use super::Namespace::BothNamespaces;
 use super::util;
+use super::trace;
 
 
 // Should I return a boxed trait object to make this signature nicer?
@@ -39,12 +40,13 @@ pub fn match_types(src: &str, blobstart: uint, blobend: uint,
 
 pub fn match_values(src: &str, blobstart: uint, blobend: uint, 
                   searchstr: &str, filepath: &Path, search_type: SearchType, 
-                  local: bool) -> iter::Chain<iter::Chain<iter::Chain<iter::Chain<option::Item<super::Match>, option::Item<super::Match>>, vec::MoveItems<super::Match>>, option::Item<super::Match>>, vec::MoveItems<super::Match>> {
+                  local: bool) -> iter::Chain<iter::Chain<iter::Chain<iter::Chain<iter::Chain<option::Item<super::Match>, option::Item<super::Match>>, vec::MoveItems<super::Match>>, option::Item<super::Match>>, vec::MoveItems<super::Match>>, vec::MoveItems<super::Match>> {
     let it = match_const(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter();
     let it = it.chain(match_static(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter());
     let it = it.chain(match_let(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter());
     let it = it.chain(match_fn(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter());
     let it = it.chain(match_if_let(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter());
+    let it = it.chain(match_for(src, blobstart, blobend, searchstr, filepath, search_type, local).into_iter());
     return it;
 }
 
@@ -70,6 +72,8 @@ pub fn match_const(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: filepath.clone(),
                                    point: blobstart + start,
                                    local: local,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: Static,
                                    contextstr: first_line(blob),
                                    generic_args: Vec::new(), 
@@ -103,6 +107,8 @@ pub fn match_static(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: filepath.clone(),
                                    point: blobstart + start,
                                    local: local,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: Const,
                                    contextstr: first_line(blob),
                                    generic_args: Vec::new(), 
@@ -114,12 +120,14 @@ pub fn match_static(msrc: &str, blobstart: uint, blobend: uint,
     return res;
 }
 
+// the bindings of 'if let' and 'while let' patterns
 pub fn match_if_let(msrc: &str, blobstart: uint, blobend: uint, 
                  searchstr: &str, filepath: &Path, search_type: SearchType,
                  local: bool)  -> Vec<Match> {
     let mut out = Vec::new();
     let blob = msrc.slice(blobstart, blobend);
-    if blob.starts_with("if let ") && txt_matches(search_type, searchstr, blob) {
+    if (blob.starts_with("if let ") || blob.starts_with("while let ")) &&
+        txt_matches(search_type, searchstr, blob) {
         let coords = ast::parse_let(String::from_str(blob));
         for &(start,end) in coords.iter() {
             let s = blob.slice(start,end);
@@ -128,6 +136,40 @@ pub fn match_if_let(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: filepath.clone(),
                                    point: blobstart + start,
                                    local: local,
+                                   accessible: true,
+                                   score: 0,
+                                   mtype: Let,
+                                   contextstr: first_line(blob),
+                                   generic_args: Vec::new(), 
+                                   generic_types: Vec::new()
+                         });
+                if let ExactMatch = search_type {
+                    break;
+                }
+            }
+        }
+    }
+    return out;
+}
+
+// the bindings of a for loop's pattern
+pub fn match_for(msrc: &str, blobstart: uint, blobend: uint, 
+                 searchstr: &str, filepath: &Path, search_type: SearchType,
+                 local: bool)  -> Vec<Match> {
+    let mut out = Vec::new();
+    let blob = msrc.slice(blobstart, blobend);
+    if blob.starts_with("for ") && txt_matches(search_type, searchstr, blob) {
+        // leave out the body, which may not parse while it's being edited
+        let coords = ast::parse_let(typeinf::generate_skeleton_for_parsing(blob));
+        for &(start,end) in coords.iter() {
+            let s = blob.slice(start,end);
+            if symbol_matches(search_type, searchstr, s) {
+                out.push(Match { matchstr: s.to_string(),
+                                   filepath: filepath.clone(),
+                                   point: blobstart + start,
+                                   local: local,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: Let,
                                    contextstr: first_line(blob),
                                    generic_args: Vec::new(), 
@@ -156,6 +198,8 @@ pub fn match_let(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: filepath.clone(),
                                    point: blobstart + start,
                                    local: local,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: Let,
                                    contextstr: first_line(blob),
                                    generic_args: Vec::new(), 
@@ -206,11 +250,13 @@ pub fn match_extern_crate(msrc: &str, blobstart: uint, blobend: uint,
 
             view_item.ident.clone().map(|ident|{
                 debug!("EXTERN CRATE {}",ident.as_slice());
-                get_crate_file(ident.as_slice()).map(|cratepath|{
+                get_crate_file(ident.as_slice(), filepath).map(|cratepath|{
                     res = Some(Match {matchstr: ident.to_string(),
                                       filepath: cratepath.clone(), 
                                       point: 0,
                                       local: false,
+                                      accessible: true,
+                                      score: 0,
                                       mtype: Module,
                                       contextstr: cratepath.as_str().unwrap().to_string(),
                                       generic_args: Vec::new(), 
@@ -224,11 +270,13 @@ pub fn match_extern_crate(msrc: &str, blobstart: uint, blobend: uint,
                 if symbol_matches(search_type, searchstr, ident.as_slice()) {
                     // e.g. extern core_collections = "collections";
                     let ref real_str = view_item.paths[0].segments[0].name;
-                    get_crate_file(real_str.as_slice()).map(|modpath|{
+                    get_crate_file(real_str.as_slice(), filepath).map(|modpath|{
                         res = Some(Match {matchstr: ident.to_string(),
                                        filepath: modpath.clone(), 
                                        point: 0,
                                        local: false,
+                                       accessible: true,
+                                       score: 0,
                                        mtype: Module,
                                        contextstr: modpath.as_str().unwrap().to_string(),
                                        generic_args: Vec::new(), 
@@ -271,6 +319,8 @@ pub fn match_mod(msrc: &str, blobstart: uint, blobend: uint,
                                filepath: filepath.clone(), 
                                point: blobstart + 4, 
                                local: false,
+                               accessible: true,
+                               score: 0,
                                mtype: Module,
                                contextstr: filepath.as_str().unwrap().to_string(),
                                   generic_args: Vec::new(), generic_types: Vec::new()
@@ -290,6 +340,8 @@ pub fn match_mod(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: modpath.clone(), 
                                    point: 0,
                                    local: false,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: Module,
                                    contextstr: modpath.as_str().unwrap().to_string(),
                                       generic_args: Vec::new(), generic_types: Vec::new()
@@ -312,6 +364,8 @@ pub fn match_mod(msrc: &str, blobstart: uint, blobend: uint,
                                filepath: filepath.clone(), 
                                point: blobstart + 8,
                                local: false,
+                               accessible: true,
+                               score: 0,
                                mtype: Module,
                                contextstr: blob.slice_to(blob.find_str("{").unwrap()).to_string(),
                                   generic_args: Vec::new(), generic_types: Vec::new()
@@ -332,6 +386,8 @@ pub fn match_mod(msrc: &str, blobstart: uint, blobend: uint,
                                       filepath: modpath.clone(), 
                                       point: 0,
                                       local: false,
+                                      accessible: true,
+                                      score: 0,
                                       mtype: Module,
                                       contextstr: modpath.as_str().unwrap().to_string(),
                                       generic_args: Vec::new(), 
@@ -368,6 +424,8 @@ pub fn match_struct(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Struct,
                            contextstr: first_line(blob),
                            generic_args: generics.generic_args,
@@ -391,6 +449,8 @@ pub fn match_type(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Type,
                            contextstr: first_line(blob),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -407,6 +467,8 @@ pub fn match_type(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Type,
                            contextstr: first_line(blob),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -429,6 +491,8 @@ pub fn match_trait(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Trait,
                            contextstr: first_line(blob),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -445,6 +509,8 @@ pub fn match_trait(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Trait,
                            contextstr: first_line(blob),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -472,6 +538,8 @@ pub fn match_enum_variants(msrc: &str, blobstart: uint, blobend: uint,
                                    filepath: filepath.clone(), 
                                    point: blobstart + offset,
                                    local: local,
+                                   accessible: true,
+                                   score: 0,
                                    mtype: EnumVariant,
                                    contextstr: first_line(blob.slice_from(offset)),
                                    generic_args: Vec::new(), 
@@ -507,6 +575,8 @@ pub fn match_enum(msrc: &str, blobstart: uint, blobend: uint,
                            filepath: filepath.clone(), 
                            point: blobstart + start,
                            local: local,
+                           accessible: true,
+                           score: 0,
                            mtype: Enum,
                            contextstr: first_line(blob),
                            generic_args: generics.generic_args,
@@ -551,24 +621,25 @@ pub fn match_use(msrc: &str, blobstart: uint, blobend: uint,
 
             if follow_glob {
                 ALREADY_GLOBBING.with(|c| { c.set(Some(true)) });
+                let span = trace::span(|| format!("follow glob '{}' for '{}'", blob, searchstr));
 
                 let seg = PathSegment{ name: searchstr.to_string(), types: Vec::new() };
                 let mut path = basepath.clone();
                 path.segments.push(seg);
                 debug!("found a glob: now searching for {}", path);
-                // TODO: pretty sure this isn't correct/complete, only works because
-                //  we recurse backwards up modules when searching
-                let path = hack_remove_self_and_super_in_modpaths(path);
+                let path = to_use_path(path);
 
-                for m in resolve_path(&path, filepath, 0, search_type, BothNamespaces) {
+                for m in resolve_path_(&path, filepath, blobstart, search_type, BothNamespaces) {
                     out.push(m);
                     if let ExactMatch = search_type {
                         break;
                     }
                 }
                 ALREADY_GLOBBING.with(|c| { c.set(None) });
+                span.found(out.len());
             } else {
                 debug!("not following glob");
+                trace::note(|| format!("not following glob '{}'", blob));
             }
         }
     } else if ((local && blob.starts_with("use ")) || blob.starts_with("pub use ")) && txt_matches(search_type, searchstr, blob) {     
@@ -580,6 +651,7 @@ pub fn match_use(msrc: &str, blobstart: uint, blobend: uint,
         }
 
         debug!("found use: {} in |{}|", searchstr, blob);
+        let span = trace::span(|| format!("follow use '{}' for '{}'", blob, searchstr));
         let view_item = ast::parse_view_item(String::from_str(blob));
 
         let ident = view_item.ident.unwrap_or("".to_string());
@@ -593,10 +665,11 @@ pub fn match_use(msrc: &str, blobstart: uint, blobend: uint,
                     // Do nothing because this will be picked up by the module
                     // search in a bit.
                 } else {
-                    let path = hack_remove_self_and_super_in_modpaths(path);
-                    for m in resolve_path(&path, filepath, 0, ExactMatch, BothNamespaces) {
+                    let path = to_use_path(path);
+                    for m in resolve_path_(&path, filepath, blobstart, ExactMatch, BothNamespaces) {
                         out.push(m);
                         if let ExactMatch = search_type  {
+                            span.found(out.len());
                             return out;
                         } else {
                             break;
@@ -612,13 +685,12 @@ pub fn match_use(msrc: &str, blobstart: uint, blobend: uint,
                     // Do nothing because this will be picked up by the module
                     // search in a bit.
                 } else if path.segments[len-1].name.as_slice().starts_with(searchstr) {
-                    // TODO: pretty sure this isn't correct/complete, only works because
-                    //  we recurse backwards up modules when searching
-                    let path = hack_remove_self_and_super_in_modpaths(path);
+                    let path = to_use_path(path);
 
-                    for m in resolve_path(&path, filepath, 0, ExactMatch, BothNamespaces) {
+                    for m in resolve_path_(&path, filepath, blobstart, ExactMatch, BothNamespaces) {
                         out.push(m);
                         if let ExactMatch = search_type  {
+                            span.found(out.len());
                             return out;
                         } else {
                             break;
@@ -627,20 +699,11 @@ pub fn match_use(msrc: &str, blobstart: uint, blobend: uint,
                 }
             }
         }
+        span.found(out.len());
     }
     return out;
 }
 
-fn hack_remove_self_and_super_in_modpaths(mut path: super::Path) -> super::Path {
-    if path.segments[0].name.as_slice() == "self" {
-        path.segments.remove(0);
-    }
-    if path.segments[0].name.as_slice() == "super" {
-        path.segments.remove(0);
-    }
-    return path;
-} 
-
 pub fn match_fn(msrc: &str, blobstart: uint, blobend: uint, 
              searchstr: &str, filepath: &Path, search_type: SearchType,
              local: bool) -> Option<Match> {
@@ -656,6 +719,8 @@ pub fn match_fn(msrc: &str, blobstart: uint, blobend: uint,
                        filepath: filepath.clone(), 
                        point: blobstart + start,
                        local: local,
+                       accessible: true,
+                       score: 0,
                        mtype: Function,
                        contextstr: first_line(blob),
                            generic_args: Vec::new(), 
@@ -671,6 +736,8 @@ pub fn match_fn(msrc: &str, blobstart: uint, blobend: uint,
                        filepath: filepath.clone(), 
                        point: blobstart + start,
                        local: local,
+                       accessible: true,
+                       score: 0,
                        mtype: Function,
                        contextstr: first_line(blob),
                            generic_args: Vec::new(), 
@@ -3,9 +3,10 @@
 extern crate collections;
 extern crate core;
 
-use super::{SearchType, Match, Namespace};
+use super::{SearchType, Match, Namespace, RacerError, RacerResult};
 use super::SearchType::{ExactMatch, StartsWith};
-use super::MatchType::{Module, Function, Struct, Enum, FnArg, Trait, StructField, Impl};
+use super::MatchType::{Module, Function, Struct, Enum, FnArg, ClosureArg, MatchArm, Trait, StructField,
+                       Impl, TypeParameter, Builtin};
 use super::Namespace::{TypeNamespace, ValueNamespace, BothNamespaces};
 
 use super::typeinf;
@@ -15,9 +16,19 @@ use super::ast;
 use super::util;
 use super::util::{symbol_matches, txt_matches, find_ident_end};
 use super::scopes;
+use super::vfs;
+use super::filecache;
+use super::cargo;
+use super::trace;
+use super::macros;
+use super::cfg;
 use std::io::{File};
 use std::{vec};
-use std::iter::Iterator;
+use std::iter::{Iterator, range};
+use super::query;
+use super::query::QueryState;
+use std::sync::Arc;
+use std::sync::atomic::SeqCst;
 use std;
 
 #[cfg(unix)]
@@ -37,11 +48,15 @@ fn reverse_to_start_of_fn(point: uint, msrc: &str) -> Option<uint> {
     })
 }
 
-fn search_struct_fields(searchstr: &str, structmatch: &Match,
-                        search_type: SearchType) -> vec::MoveItems<Match> {
+pub fn search_struct_fields(searchstr: &str, structmatch: &Match,
+                            search_type: SearchType) -> vec::MoveItems<Match> {
     let src = super::load_file(&structmatch.filepath);
-    let opoint = scopes::find_stmt_start(&*src, structmatch.point);
-    let structsrc = scopes::end_of_next_scope(src.slice_from(opoint.unwrap()));
+    let msrc = super::load_file_and_mask_comments(&structmatch.filepath);
+    let opoint = match scopes::find_stmt_start(&*msrc, structmatch.point) {
+        Some(opoint) => opoint,
+        None => return Vec::new().into_iter()
+    };
+    let structsrc = scopes::end_of_next_scope(src.slice_from(opoint));
 
     let fields = ast::parse_struct_fields(String::from_str(structsrc), 
                                           super::Scope::from_match(structmatch));
@@ -53,8 +68,10 @@ fn search_struct_fields(searchstr: &str, structmatch: &Match,
         if symbol_matches(search_type, searchstr, field.as_slice()) {
             out.push(Match { matchstr: field.to_string(),
                                 filepath: structmatch.filepath.clone(),
-                                point: fpos + opoint.unwrap(),
+                                point: fpos + opoint,
                                 local: structmatch.local,
+                                accessible: true,
+                                score: 0,
                                 mtype: StructField,
                                 contextstr: field.to_string(),
                                 generic_args: Vec::new(), generic_types: Vec::new()
@@ -64,6 +81,28 @@ fn search_struct_fields(searchstr: &str, structmatch: &Match,
     return out.into_iter();
 }
 
+pub fn search_enum_variants(searchstr: &str, enummatch: &Match,
+                            search_type: SearchType) -> vec::MoveItems<Match> {
+    let mut out = Vec::new();
+    let filesrc = super::load_file(&enummatch.filepath);
+    let fmsrc = super::load_file_and_mask_comments(&enummatch.filepath);
+    let scopestart = match scopes::find_stmt_start(&*fmsrc, enummatch.point) {
+        Some(scopestart) => scopestart,
+        None => return out.into_iter()
+    };
+    let scopesrc = filesrc.slice_from(scopestart);
+    codeiter::iter_stmts(scopesrc).nth(0).map(|(blobstart,blobend)|{
+        for m in matchers::match_enum_variants(&*filesrc,
+                                               scopestart+blobstart,
+                                               scopestart+blobend,
+                                               searchstr, &enummatch.filepath, search_type, true) {
+            debug!("Found enum variant: {}", m.matchstr);
+            out.push(m);
+        }
+    });
+    return out.into_iter();
+}
+
 pub fn search_for_impl_methods(implsearchstr: &str,
                            fieldsearchstr: &str, point: uint, 
                            fpath: &Path, local: bool,
@@ -71,25 +110,118 @@ pub fn search_for_impl_methods(implsearchstr: &str,
     
     debug!("searching for impl methods |{}| |{}| {}",implsearchstr, fieldsearchstr, fpath.as_str());
 
-    let mut out = Vec::new();
+    let mut out: Vec<Match> = Vec::new();
 
-    for m in search_for_impls(point, implsearchstr, fpath, local, true) {
+    // methods implemented for the type come before the default methods of
+    // the traits it implements, so that they win when deduping
+    let (impls, traits): (Vec<Match>, Vec<Match>) =
+        search_for_all_impls(point, implsearchstr, fpath, local)
+        .into_iter().partition(|m| m.mtype != Trait);
+
+    for m in impls.into_iter().chain(traits.into_iter()) {
         debug!("found impl!! |{}| looking for methods",m);
         let src = super::load_file(&m.filepath);
+        // a trait's required methods only have a declaration
+        let include_decls = m.mtype == Trait;
 
         // find the opening brace and skip to it. 
         src.slice_from(m.point).find_str("{").map(|n|{
             let point = m.point + n + 1;
-            for m in search_scope_for_methods(point, &*src, fieldsearchstr, &m.filepath, search_type) {
-                out.push(m);
+            for m in search_scope_for_methods(point, &*src, fieldsearchstr, &m.filepath,
+                                              search_type, include_decls) {
+                if !out.iter().any(|o| o.matchstr == m.matchstr) {
+                    out.push(m);
+                }
             }
         });
     };
     return out.into_iter();
 }
 
+/// The method called name that the trait declares (or defines by default)
+pub fn search_trait_method(name: &str, traitm: &Match) -> Option<Match> {
+    let src = super::load_file(&traitm.filepath);
+    return src.slice_from(traitm.point).find_str("{").and_then(|n| {
+        search_scope_for_methods(traitm.point + n + 1, src.as_slice(), name, &traitm.filepath,
+                                 ExactMatch, true).nth(0)
+    });
+}
+
+// The impls of the type (and the traits they implement) anywhere in the
+// crate defining it, plus the traits named in #[deriving(...)] on its
+// definition
+fn search_for_all_impls(point: uint, searchstr: &str, filepath: &Path, local: bool) -> Vec<Match> {
+    // from point onwards in the defining file picks up impls of types
+    // declared inside functions. The top level ones are in the crate's index
+    let mut out = if point > 0 {
+        search_for_impls(point, searchstr, filepath, local, true).collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    for m in search_crate_for_impls(searchstr, filepath, local, true).into_iter() {
+        if !out.iter().any(|o| o.filepath == m.filepath && o.point == m.point) {
+            out.push(m);
+        }
+    }
+
+    let msrc = super::load_file_and_mask_comments(filepath);
+    for traitname in get_derived_traits(msrc.as_slice(), point).iter() {
+        let path = super::Path::from_str(derived_trait_path(traitname.as_slice()));
+        let m = resolve_path_(&path, filepath, point, ExactMatch, TypeNamespace).nth(0);
+        debug!("found derived trait |{}| {}", traitname, m);
+        m.map(|m| out.push(m));
+    }
+    return out;
+}
+
+// Where the traits that can be derived live, for those not in the prelude
+fn derived_trait_path(name: &str) -> &str {
+    match name {
+        "Show" => "std::fmt::Show",
+        "Hash" => "std::hash::Hash",
+        "Default" => "std::default::Default",
+        "Rand" => "std::rand::Rand",
+        "Encodable" => "serialize::Encodable",
+        "Decodable" => "serialize::Decodable",
+        _ => name
+    }
+}
+
+/// The traits named in the #[deriving(...)] attributes of the item at point
+pub fn get_derived_traits(msrc: &str, point: uint) -> Vec<String> {
+    let mut out = Vec::new();
+    let start = match scopes::find_stmt_start(msrc, point) {
+        Some(start) => start,
+        None => return out
+    };
+    // attributes are separate statements on the lines before the item
+    for line in msrc.slice_to(start).lines().rev() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with("#[") {
+            break;
+        }
+        for prefix in ["#[deriving(", "#[derive("].iter() {
+            if line.starts_with(*prefix) {
+                let args = line.slice_from(prefix.len());
+                let args = args.slice_to(args.find(')').unwrap_or(args.len()));
+                for name in args.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+                    out.push(name.to_string());
+                }
+            }
+        }
+    }
+    return out;
+}
+
+// Searches the scope for methods (functions that take self). If
+// include_decls is set then methods without a body (i.e. required trait
+// methods) are included too.
 fn search_scope_for_methods(point: uint, src:&str, searchstr:&str, filepath:&Path, 
-                      search_type: SearchType) -> vec::MoveItems<Match> {
+                      search_type: SearchType, include_decls: bool) -> vec::MoveItems<Match> {
     debug!("searching scope for methods {} |{}| {}",point, searchstr, filepath.as_str());
     
     let scopesrc = src.slice_from(point);
@@ -101,16 +233,26 @@ fn search_scope_for_methods(point: uint, src:&str, searchstr:&str, filepath:&Pat
             && typeinf::first_param_is_self(blob) {
             debug!("found a method starting |{}| |{}|",searchstr,blob);
             // TODO: parse this properly
-            let start = blob.find_str(format!("fn {}", searchstr).as_slice()).unwrap() + 3;
+            let start = match blob.find_str(format!("fn {}", searchstr).as_slice()) {
+                Some(n) => n + 3,
+                None => continue
+            };
             let end = find_ident_end(blob, start);
             let l = blob.slice(start, end);
             // TODO: make a better context string for functions
-            blob.find_str("{").map(|n| { // only matches if is a method implementation
-                let ctxt = blob.slice_to(n -1);
+            let decl_end = match blob.find_str("{") {
+                Some(n) => Some(n - 1),
+                None if include_decls => blob.find_str(";"),
+                None => None    // only matches if is a method implementation
+            };
+            decl_end.map(|n| {
+                let ctxt = blob.slice_to(n);
                 let m = Match {matchstr: l.to_string(),
                            filepath: filepath.clone(), 
                            point: point + blobstart + start,
                            local: true,
+                           accessible: true,
+                           score: 0,
                            mtype: Function,
                            contextstr: ctxt.to_string(),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -130,47 +272,102 @@ pub fn search_for_impls(pos: uint, searchstr: &str, filepath: &Path, local: bool
 
     let mut out = Vec::new();
     for (start,end) in codeiter::iter_stmts(src) { 
-        let blob = src.slice(start,end);
-
-        if blob.starts_with("impl") {
-            blob.find_str("{").map(|n|{
-                let mut decl = String::from_str(blob.slice_to(n+1));
-                decl.push_str("}");
-                if txt_matches(ExactMatch, searchstr, decl.as_slice()) {
-                    debug!("impl decl {}",decl);
-                    let implres = ast::parse_impl(decl);
-
-                    implres.name_path.map(|name_path| {
-                        name_path.segments.last().map(|name| {
-                            let m = Match {matchstr: name.name.clone(),
-                                       filepath: filepath.clone(), 
-                                       point: pos + start + 5,
-                                       local: local,
-                                       mtype: Impl,
-                                       contextstr: name.to_string(),
-                                       generic_args: Vec::new(), 
-                                       generic_types: Vec::new()
-                            };
-                            out.push(m);
-                        });
-                    });
-
-                    // find trait
-                    if include_traits && implres.trait_path.is_some() {
-                        let trait_path = implres.trait_path.unwrap();
-                        let m = resolve_path(&trait_path, 
-                                             filepath, pos + start, ExactMatch, TypeNamespace).nth(0);
-                        debug!("found trait |{}| {}",
-                                 trait_path, m);
-                        m.map(|m| out.push(m));
-                    }
-                }
-            });
-        }
+        search_impl(s.as_slice(), pos + start, pos + end, searchstr, filepath,
+                    local, include_traits, &mut out);
     }
     return out.into_iter();
 }
 
+// The header of the impl in the blob, closed off so that it parses, e.g.
+// 'impl Foo for Bar {}'
+fn impl_decl(blob: &str) -> Option<String> {
+    if !blob.starts_with("impl") {
+        return None;
+    }
+    return blob.find_str("{").map(|n| {
+        let mut decl = String::from_str(blob.slice_to(n+1));
+        decl.push_str("}");
+        decl
+    });
+}
+
+// Adds the impl of searchstr at src[start..end] (if it is one) to out, and
+// the trait it implements if include_traits is set
+fn search_impl(src: &str, start: uint, end: uint, searchstr: &str, filepath: &Path,
+               local: bool, include_traits: bool, out: &mut Vec<Match>) {
+    let decl = match impl_decl(src.slice(start, end)) {
+        Some(decl) => decl,
+        None => return
+    };
+    if !txt_matches(ExactMatch, searchstr, decl.as_slice()) {
+        return;
+    }
+    debug!("impl decl {}",decl);
+    let implres = ast::parse_impl(decl);
+
+    implres.name_path.map(|name_path| {
+        name_path.segments.last().map(|name| {
+            let m = Match {matchstr: name.name.clone(),
+                       filepath: filepath.clone(), 
+                       point: start + 5,
+                       local: local,
+                       accessible: true,
+                       score: 0,
+                       mtype: Impl,
+                       contextstr: name.to_string(),
+                       generic_args: Vec::new(), 
+                       generic_types: Vec::new()
+            };
+            out.push(m);
+        });
+    });
+
+    // find trait
+    if include_traits && implres.trait_path.is_some() {
+        let trait_path = implres.trait_path.unwrap();
+        let m = resolve_path_(&trait_path, 
+                             filepath, start, ExactMatch, TypeNamespace).nth(0);
+        debug!("found trait |{}| {}",
+                 trait_path, m);
+        m.map(|m| out.push(m));
+    }
+}
+
+// The impls at the top level of the files: the name of the type each is
+// for, and the file and extent of the impl
+fn index_impls(files: &[Path]) -> Vec<(String, Path, uint, uint)> {
+    let mut out = Vec::new();
+    for file in files.iter() {
+        let src = super::load_file(file);
+        for (start, end) in filecache::load_stmts(file).into_iter() {
+            let decl = match impl_decl(src.slice(start, end)) {
+                Some(decl) => decl,
+                None => continue
+            };
+            let name = ast::parse_impl(decl).name_path
+                .and_then(|path| path.segments.last().map(|seg| seg.name.clone()));
+            name.map(|name| out.push((name, file.clone(), start, end)));
+        }
+    }
+    return out;
+}
+
+/// The impls of searchstr anywhere in the crate containing filepath (and
+/// the traits they implement if include_traits is set). Only impls at the
+/// top level of the crate's files are found.
+pub fn search_crate_for_impls(searchstr: &str, filepath: &Path, local: bool,
+                              include_traits: bool) -> Vec<Match> {
+    let index = filecache::load_crate_index(filepath, "impls", |files| index_impls(files));
+    let mut out = Vec::new();
+    for &(ref name, ref file, start, end) in index.iter() {
+        if name.as_slice() == searchstr {
+            let src = super::load_file(file);
+            search_impl(src.as_slice(), start, end, searchstr, file, local, include_traits, &mut out);
+        }
+    }
+    return out;
+}
+
 fn search_fn_args(point: uint, msrc:&str, searchstr:&str, filepath:&Path, 
                       search_type: SearchType, local: bool) -> vec::MoveItems<Match> {
     debug!("search_fn_args for |{}| pt: {}",searchstr, point);
@@ -207,6 +404,8 @@ fn search_fn_args(point: uint, msrc:&str, searchstr:&str, filepath:&Path,
                                        filepath: filepath.clone(),
                                        point: fnstart + start - impl_header_len,
                                        local: local,
+                                       accessible: true,
+                                       score: 0,
                                        mtype: FnArg,
                                        contextstr: s.to_string(),
                                        generic_args: Vec::new(), 
@@ -221,6 +420,82 @@ fn search_fn_args(point: uint, msrc:&str, searchstr:&str, filepath:&Path,
     return out.into_iter();
 }
 
+// Searches the parameters of the closures starting in [scopestart, point)
+// whose bodies enclose point, innermost first
+fn search_closure_args(scopestart: uint, point: uint, msrc: &str, searchstr: &str,
+                       filepath: &Path, search_type: SearchType) -> Vec<Match> {
+    debug!("search_closure_args for |{}| pt: {}", searchstr, point);
+    let mut out = Vec::new();
+    for (open, close) in scopes::find_enclosing_closures(msrc, scopestart, point).into_iter() {
+        let header = msrc.slice(open, close + 1);
+        if !txt_matches(search_type, searchstr, header) {
+            continue;
+        }
+        // parse the header as a closure with an empty body
+        let coords = ast::parse_fn_args(header.to_string() + " ();");
+        for &(start, end) in coords.iter() {
+            let s = header.slice(start, end);
+            if symbol_matches(search_type, searchstr, s) {
+                let m = Match { matchstr: s.to_string(),
+                                filepath: filepath.clone(),
+                                point: open + start,
+                                local: true,
+                                accessible: true,
+                                score: 0,
+                                mtype: ClosureArg,
+                                contextstr: header.to_string(),
+                                generic_args: Vec::new(),
+                                generic_types: Vec::new()
+                };
+                debug!("search_closure_args matched: {}", m);
+                out.push(m);
+            }
+        }
+    }
+    return out;
+}
+
+// The bindings in the pattern of the match arm enclosing point, if the
+// scope starting at scopestart is the body of a match expression
+fn search_match_arm(scopestart: uint, point: uint, msrc: &str, searchstr: &str,
+                    filepath: &Path, search_type: SearchType) -> Vec<Match> {
+    debug!("search_match_arm for |{}| pt: {}", searchstr, point);
+    let mut out = Vec::new();
+    if scopes::find_match_discriminant(msrc, scopestart).is_none() {
+        return out;
+    }
+    let (patstart, patend) = match scopes::find_match_arm_pattern(msrc, scopestart, point) {
+        Some(pattern) => pattern,
+        None => return out
+    };
+    let pattern = msrc.slice(patstart, patend);
+    if !txt_matches(search_type, searchstr, pattern) {
+        return out;
+    }
+    // parse the pattern (and any guard) as the only arm of a match
+    let header = "match () { ";
+    let coords = ast::parse_fn_args(format!("{}{} => () }}", header, pattern));
+    for &(start, end) in coords.iter() {
+        let s = pattern.slice(start - header.len(), end - header.len());
+        if symbol_matches(search_type, searchstr, s) {
+            let m = Match { matchstr: s.to_string(),
+                            filepath: filepath.clone(),
+                            point: patstart + start - header.len(),
+                            local: true,
+                            accessible: true,
+                            score: 0,
+                            mtype: MatchArm,
+                            contextstr: pattern.trim_right().to_string(),
+                            generic_args: Vec::new(),
+                            generic_types: Vec::new()
+            };
+            debug!("search_match_arm matched: {}", m);
+            out.push(m);
+        }
+    }
+    return out;
+}
+
 pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Match> {
     debug!("do_file_search {}",searchstr);
     let mut out = Vec::new();
@@ -243,6 +518,8 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
                                            filepath: filepath.clone(), 
                                            point: 0,
                                            local: false,
+                                           accessible: true,
+                                           score: 0,
                                            mtype: Module,
                                            contextstr: fname.slice_from(3).to_string(),
                                            generic_args: Vec::new(), 
@@ -262,6 +539,8 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
                                                filepath: filepath.clone(), 
                                                point: 0,
                                                local: false,
+                                               accessible: true,
+                                               score: 0,
                                                mtype: Module,
                                                contextstr: filepath.as_str().unwrap().to_string(),
                                                generic_args: Vec::new(), 
@@ -278,6 +557,8 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
                                                filepath: filepath.clone(), 
                                                point: 0,
                                                local: false,
+                                               accessible: true,
+                                               score: 0,
                                                mtype: Module,
                                                contextstr: filepath.as_str().unwrap().to_string(),
                                                generic_args: Vec::new(), 
@@ -294,6 +575,8 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
                                                filepath: filepath.clone(), 
                                                point: 0,
                                                local: false,
+                                               accessible: true,
+                                               score: 0,
                                                mtype: Module,
                                                contextstr: filepath.as_str().unwrap().to_string(),
                                                generic_args: Vec::new(), 
@@ -309,6 +592,8 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
                                                filepath: fpath.clone(),
                                                point: 0,
                                                local: false,
+                                               accessible: true,
+                                               score: 0,
                                                mtype: Module,
                                                contextstr: fpath.as_str().unwrap().to_string(),
                                                generic_args: Vec::new(), 
@@ -326,20 +611,43 @@ pub fn do_file_search(searchstr: &str, currentdir: &Path) -> vec::MoveItems<Matc
             Err(_) => ()
         }
     }
+
+    // crates declared as dependencies in the enclosing Cargo.toml
+    for (cratename, filepath) in cargo::get_dependency_crates(&currentdir.join("Cargo.toml")).into_iter() {
+        if cratename.as_slice().starts_with(searchstr) {
+            out.push(Match {matchstr: cratename.clone(),
+                            filepath: filepath.clone(),
+                            point: 0,
+                            local: false,
+                            accessible: true,
+                            score: 0,
+                            mtype: Module,
+                            contextstr: filepath.as_str().unwrap().to_string(),
+                            generic_args: Vec::new(),
+                            generic_types: Vec::new()
+            });
+        }
+    }
     return out.into_iter();
 }
 
 pub fn search_crate_root(pathseg: &super::PathSegment, modfpath: &Path, 
                          searchtype: SearchType, namespace: Namespace) -> vec::MoveItems<Match> {
     debug!("search_crate_root |{}| {}", pathseg, modfpath.as_str());
+    let span = trace::span(|| format!("search crate root for '{}'", pathseg.name));
 
     let crateroots = find_possible_crate_root_modules(&modfpath.dir_path());
+    if crateroots.is_empty() {
+        trace::note_failure(|| format!("no lib.rs or main.rs above {}", modfpath.display()));
+    }
     let mut out = Vec::new();
     for crateroot in crateroots.iter() {
         if crateroot == modfpath {
+            trace::note(|| format!("{} is the crate root, already searched", crateroot.display()));
             continue;
         }
         debug!("going to search for {} in crateroot {}",pathseg, crateroot.as_str());
+        trace::note(|| format!("chose crate root {}", crateroot.display()));
         for m in resolve_name(pathseg, crateroot, 0, searchtype, namespace) {
             out.push(m);
             if let ExactMatch = searchtype {
@@ -348,6 +656,7 @@ pub fn search_crate_root(pathseg: &super::PathSegment, modfpath: &Path,
         }
         break
     }
+    span.found(out.len());
     return out.into_iter();
 }
 
@@ -356,14 +665,14 @@ pub fn find_possible_crate_root_modules(currentdir: &Path) -> Vec<Path> {
     
     {
         let filepath = currentdir.join_many(&[Path::new("lib.rs")]);
-        if File::open(&filepath).is_ok() {
+        if vfs::exists(&filepath) {
             res.push(filepath);
             return res;   // for now stop at the first match
         }
     }
     {
         let filepath = currentdir.join_many(&[Path::new("main.rs")]);
-        if File::open(&filepath).is_ok() {
+        if vfs::exists(&filepath) {
             res.push(filepath);
             return res;   // for now stop at the first match
         }
@@ -398,8 +707,31 @@ pub fn search_next_scope(mut startpoint: uint, pathseg: &super::PathSegment,
     return search_scope(startpoint, &*filesrc, pathseg, filepath, search_type, local, namespace);
 }
 
-pub fn get_crate_file(name: &str) -> Option<Path> {
-    let srcpaths = std::os::getenv("RUST_SRC_PATH").unwrap();
+/// Finds the root source file of the crate 'name', as referenced from
+/// filepath. Dependencies declared in the enclosing Cargo.toml take
+/// precedence; failing that the crate is looked for in RUST_SRC_PATH
+/// (i.e. std and friends).
+pub fn get_crate_file(name: &str, filepath: &Path) -> Option<Path> {
+    let cratefile = find_crate_file(name, filepath);
+    match cratefile {
+        Some(ref cratefile) =>
+            trace::note(|| format!("crate '{}' is {}", name, cratefile.display())),
+        None =>
+            trace::note_failure(|| format!("couldn't find crate '{}' in Cargo.toml or RUST_SRC_PATH", name))
+    }
+    return cratefile;
+}
+
+fn find_crate_file(name: &str, filepath: &Path) -> Option<Path> {
+    let cratefile = cargo::get_crate_file(name, filepath);
+    if cratefile.is_some() {
+        return cratefile;
+    }
+
+    let srcpaths = match std::os::getenv("RUST_SRC_PATH") {
+        Some(paths) => paths,
+        None => return None
+    };
     let v = srcpaths.as_slice().split_str(PATH_SEP).collect::<Vec<_>>();
     for srcpath in v.into_iter() {
         {
@@ -424,11 +756,164 @@ pub fn get_crate_file(name: &str) -> Option<Path> {
     return None;
 }
 
+/// All the source files making up the crate containing filepath, i.e. the
+/// crate root and every module file reachable from it through 'mod foo;'
+/// declarations
+pub fn get_crate_module_files(filepath: &Path) -> Vec<Path> {
+    let mut out = Vec::new();
+    let crateroot = crate_root_file(filepath);
+    collect_module_files(&crateroot, &mut out);
+    if !out.contains(filepath) {
+        // not reachable from the crate root (e.g. a test or example)
+        collect_module_files(filepath, &mut out);
+    }
+    return out;
+}
+
+fn collect_module_files(filepath: &Path, out: &mut Vec<Path>) {
+    if out.contains(filepath) {
+        return;
+    }
+    out.push(filepath.clone());
+    for (_, modpath) in module_file_decls(filepath).into_iter() {
+        collect_module_files(&modpath, out);
+    }
+}
+
+// The 'mod foo;' decls in the file, and the files they refer to
+fn module_file_decls(filepath: &Path) -> Vec<(uint, Path)> {
+    let mut out = Vec::new();
+    let msrc = super::load_file_and_mask_comments(filepath);
+    let msrc = msrc.as_slice();
+
+    for point in util::find_ident_occurrences(msrc, "mod").into_iter() {
+        let namestart = point + 4;
+        if namestart >= msrc.len() || msrc.char_at(point + 3) != ' ' {
+            continue;
+        }
+        let nameend = find_ident_end(msrc, namestart);
+        if nameend == namestart || !msrc.slice_from(nameend).trim_left().starts_with(";") {
+            continue;    // inline module, the decls inside are found anyway
+        }
+        // same lookup as matchers::match_mod, taking inline parent modules into account
+        let internalpath = scopes::get_local_module_path(msrc, point);
+        let searchdir = filepath.dir_path().join_many(internalpath.as_slice());
+        get_module_file(msrc.slice(namestart, nameend), &searchdir).map(|modpath| {
+            out.push((point, modpath));
+        });
+    }
+    return out;
+}
+
+fn module_match(name: &str, filepath: &Path, point: uint) -> Match {
+    return Match { matchstr: name.to_string(),
+                   filepath: filepath.clone(),
+                   point: point,
+                   local: false,
+                   accessible: true,
+                   score: 0,
+                   mtype: Module,
+                   contextstr: filepath.as_str().unwrap_or("").to_string(),
+                   generic_args: Vec::new(),
+                   generic_types: Vec::new() };
+}
+
+/// The root module file of the crate containing filepath. Files outside of
+/// a crate (e.g. scripts) are their own root.
+pub fn crate_root_file(filepath: &Path) -> Path {
+    return find_possible_crate_root_modules(&filepath.dir_path())
+        .into_iter().next().unwrap_or(filepath.clone());
+}
+
+fn crate_root_module(filepath: &Path) -> Match {
+    return module_match("", &crate_root_file(filepath), 0);
+}
+
+/// The module that pos is in: either an inline 'mod foo {...}' (with the
+/// point of its stmt) or the file itself (point 0)
+pub fn enclosing_module(filepath: &Path, pos: uint) -> Match {
+    let msrc = super::load_file_and_mask_comments(filepath);
+    if let Some(start) = scopes::get_local_module_starts(msrc.as_slice(), pos).pop() {
+        let blob = msrc.slice_from(start);
+        if let Some(n) = blob.find_str("mod ") {
+            let nameend = find_ident_end(blob, n + 4);
+            return module_match(blob.slice(n + 4, nameend), filepath, start);
+        }
+    }
+    // otherwise pos is in the module of the file itself
+    let name = match filepath.filestem_str() {
+        Some("mod") => filepath.dir_path().filename_str().unwrap_or("").to_string(),
+        name => name.unwrap_or("").to_string()
+    };
+    return module_match(name.as_slice(), filepath, 0);
+}
+
+/// The module containing the module m (as returned by enclosing_module).
+/// None for the crate root, or a file that no module in the crate declares.
+pub fn parent_module(m: &Match) -> Option<Match> {
+    if m.point != 0 {
+        return Some(enclosing_module(&m.filepath, m.point));
+    }
+    return find_module_decl(&m.filepath).map(|(f, point)| enclosing_module(&f, point));
+}
+
+/// The file and point of the 'mod foo;' that declares the module in filepath
+pub fn find_module_decl(filepath: &Path) -> Option<(Path, uint)> {
+    for f in get_crate_module_files(filepath).into_iter() {
+        for (point, modpath) in module_file_decls(&f).into_iter() {
+            if modpath == *filepath {
+                return Some((f, point));
+            }
+        }
+    }
+    return None;
+}
+
+/// Paths in use statements are relative to the crate root, unless they
+/// start with 'self' or 'super'
+pub fn to_use_path(mut path: super::Path) -> super::Path {
+    match path.segments.get(0).map(|s| s.name.as_slice()) {
+        Some("self") | Some("super") => {}
+        _ => path.global = true
+    }
+    return path;
+}
+
+// The module that a '::', 'self::' or 'super::' path starts from, and how
+// many of the path's segments that used up. None for other paths.
+fn resolve_path_prefix(path: &super::Path, filepath: &Path, pos: uint) -> Option<(Match, uint)> {
+    if path.global {
+        return Some((crate_root_module(filepath), 0));
+    }
+    // on its own 'self' is a value (e.g. the method receiver)
+    if path.segments.len() < 2 {
+        return None;
+    }
+    if path.segments[0].name.as_slice() == "self" {
+        return Some((enclosing_module(filepath, pos), 1));
+    }
+    let nsupers = path.segments.iter().take_while(|s| s.name.as_slice() == "super").count();
+    if nsupers == 0 {
+        return None;
+    }
+    let mut m = enclosing_module(filepath, pos);
+    for _ in range(0, nsupers) {
+        match parent_module(&m) {
+            Some(parent) => m = parent,
+            None => {
+                trace::note_failure(|| format!("{} has no parent module", m.filepath.display()));
+                return None;
+            }
+        }
+    }
+    return Some((m, nsupers));
+}
+
 pub fn get_module_file(name: &str, parentdir: &Path) -> Option<Path> {
     {            
         // try just <name>.rs
         let filepath = parentdir.join_many(&[Path::new(format!("{}.rs", name))]);
-        if File::open(&filepath).is_ok() {
+        if vfs::exists(&filepath) {
             return Some(filepath);
         }
     }
@@ -436,7 +921,7 @@ pub fn get_module_file(name: &str, parentdir: &Path) -> Option<Path> {
         // try <name>/mod.rs
         let filepath = parentdir.join_many(&[Path::new(name),
                                             Path::new("mod.rs")]);
-        if File::open(&filepath).is_ok() {
+        if vfs::exists(&filepath) {
             return Some(filepath);
         }
     }
@@ -454,37 +939,46 @@ pub fn search_scope(point: uint, src: &str, pathseg: &super::PathSegment,
 
     debug!("searching scope {} {} {} {} {} local: {}",namespace, point, searchstr, 
            filepath.as_str(), search_type, local);
+    let span = trace::span(|| format!("search {} scope at {} in {} for '{}' ({}, {})",
+                                      if local { "local" } else { "module" }, point,
+                                      filepath.display(), searchstr, search_type, namespace));
     
     let scopesrc = src.slice_from(point);
 
-    let mut skip_next_block = false;
+    // src usually has its strings masked, and the cfg attributes and macro
+    // invocations are read from the text of the file instead (unless src is
+    // some other text)
+    let filesrc = filecache::load_file(filepath);
+    let rawsrc = if filesrc.len() == src.len() { filesrc.as_slice() } else { src };
+    let rawscopesrc = rawsrc.slice_from(point);
 
     let mut delayed_use_globs = Vec::new();
 
-    let v = codeiter::iter_stmts(scopesrc).collect::<Vec<_>>();
-    let v = 
-        if point == 0 { 
-            v 
-        } else {
-            // if we're not searching a file from the start, work backwards
-            v.into_iter().rev().collect::<Vec<_>>()
-        };
+    let cached = if point == 0 {
+        // searching the whole file, so can use the cached stmt index if src
+        // is the text it was made from
+        filecache::load_stmts_for_src(filepath, src)
+    } else {
+        None
+    };
+    let v = match cached {
+        Some(stmts) => stmts,
+        None if point == 0 => codeiter::iter_stmts(src).collect::<Vec<_>>(),
+        // if we're not searching a file from the start, work backwards
+        None => codeiter::iter_stmts(scopesrc).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>()
+    };
 
     for (blobstart,blobend) in v.into_iter() { 
-        // sometimes we need to skip blocks of code if the preceeding attribute disables it
-        //  (e.g. #[cfg(test)])
-        if skip_next_block {
-            skip_next_block = false;
-            continue;
-        }
-
         let blob = scopesrc.slice(blobstart,blobend);
 
-        // for now skip stuff that's meant for testing. Often the test
-        // module hierarchy is incompatible with the non-test
-        // hierarchy and we get into recursive loops
-        if blob.starts_with("#[cfg(test)") {
-            skip_next_block = true;
+        // skip attributes, and items that the attributes in front of them
+        // leave out of the build (e.g. #[cfg(windows)] on unix)
+        if blob.starts_with("#") {
+            continue;
+        }
+        if !cfg::is_item_enabled(rawscopesrc, scopesrc, blobstart) {
+            trace::note(|| format!("skipped the item at {} in {}, disabled by cfg",
+                                   point+blobstart, filepath.display()));
             continue;
         }
 
@@ -497,6 +991,26 @@ pub fn search_scope(point: uint, src: &str, pathseg: &super::PathSegment,
             continue;
         }
 
+        // items generated by a macro invocation live in an expanded copy of
+        // the file. The names needn't appear in the invocation, so this has
+        // to come before the fail fast check. Only invocations of macros in
+        // the crate's index of macro_rules! are expanded.
+        if let Some((expfile, expsrc, expstart, expend)) =
+            macros::expand_item_invocation(rawsrc, point+blobstart, point+blobend, filepath) {
+            for (s, e) in codeiter::iter_stmts(expsrc.as_slice().slice(expstart, expend)) {
+                out = out + run_matchers_on_blob(expsrc.as_slice(), expstart+s, expstart+e,
+                                                 searchstr, &expfile, search_type,
+                                                 local, namespace).as_slice();
+            }
+            if let ExactMatch = search_type {
+                if !out.is_empty() {
+                    span.found(out.len());
+                    return out.into_iter();
+                }
+            }
+            continue;
+        }
+
         // Optimisation: if the search string is not in the blob and it is not 
         // a 'use glob', this cannot match so fail fast!
         if blob.find_str(searchstr).is_none() {
@@ -510,6 +1024,7 @@ pub fn search_scope(point: uint, src: &str, pathseg: &super::PathSegment,
                                       filepath, search_type, local, namespace).as_slice();
         if let ExactMatch = search_type {
             if !out.is_empty() {
+                span.found(out.len());
                 return out.into_iter();
             }
         }
@@ -523,12 +1038,14 @@ pub fn search_scope(point: uint, src: &str, pathseg: &super::PathSegment,
                                       local, namespace).into_iter() {
             out.push(m);
             if let ExactMatch = search_type {
+                span.found(out.len());
                 return out.into_iter();
             }
         }
     }
 
     debug!("search_scope found matches {} {}",search_type, out);
+    span.found(out.len());
     return out.into_iter();
 }
 
@@ -595,6 +1112,22 @@ fn search_local_scopes(pathseg: &super::PathSegment, filepath: &Path, msrc: &str
         // search each parent scope in turn
         while point > 0 {
             let start = scopes::scope_start(msrc, point);
+            // closure parameters shadow the bindings in the rest of the scope
+            for m in search_closure_args(start, point, msrc, pathseg.name.as_slice(),
+                                         filepath, search_type).into_iter() {
+                out.push(m);
+                if let ExactMatch = search_type {
+                    return out.into_iter();
+                }
+            }
+            // as do the bindings of the match arm point is in
+            for m in search_match_arm(start, point, msrc, pathseg.name.as_slice(),
+                                      filepath, search_type).into_iter() {
+                out.push(m);
+                if let ExactMatch = search_type {
+                    return out.into_iter();
+                }
+            }
             for m in search_scope(start, msrc, pathseg, filepath, search_type, is_local, namespace) {
                 out.push(m);
                 if let ExactMatch = search_type {
@@ -618,17 +1151,44 @@ fn search_local_scopes(pathseg: &super::PathSegment, filepath: &Path, msrc: &str
 
 }
 
+// The crate roots of the std crates that implement methods for the builtin
+// type, from RUST_SRC_PATH
+fn builtin_impl_files(name: &str) -> Vec<Path> {
+    let crates = match name {
+        "str" => vec!["libcore", "libcollections", "libunicode"],
+        "char" => vec!["libcore", "libunicode"],
+        "[T]" => vec!["libcore", "libcollections"],
+        _ => vec!["libcore", "libstd"]    // numbers and bool
+    };
+
+    let mut out = Vec::new();
+    let srcpaths = std::os::getenv("RUST_SRC_PATH").unwrap_or("".to_string());
+    for srcpath in srcpaths.as_slice().split_str(PATH_SEP).filter(|p| !p.is_empty()) {
+        for krate in crates.iter() {
+            let filepath = Path::new(srcpath).join_many(&[Path::new(*krate),
+                                                          Path::new("lib.rs")]);
+            if File::open(&filepath).is_ok() {
+                out.push(filepath);
+            }
+        }
+    }
+    return out;
+}
+
 pub fn search_prelude_file(pathseg: &super::PathSegment, search_type: SearchType, 
                            namespace: Namespace) -> vec::MoveItems<Match> {
     debug!("search_prelude file {} {} {}", pathseg, search_type, namespace);
-    debug!("PHIL {}",util::get_backtrace());
+    let span = trace::span(|| format!("search the prelude for '{}'", pathseg.name));
 
     let mut out : Vec<Match> = Vec::new();
 
     // find the prelude file from the search path and scan it
     let srcpaths = match std::os::getenv("RUST_SRC_PATH") { 
         Some(paths) => paths,
-        None => return out.into_iter()
+        None => {
+            span.fail("RUST_SRC_PATH is not set".to_string());
+            return out.into_iter();
+        }
     };
 
     let v = srcpaths.as_slice().split_str(PATH_SEP).collect::<Vec<_>>();
@@ -644,6 +1204,7 @@ pub fn search_prelude_file(pathseg: &super::PathSegment, search_type: SearchType
             }
         }
     }
+    span.found(out.len());
     return out.into_iter();
 }
 
@@ -666,6 +1227,8 @@ pub fn resolve_path_with_str(path: &super::Path, filepath: &Path, pos: uint,
                            filepath: str_match.filepath.clone(), 
                            point: str_match.point,
                            local: false,
+                           accessible: true,
+                           score: 0,
                            mtype: Struct,
                            contextstr: "str".to_string(),
                            generic_args: Vec::new(), 
@@ -674,7 +1237,7 @@ pub fn resolve_path_with_str(path: &super::Path, filepath: &Path, pos: uint,
             out.push(m);
         });
     } else {
-        for m in resolve_path(path, filepath, pos, search_type, namespace) {
+        for m in resolve_path_(path, filepath, pos, search_type, namespace) {
             out.push(m);
             if let ExactMatch = search_type {
                 break;
@@ -713,6 +1276,8 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
     let searchstr = pathseg.name.as_slice();
     
     debug!("resolve_name {} {} {} {} {}",searchstr, filepath.as_str(), pos, search_type, namespace);
+    let span = trace::span(|| format!("resolve name '{}' at {} in {} ({}, {})",
+                                      searchstr, pos, filepath.display(), search_type, namespace));
 
     let msrc = super::load_file_and_mask_comments(filepath);
 
@@ -720,11 +1285,13 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
     let is_exact_match = match search_type { ExactMatch => true, StartsWith => false };
 
     if (is_exact_match && searchstr == "std") || (!is_exact_match && "std".starts_with(searchstr)) {
-        get_crate_file("std").map(|cratepath|{
+        get_crate_file("std", filepath).map(|cratepath|{
             out.push(Match { matchstr: "std".to_string(),
                         filepath: cratepath.clone(), 
                         point: 0,
                         local: false,
+                        accessible: true,
+                        score: 0,
                         mtype: Module,
                         contextstr: cratepath.as_str().unwrap().to_string(),
                         generic_args: Vec::new(), generic_types: Vec::new()
@@ -733,6 +1300,7 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
         
         if let ExactMatch = search_type {
             if !out.is_empty() {
+                span.found(out.len());
                 return out.into_iter();
             }
         }
@@ -744,6 +1312,7 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
         out.push(m);
         if let ExactMatch = search_type {
             if !out.is_empty() {
+                span.found(out.len());
                 return out.into_iter();
             }
         }
@@ -753,6 +1322,7 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
         out.push(m);
         if let ExactMatch = search_type {
             if !out.is_empty() {
+                span.found(out.len());
                 return out.into_iter();
             }
         }        
@@ -762,6 +1332,7 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
         out.push(m);
         if let ExactMatch = search_type {
             if !out.is_empty() {
+                span.found(out.len());
                 return out.into_iter();
             }
         }        
@@ -773,22 +1344,103 @@ pub fn resolve_name(pathseg: &super::PathSegment, filepath: &Path, pos: uint,
         }
     }
 
+    span.found(out.len());
     return out.into_iter();
 }
 
-pub fn resolve_path(path: &super::Path, filepath: &Path, pos: uint, 
+// Resolving a path can lead to resolving others (e.g. following 'use'
+// statements and globs), and cycles between those can recurse without end.
+// The depth is kept per query (see query.rs) rather than per task because
+// the parser visitors (see ast.rs) call back into the resolver from their
+// own tasks.
+pub const RECURSION_LIMIT: uint = 50;
+
+struct ResolveDepthGuard {
+    state: Arc<QueryState>
+}
+
+impl Drop for ResolveDepthGuard {
+    fn drop(&mut self) {
+        self.state.resolve_depth.fetch_sub(1, SeqCst);
+    }
+}
+
+/// Clears the flag set when a search of this query is cut short by the
+/// recursion limit
+pub fn reset_recursion_limit() {
+    query::state().recursion_limit_hit.store(false, SeqCst);
+}
+
+/// True if a search of this query since the last reset_recursion_limit()
+/// was cut short
+pub fn recursion_limit_hit() -> bool {
+    query::state().recursion_limit_hit.load(SeqCst)
+}
+
+/// Resolves the path to the items it refers to
+pub fn resolve_path(path: &super::Path, filepath: &Path, pos: uint,
+                    search_type: SearchType, namespace: Namespace) -> RacerResult<vec::MoveItems<Match>> {
+    reset_recursion_limit();
+    let out = resolve_path_(path, filepath, pos, search_type, namespace).collect::<Vec<_>>();
+    if !out.is_empty() {
+        return Ok(out.into_iter());
+    }
+    if recursion_limit_hit() {
+        return Err(RacerError::RecursionLimit(RECURSION_LIMIT));
+    }
+    return Err(RacerError::UnresolvedPath(path_to_string(path)));
+}
+
+// e.g. foo::bar, without any generic types
+fn path_to_string(path: &super::Path) -> String {
+    path.segments.iter().map(|s| s.name.as_slice()).collect::<Vec<_>>().connect("::")
+}
+
+pub fn resolve_path_(path: &super::Path, filepath: &Path, pos: uint, 
                   search_type: SearchType, namespace: Namespace) -> vec::MoveItems<Match> {
     debug!("resolve_path {} {} {} {}", path, filepath.as_str(), pos, search_type);
+
+    let state = query::state();
+    let depth = state.resolve_depth.fetch_add(1, SeqCst) + 1;
+    let _guard = ResolveDepthGuard { state: state.clone() };
+    if depth > RECURSION_LIMIT {
+        debug!("resolve_path hit the recursion limit ({}) resolving {}", RECURSION_LIMIT, path);
+        state.recursion_limit_hit.store(true, SeqCst);
+        trace::note_failure(|| format!("hit the recursion limit ({}) resolving {}",
+                                       RECURSION_LIMIT, path_to_string(path)));
+        return Vec::new().into_iter();
+    }
+
+    if let Some((modm, nprefix)) = resolve_path_prefix(path, filepath, pos) {
+        trace::note(|| format!("'{}' starts from module {} in {}", path_to_string(path),
+                               modm.matchstr, modm.filepath.display()));
+        let rest = path.segments.slice_from(nprefix);
+        match rest.len() {
+            0 => return vec![modm].into_iter(),
+            1 if path.global => {
+                // the crate root, including the extern crates and prelude
+                return resolve_name(&rest[0], &modm.filepath, 0, search_type, namespace);
+            }
+            1 => return search_next_scope(modm.point, &rest[0], &modm.filepath,
+                                          search_type, false, namespace),
+            _ => {}   // the parent path is resolved from the module below
+        }
+    }
+
     let len = path.segments.len();
     if len == 1 {
         let ref pathseg = path.segments[0];
         return resolve_name(pathseg, filepath, pos, search_type, namespace);
     } else {
+        let span = trace::span(|| format!("resolve path '{}' at {} in {}",
+                                          path_to_string(path), pos, filepath.display()));
         let mut out = Vec::new();
         let mut parent_path: super::Path = path.clone();
         parent_path.segments.remove(len-1);
-        let context = resolve_path(&parent_path, filepath, pos, ExactMatch, TypeNamespace).nth(0);
+        let context = resolve_path_(&parent_path, filepath, pos, ExactMatch, TypeNamespace).nth(0);
         context.map(|m| {
+            trace::note(|| format!("'{}' is {} {} at {} in {}", path_to_string(&parent_path),
+                                   m.mtype, m.matchstr, m.point, m.filepath.display()));
             match m.mtype {
                 Module => {
                     debug!("searching a module '{}' (whole path: {})",m.matchstr, path);
@@ -800,19 +1452,9 @@ pub fn resolve_path(path: &super::Path, filepath: &Path, pos: uint,
                 Enum => {
                     let ref pathseg = path.segments[len-1];
                     debug!("searching an enum '{}' (whole path: {}) searchtype: {}",m.matchstr, path, search_type);
-
-                    let filesrc = super::load_file(&m.filepath);
-                    let scopestart = scopes::find_stmt_start(&*filesrc, m.point).unwrap();
-                    let scopesrc = filesrc.slice_from(scopestart);
-                    codeiter::iter_stmts(scopesrc).nth(0).map(|(blobstart,blobend)|{
-                        for m in matchers::match_enum_variants(&*filesrc, 
-                                                               scopestart+blobstart,
-                                                               scopestart+ blobend,
-                                                      &*pathseg.name, &m.filepath, search_type, true) {
-                            debug!("Found enum variant: {}", m.matchstr);
-                            out.push(m);
-                        }
-                    });
+                    for m in search_enum_variants(pathseg.name.as_slice(), &m, search_type) {
+                        out.push(m);
+                    }
                 }
                 Struct => {
                     debug!("found a struct. Now need to look for impl");
@@ -834,6 +1476,7 @@ pub fn resolve_path(path: &super::Path, filepath: &Path, pos: uint,
             }
         });
         debug!("resolve_path returning {}",out);
+        span.found(out.len());
         return out.into_iter();
     }
 }
@@ -856,6 +1499,8 @@ pub fn do_external_search(path: &[&str], filepath: &Path, pos: uint, search_type
                            filepath: path.clone(), 
                            point: 0,
                            local: false,
+                           accessible: true,
+                           score: 0,
                            mtype: Module,
                            contextstr: path.as_str().unwrap().to_string(),
                            generic_args: Vec::new(),
@@ -898,6 +1543,21 @@ pub fn do_external_search(path: &[&str], filepath: &Path, pos: uint, search_type
     return out.into_iter();
 }
 
+/// Searches for the field or method on the context type and then on the
+/// types it derefs to. Earlier matches shadow later ones of the same name
+pub fn search_for_field_or_method_with_derefs(context: Match, searchstr: &str,
+                                              search_type: SearchType) -> vec::MoveItems<Match> {
+    let mut out: Vec<Match> = Vec::new();
+    for m in typeinf::deref_chain(context).into_iter() {
+        for m in search_for_field_or_method(m, searchstr, search_type) {
+            if !out.iter().any(|o| o.matchstr == m.matchstr) {
+                out.push(m);
+            }
+        }
+    }
+    return out.into_iter();
+}
+
 pub fn search_for_field_or_method(context: Match, searchstr: &str, search_type: SearchType) -> vec::MoveItems<Match> {
     let m = context;
     let mut out = Vec::new();
@@ -932,12 +1592,39 @@ pub fn search_for_field_or_method(context: Match, searchstr: &str, search_type:
             let src = super::load_file(&m.filepath);
             src.slice_from(m.point).find_str("{").map(|n|{
                 let point = m.point + n + 1;
-                for m in search_scope_for_methods(point, &*src, searchstr, &m.filepath, search_type) {
+                for m in search_scope_for_methods(point, &*src, searchstr, &m.filepath,
+                                                  search_type, true) {
                     out.push(m);
                 }
             });
         }
-        _ => { debug!("WARN!! context wasn't a Struct, Enum or Trait {}",m);}
+        TypeParameter => {
+            debug!("got a type parameter, looking for methods of its bounds {}",m.matchstr);
+            // the union of the methods of the traits it is bound by
+            for bound in m.generic_types.iter() {
+                let t = resolve_path_(&bound.path, &bound.filepath, bound.point,
+                                      ExactMatch, TypeNamespace).find(|t| t.mtype == Trait);
+                if let Some(t) = t {
+                    for m in search_for_field_or_method(t, searchstr, search_type) {
+                        if !out.iter().any(|o: &Match| o.matchstr == m.matchstr) {
+                            out.push(m);
+                        }
+                    }
+                }
+            }
+        }
+        Builtin => {
+            debug!("got a builtin type, looking for impl methods in std {}",m.matchstr);
+            for file in builtin_impl_files(m.matchstr.as_slice()).iter() {
+                for m in search_for_impl_methods(m.matchstr.as_slice(), searchstr, 0, file,
+                                                 false, search_type) {
+                    if !out.iter().any(|o: &Match| o.matchstr == m.matchstr) {
+                        out.push(m);
+                    }
+                }
+            }
+        }
+        _ => { debug!("WARN!! context has no fields or methods {}",m);}
     };
     return out.into_iter();
 }
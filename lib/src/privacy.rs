@@ -0,0 +1,189 @@
+// Privacy checks. An item can be used from the module it is declared in
+// (and that module's descendants), and if it is 'pub' also from wherever
+// its module can be used. Trait items, trait impl methods and enum variants
+// are as visible as the trait or enum.
+//
+// Items are found through the module tree, so a pub item in a private module
+// that is re-exported with 'pub use' would look inaccessible. As an
+// approximation, a private module's pub items are treated as accessible if
+// a 'pub use' next to the module's declaration mentions them.
+
+use std::collections::HashMap;
+use std::iter::range;
+use super::{Match, MatchType};
+use super::MatchType::{Struct, Module, Function, StructField, Enum, EnumVariant, Type,
+                       Trait, Const, Static};
+use super::SearchType::ExactMatch;
+use super::{nameres, scopes, util, filecache, macros};
+
+// enough for any sane module tree, and stops cycles from misdeclared modules
+const MAX_MODULE_DEPTH: uint = 32;
+
+/// Works out which matches can be used from a given point. Module lookups
+/// are cached, since the matches of a query tend to share modules.
+pub struct AccessChecker {
+    ancestors: Vec<Match>,   // the module of the point, and the modules containing it
+    parents: HashMap<(Path, uint), Option<Match>>
+}
+
+impl AccessChecker {
+    pub fn new(filepath: &Path, pos: uint) -> AccessChecker {
+        let mut checker = AccessChecker{ ancestors: Vec::new(), parents: HashMap::new() };
+        let mut m = Some(nameres::enclosing_module(filepath, pos));
+        while let Some(module) = m {
+            if checker.ancestors.len() == MAX_MODULE_DEPTH {
+                break;
+            }
+            m = checker.parent_module(&module);
+            checker.ancestors.push(module);
+        }
+        return checker;
+    }
+
+    /// True if the item in m can be used from the checker's point
+    pub fn is_accessible(&mut self, m: &Match) -> bool {
+        if !has_visibility(m.mtype) {
+            return true;
+        }
+        let (mut ispub, mut parent) = self.item_visibility(m);
+        for _ in range(0, MAX_MODULE_DEPTH) {
+            if self.is_ancestor(&parent) {
+                return true;
+            }
+            if !ispub {
+                return false;
+            }
+            // a pub item is as visible as the module it is in
+            let grandparent = match self.parent_module(&parent) {
+                Some(grandparent) => grandparent,
+                None => return true    // the root of another crate
+            };
+            ispub = is_module_pub(&parent) || is_reexported(m, &parent);
+            parent = grandparent;
+        }
+        return true;
+    }
+
+    // Items generated by macros are found in expanded copies of files (see
+    // macros.rs), which are the same modules as the originals
+    fn is_ancestor(&self, module: &Match) -> bool {
+        let filepath = macros::unexpanded_file_path(&module.filepath);
+        return self.ancestors.iter().any(|a| {
+            macros::unexpanded_file_path(&a.filepath) == filepath && a.point == module.point
+        });
+    }
+
+    fn parent_module(&mut self, module: &Match) -> Option<Match> {
+        let key = (module.filepath.clone(), module.point);
+        if let Some(parent) = self.parents.get(&key) {
+            return parent.clone();
+        }
+        let mut unexpanded = module.clone();
+        if module.point == 0 {
+            unexpanded.filepath = macros::unexpanded_file_path(&module.filepath);
+        }
+        let parent = nameres::parent_module(&unexpanded);
+        self.parents.insert(key, parent.clone());
+        return parent;
+    }
+
+    // Whether the item is declared pub (or is implicitly), and the module it
+    // is declared in
+    fn item_visibility(&self, m: &Match) -> (bool, Match) {
+        if m.mtype == Module && m.point == 0 {
+            // a 'mod foo;' file module
+            return match nameres::find_module_decl(&m.filepath) {
+                Some((declfile, declpoint)) => {
+                    let msrc = super::load_file_and_mask_comments(&declfile);
+                    (is_declared_pub(msrc.as_slice(), declpoint),
+                     nameres::enclosing_module(&declfile, declpoint))
+                }
+                // a crate root
+                None => (true, nameres::enclosing_module(&m.filepath, 0))
+            };
+        }
+
+        let msrc = super::load_file_and_mask_comments(&m.filepath);
+        let msrc = msrc.as_slice();
+        let parent = nameres::enclosing_module(&m.filepath, m.point);
+        let ispub = match m.mtype {
+            // variants are as visible as the enum
+            EnumVariant => is_declared_pub(msrc, scopes::scope_start(msrc, m.point) - 1),
+            Function if is_in_trait_or_trait_impl(msrc, m.point) => true,
+            _ => is_declared_pub(msrc, m.point)
+        };
+        return (ispub, parent);
+    }
+}
+
+fn has_visibility(mtype: MatchType) -> bool {
+    return match mtype {
+        Struct | Module | Function | StructField | Enum | EnumVariant |
+        Type | Trait | Const | Static => true,
+        _ => false
+    };
+}
+
+// Looks back from the name of an item to the end of whatever came before its
+// declaration for a 'pub', e.g. 'pub fn' or '#[inline] pub fn' or 'pub x: T'
+fn is_declared_pub(msrc: &str, point: uint) -> bool {
+    let before = msrc.slice_to(point);
+    let declstart = before.rfind(|c: char| c == ';' || c == '{' || c == '}' || c == ',')
+        .map_or(0, |n| n + 1);
+    return util::txt_matches(ExactMatch, "pub", before.slice_from(declstart));
+}
+
+fn is_in_trait_or_trait_impl(msrc: &str, point: uint) -> bool {
+    let bodystart = scopes::scope_start(msrc, point);
+    if bodystart == 0 {
+        return false;
+    }
+    let before = msrc.slice_to(bodystart - 1);
+    let declstart = before.rfind(|c: char| c == ';' || c == '{' || c == '}')
+        .map_or(0, |n| n + 1);
+    let decl = before.slice_from(declstart).trim_left();
+    return util::txt_matches(ExactMatch, "trait", decl) ||
+        (decl.starts_with("impl") && util::txt_matches(ExactMatch, "for", decl));
+}
+
+fn is_module_pub(module: &Match) -> bool {
+    if module.point != 0 {
+        // an inline module, point is the start of its stmt
+        let msrc = super::load_file_and_mask_comments(&module.filepath);
+        return msrc.as_slice().slice_from(module.point).starts_with("pub ");
+    }
+    return match nameres::find_module_decl(&module.filepath) {
+        Some((declfile, declpoint)) => {
+            let msrc = super::load_file_and_mask_comments(&declfile);
+            is_declared_pub(msrc.as_slice(), declpoint)
+        }
+        None => true
+    };
+}
+
+// Is there a 'pub use' in the file that declares the module that mentions m?
+fn is_reexported(m: &Match, module: &Match) -> bool {
+    let declfile = if module.point != 0 {
+        module.filepath.clone()
+    } else {
+        match nameres::find_module_decl(&module.filepath) {
+            Some((declfile, _)) => declfile,
+            None => return false
+        }
+    };
+    let msrc = super::load_file_and_mask_comments(&declfile);
+    return filecache::load_stmts(&declfile).into_iter().any(|(start, end)| {
+        let blob = msrc.as_slice().slice(start, end);
+        blob.starts_with("pub use ") && util::txt_matches(ExactMatch, m.matchstr.as_slice(), blob)
+    });
+}
+
+#[test]
+fn finds_pub_declarations() {
+    let src = "pub struct Foo { pub a: uint, b: uint }\n#[inline]\npub fn foo() {}\nfn bar() {}";
+    assert!(is_declared_pub(src, src.find_str("Foo").unwrap()));
+    assert!(is_declared_pub(src, src.find_str("a:").unwrap()));
+    assert!(!is_declared_pub(src, src.find_str("b:").unwrap()));
+    assert!(is_declared_pub(src, src.find_str("foo").unwrap()));
+    assert!(!is_declared_pub(src, src.find_str("bar").unwrap()));
+}
@@ -0,0 +1,104 @@
+// State that belongs to a single query, e.g. how deep the resolver has
+// recursed (see nameres.rs), the cfg options it is made with and whether it
+// was made in test code (see cfg.rs) or where its steps are recorded (see
+// trace.rs).
+//
+// Every query runs in its own task (see run_query in lib.rs), so the state
+// is kept task local and concurrent queries (the requests of a daemon, or
+// tests running in parallel) don't see each other's. The parser visitors
+// (see ast.rs) run in tasks of their own and call back into the resolver,
+// so those are started with try_in_task, which hands them the state of the
+// query that started them. A new query takes the cfg options and recorder
+// of the task that runs it.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUint, AtomicBool};
+use std::task;
+use super::cfg::Config;
+use super::trace::Recorder;
+
+pub struct QueryState {
+    pub resolve_depth: AtomicUint,
+    pub recursion_limit_hit: AtomicBool,
+    pub cursor_in_test: AtomicBool,
+    pub config: Mutex<Config>,
+    pub recorder: Option<Arc<Mutex<Recorder>>>
+}
+
+impl QueryState {
+    fn new(config: Config, recorder: Option<Arc<Mutex<Recorder>>>) -> QueryState {
+        QueryState {
+            resolve_depth: AtomicUint::new(0),
+            recursion_limit_hit: AtomicBool::new(false),
+            cursor_in_test: AtomicBool::new(false),
+            config: Mutex::new(config),
+            recorder: recorder
+        }
+    }
+}
+
+thread_local!(static STATE: RefCell<Option<Arc<QueryState>>> = RefCell::new(None))
+
+/// The state of the query this task is part of. A task that isn't running
+/// a query (e.g. one calling the resolver directly) gets a state of its own,
+/// with the host configuration.
+pub fn state() -> Arc<QueryState> {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.is_none() {
+            *s = Some(Arc::new(QueryState::new(Config::host(), None)));
+        }
+        s.as_ref().unwrap().clone()
+    })
+}
+
+fn set_state(state: Arc<QueryState>) -> Option<Arc<QueryState>> {
+    STATE.with(|s| mem::replace(&mut *s.borrow_mut(), Some(state)))
+}
+
+/// Runs f as a new query, in a task of its own with fresh state. The query
+/// uses the cfg options set in this task (see cfg::set_config), and a query
+/// run while recording (see with_recorder) is recorded too.
+pub fn run<T: Send, F: FnOnce() -> T + Send>(f: F) -> Result<T, Box<Any + Send>> {
+    let current = state();
+    let (config, recorder) = (current.config.lock().clone(), current.recorder.clone());
+    task::try(move || {
+        set_state(Arc::new(QueryState::new(config, recorder)));
+        f()
+    })
+}
+
+/// Runs f with its steps, and those of the queries it runs, recorded in
+/// recorder (see trace::record)
+pub fn with_recorder<T, F: FnOnce() -> T>(recorder: Arc<Mutex<Recorder>>, f: F) -> T {
+    let config = state().config.lock().clone();
+    let prev = set_state(Arc::new(QueryState::new(config, Some(recorder))));
+    let res = f();
+    STATE.with(|s| *s.borrow_mut() = prev);
+    return res;
+}
+
+/// Runs f in a new task that is part of the current query, e.g. so that a
+/// panic in the parser doesn't take the query down with it
+pub fn try_in_task<T: Send, F: FnOnce() -> T + Send>(f: F) -> Result<T, Box<Any + Send>> {
+    let state = state();
+    task::try(move || {
+        set_state(state);
+        f()
+    })
+}
+
+#[test]
+fn queries_get_their_own_state() {
+    use std::sync::atomic::SeqCst;
+    state().resolve_depth.store(3, SeqCst);
+    assert_eq!(3, try_in_task(|| state().resolve_depth.load(SeqCst)).unwrap());
+    assert_eq!(0, run(|| state().resolve_depth.load(SeqCst)).unwrap());
+
+    state().config.lock().test = true;
+    assert!(run(|| state().config.lock().test).unwrap());
+    assert!(!task::try(|| state().config.lock().test).unwrap());
+}
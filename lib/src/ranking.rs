@@ -0,0 +1,282 @@
+// Ranking of completions. complete_from_file finds matches in the order it
+// searches (local scopes, the crate root, the prelude, ...), and often finds
+// the same item along more than one route. This drops the duplicates and
+// sorts what is left by a score made of:
+//
+//  - how close the item is: local, same file, same crate or further away
+//  - how the name matches: exact case prefix, camel case humps (e.g. 'HaMa'
+//    for 'HashMap'), or the whole name
+//  - whether the kind of item fits the context: a type after ':', a value
+//    in an expression
+//  - how recently the item was chosen (see record_choice)
+//
+// Like vfs, the record of chosen items is global, so that it survives
+// between the queries of a daemon.
+
+use std::ascii::AsciiExt;
+use std::mem;
+use std::sync::{Mutex, Once, ONCE_INIT};
+use super::Match;
+use super::MatchType::{Struct, Enum, Type, Trait, TypeParameter, Builtin, Function, Let, FnArg,
+                       ClosureArg, MatchArm, Const, Static, EnumVariant, StructField};
+use super::{macros, nameres, scopes, util};
+
+// how many chosen items are remembered
+const MAX_RECENT: uint = 20;
+
+/// The kind of item that fits where a completion is made
+#[deriving(Show, PartialEq)]
+pub enum Expected {
+    TypeExpected,
+    ValueExpected,
+    AnyExpected
+}
+
+impl Copy for Expected {}
+
+/// What a completion was made for
+pub struct Query<'a> {
+    pub filepath: &'a Path,
+    pub searchstr: &'a str,
+    pub expected: Expected,
+    pub preferred: Vec<Match>    // e.g. the variants of the enum being matched on
+}
+
+static mut RECENT: *const Mutex<Vec<(Path, uint)>> = 0 as *const Mutex<Vec<(Path, uint)>>;
+static INIT: Once = ONCE_INIT;
+
+// the chosen items, most recent first
+fn recent() -> &'static Mutex<Vec<(Path, uint)>> {
+    unsafe {
+        INIT.doit(|| {
+            let m: Box<Mutex<Vec<(Path, uint)>>> = box Mutex::new(Vec::new());
+            RECENT = mem::transmute(m);
+        });
+        return &*RECENT;
+    }
+}
+
+/// Records that the completion defined at point in filepath was chosen, so
+/// that it ranks higher next time
+pub fn record_choice(filepath: &Path, point: uint) {
+    let mut recent = recent().lock();
+    recent.retain(|&(ref f, p)| !(*f == *filepath && p == point));
+    recent.insert(0, (filepath.clone(), point));
+    recent.truncate(MAX_RECENT);
+}
+
+/// The kind of item expected at start: a type after ':' or '->' (e.g. 'let a: Fo'),
+/// a value after '=', '(' or an operator (e.g. 'let a = fo') or after the
+/// ':' of a field in a struct literal (e.g. 'Foo { a: fo')
+pub fn expected_kind(msrc: &str, start: uint) -> Expected {
+    let before = msrc.slice_to(start).trim_right();
+    let word = last_word(before);
+    if is_struct_field_value(msrc, before) {
+        return ValueExpected;
+    }
+    if before.ends_with("->") || (before.ends_with(":") && !before.ends_with("::")) ||
+        word == "as" {
+        return TypeExpected;
+    }
+    if word == "return" {
+        return ValueExpected;
+    }
+    return match before.chars().rev().next() {
+        Some('=') | Some('(') | Some('[') | Some('!') | Some('+') | Some('-') |
+        Some('/') | Some('%') => ValueExpected,
+        _ => AnyExpected
+    };
+}
+
+// Whether before (the text up to a completion) ends with the 'name:' of a
+// field in a struct literal or pattern
+fn is_struct_field_value(msrc: &str, before: &str) -> bool {
+    if !before.ends_with(":") || before.ends_with("::") {
+        return false;
+    }
+    let field = before.slice_to(before.len() - 1).trim_right();
+    let name = last_word(field);
+    return !name.is_empty() &&
+        scopes::get_struct_field_context(msrc, field.len() - name.len()).is_some();
+}
+
+fn last_word(s: &str) -> &str {
+    return s.slice_from(s.rfind(|c: char| !util::is_ident_char(c)).map_or(0, |n| n + 1));
+}
+
+/// Drops duplicate matches (the same item found along different routes),
+/// scores the rest and sorts them best first. Equal scores keep the order
+/// they were found in.
+pub fn rank(matches: Vec<Match>, query: &Query) -> Vec<Match> {
+    let crate_files = nameres::get_crate_module_files(query.filepath);
+    let recent = recent().lock().clone();
+
+    let mut out: Vec<Match> = Vec::new();
+    for mut m in matches.into_iter() {
+        if out.iter().any(|o| o.filepath == m.filepath && o.point == m.point) {
+            continue;
+        }
+        m.score = score(&m, query, crate_files.as_slice(), recent.as_slice());
+        out.push(m);
+    }
+    // sort_by is stable
+    out.sort_by(|a, b| b.score.cmp(&a.score));
+    return out;
+}
+
+fn score(m: &Match, query: &Query, crate_files: &[Path], recent: &[(Path, uint)]) -> int {
+    let mut score = 0i;
+
+    // items generated by macros are in expanded copies of files, see macros.rs
+    let filepath = macros::unexpanded_file_path(&m.filepath);
+    score += if filepath == *query.filepath {
+        if m.local { 40 } else { 30 }
+    } else if crate_files.contains(&filepath) {
+        20
+    } else {
+        0
+    };
+
+    score += name_score(query.searchstr, m.matchstr.as_slice());
+
+    let fits = match (query.expected, m.mtype) {
+        (TypeExpected, Struct) | (TypeExpected, Enum) | (TypeExpected, Type) |
+        (TypeExpected, Trait) | (TypeExpected, TypeParameter) | (TypeExpected, Builtin) => true,
+        (ValueExpected, Function) | (ValueExpected, Let) | (ValueExpected, FnArg) |
+        (ValueExpected, ClosureArg) | (ValueExpected, MatchArm) | (ValueExpected, Const) |
+        (ValueExpected, Static) | (ValueExpected, EnumVariant) |
+        (ValueExpected, StructField) => true,
+        _ => false
+    };
+    if fits {
+        score += 20;
+    }
+
+    if query.preferred.iter().any(|p| p.filepath == m.filepath && p.point == m.point) {
+        score += 50;
+    }
+
+    // up to 100 for the item chosen last
+    recent.iter().position(|&(ref f, p)| *f == m.filepath && p == m.point).map(|i| {
+        score += (MAX_RECENT - i) as int * 5;
+    });
+    return score;
+}
+
+fn name_score(searchstr: &str, name: &str) -> int {
+    if searchstr.is_empty() {
+        return 0;
+    }
+    let mut score = 0i;
+    if name == searchstr {
+        score += 10;
+    }
+    if name.starts_with(searchstr) {
+        score += 30;
+    } else if name.to_ascii_lower().starts_with(searchstr.to_ascii_lower().as_slice()) {
+        score += 15;
+    }
+    if is_hump_match(searchstr, name) {
+        score += 20;
+    }
+    return score;
+}
+
+// The words of a name, split at '_' and where lower case turns to upper,
+// e.g. ["Hash", "Map"] or ["get", "mut"]
+fn humps(name: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0u;
+    let mut prev_lower = false;
+    for (i, c) in name.char_indices() {
+        if c == '_' {
+            if i > start {
+                out.push(name.slice(start, i));
+            }
+            start = i + 1;
+        } else if c.is_uppercase() && prev_lower && i > start {
+            out.push(name.slice(start, i));
+            start = i;
+        }
+        prev_lower = c.is_lowercase();
+    }
+    if start < name.len() {
+        out.push(name.slice_from(start));
+    }
+    return out;
+}
+
+// Does the abbreviation take the start of two or more humps of the name in
+// order, starting with the first? e.g. 'HM' or 'HaMa' for 'HashMap'
+fn is_hump_match(abbrev: &str, name: &str) -> bool {
+    let humps = humps(name);
+    return humps.len() > 1 && match_humps(abbrev.to_ascii_lower().as_slice(),
+                                          humps.as_slice(), 0);
+}
+
+fn match_humps(abbrev: &str, humps: &[&str], matched: uint) -> bool {
+    if abbrev.is_empty() {
+        return matched > 1;
+    }
+    if humps.is_empty() {
+        return false;
+    }
+    let hump = humps[0].to_ascii_lower();
+    // take as much of this hump as the abbreviation has, backing off if the
+    // rest then doesn't match the following humps
+    let mut n = 0u;
+    while n < abbrev.len() && n < hump.len() &&
+        abbrev.char_at(n) == hump.as_slice().char_at(n) {
+        n += 1;
+    }
+    while n > 0 {
+        if match_humps(abbrev.slice_from(n), humps.slice_from(1), matched + 1) {
+            return true;
+        }
+        n -= 1;
+    }
+    // later humps can be skipped, but not the first
+    return matched > 0 && match_humps(abbrev, humps.slice_from(1), matched);
+}
+
+#[cfg(test)]
+fn match_at(name: &str, point: uint, local: bool, mtype: super::MatchType) -> Match {
+    Match { matchstr: name.to_string(), filepath: Path::new("src/ranking_test.rs"),
+            point: point, local: local, accessible: true, score: 0, mtype: mtype,
+            contextstr: "".to_string(), generic_args: Vec::new(), generic_types: Vec::new() }
+}
+
+#[test]
+fn splits_names_into_humps() {
+    assert_eq!(vec!["Hash", "Map"], humps("HashMap"));
+    assert_eq!(vec!["get", "mut"], humps("get_mut"));
+    assert_eq!(vec!["IO", "Error"], humps("IO_Error"));
+    assert!(is_hump_match("HM", "HashMap"));
+    assert!(is_hump_match("HaMa", "HashMap"));
+    assert!(is_hump_match("gm", "get_mut"));
+    assert!(!is_hump_match("Ha", "HashMap"));
+    assert!(!is_hump_match("MH", "HashMap"));
+}
+
+#[test]
+fn works_out_expected_kind() {
+    assert_eq!(TypeExpected, expected_kind("let a: Fo", 7));
+    assert_eq!(TypeExpected, expected_kind("fn foo() -> Fo", 12));
+    assert_eq!(ValueExpected, expected_kind("let a = fo", 8));
+    assert_eq!(AnyExpected, expected_kind("use std::io::Fo", 13));
+    assert_eq!(ValueExpected, expected_kind("let a = Foo { a: Al", 17));
+}
+
+#[test]
+fn dedupes_and_sorts_by_score() {
+    let path = Path::new("src/ranking_test.rs");
+    let query = Query{ filepath: &path, searchstr: "fo", expected: ValueExpected,
+                       preferred: Vec::new() };
+    let matches = vec![match_at("Foo", 1, false, Struct),
+                       match_at("foo", 2, true, Let),
+                       match_at("foo", 2, true, Let)];
+    let got = rank(matches, &query);
+    assert_eq!(vec![("foo".to_string(), 2u), ("Foo".to_string(), 1u)],
+               got.iter().map(|m| (m.matchstr.clone(), m.point)).collect::<Vec<_>>());
+    assert!(got[0].score > got[1].score);
+}
@@ -1,6 +1,3 @@
-use std::io::File;
-use std::io::BufferedReader;
-
 use super::util;
 use super::codecleaner;
 use super::codeiter;
@@ -30,9 +27,10 @@ pub fn find_closing_paren(src:&str, mut pos:uint) -> uint {
 }
 
 
+// N.B. src must already have its comments masked (see mask_comments), otherwise
+// braces in comments and strings will throw the count off
 pub fn scope_start(src:&str, point:uint) -> uint {
-    let masked_src = mask_comments(src);
-    let s = masked_src.as_slice().slice(0,point);
+    let s = src.slice(0,point);
     let mut pt = point;
     let mut levels = 0i;
     for c in s.chars().rev() {
@@ -86,6 +84,61 @@ fn get_local_module_path_(msrc: &str, point: uint, out: &mut Vec<String>) {
     }
 }
 
+/// The start points of the inline modules ('mod foo { ... }') whose bodies
+/// enclose point, outermost first
+pub fn get_local_module_starts(msrc: &str, point: uint) -> Vec<uint> {
+    let mut v = Vec::new();
+    get_local_module_starts_(msrc, point, 0, &mut v);
+    return v;
+}
+
+fn get_local_module_starts_(msrc: &str, point: uint, offset: uint, out: &mut Vec<uint>) {
+    for (start, end) in codeiter::iter_stmts(msrc) {
+        if start < point && end > point {
+            let blob = msrc.slice(start, end);
+            if blob.starts_with("mod ") || blob.starts_with("pub mod ") {
+                blob.find_str("{").map(|n| {
+                    let bodystart = start + n + 1;
+                    if bodystart <= point {
+                        out.push(offset + start);
+                        get_local_module_starts_(blob.slice_from(n + 1), point - bodystart,
+                                                 offset + bodystart, out);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// If the path expression starting at point is in a use statement, the text
+/// to put in front of it to get the whole path. That is "" directly after
+/// 'use', or e.g. "foo::" in the braces of 'use foo::{Bar, Ba'.
+pub fn get_use_path_prefix(msrc: &str, point: uint) -> Option<String> {
+    let is_after_use = |s: &str| {
+        s.ends_with("use") && (s.len() == 3 || !util::is_ident_char(s.char_at(s.len() - 4)))
+    };
+
+    let before = msrc.slice_to(point).trim_right();
+    if is_after_use(before) {
+        return Some("".to_string());
+    }
+
+    // in a use list?
+    let bodystart = scope_start(msrc, point);
+    if bodystart == 0 || msrc.slice(bodystart, point).contains_char(';') {
+        return None;
+    }
+    let before = msrc.slice_to(bodystart - 1).trim_right();
+    if !before.ends_with("::") {
+        return None;
+    }
+    let pathstart = before.rfind(|c: char| !util::is_path_char(c)).map_or(0, |n| n + 1);
+    if is_after_use(before.slice_to(pathstart).trim_right()) {
+        return Some(before.slice_from(pathstart).to_string());
+    }
+    return None;
+}
+
 pub fn find_impl_start(msrc: &str, point: uint, scopestart: uint) -> Option<uint> {
 
     for (start, end) in codeiter::iter_stmts(msrc.slice_from(scopestart)) {
@@ -152,6 +205,9 @@ pub fn split_into_context_and_completion<'a>(s: &'a str) -> (&'a str, &'a str, s
 pub fn get_start_of_search_expr(msrc: &str, point: uint) -> uint {
     let openparen: u8 = "(".as_bytes()[0];
     let closeparen: u8 = ")".as_bytes()[0];
+    let opensqbrace: u8 = "[".as_bytes()[0];
+    let closesqbrace: u8 = "]".as_bytes()[0];
+    let quote: u8 = "\"".as_bytes()[0];
     let msrc_bytes = msrc.as_bytes();
     let mut levels = 0i;
     let mut i = point-1;
@@ -161,7 +217,15 @@ pub fn get_start_of_search_expr(msrc: &str, point: uint) -> uint {
             break;
         }
 
-        if msrc_bytes[i] == closeparen {
+        if levels == 0 && msrc_bytes[i] == quote {
+            // skip back over a string literal, e.g. '"abc".len'
+            if let Some(n) = find_string_start(msrc, i) {
+                i = n - 1;
+                continue;
+            }
+        }
+
+        if msrc_bytes[i] == closeparen || msrc_bytes[i] == closesqbrace {
             levels += 1;
         }
         if levels == 0 && (!util::is_path_char(msrc.char_at(i)) || 
@@ -169,7 +233,7 @@ pub fn get_start_of_search_expr(msrc: &str, point: uint) -> uint {
             i += 1;
             break;
         }
-        if msrc_bytes[i] == openparen && levels > 0 {
+        if (msrc_bytes[i] == openparen || msrc_bytes[i] == opensqbrace) && levels > 0 {
             levels -= 1;
         }
 
@@ -178,6 +242,311 @@ pub fn get_start_of_search_expr(msrc: &str, point: uint) -> uint {
     return i;
 }
 
+// The point of the quote opening the string literal closed by the quote at end
+fn find_string_start(msrc: &str, end: uint) -> Option<uint> {
+    let mut i = end;
+    while let Some(n) = msrc.slice_to(i).rfind('"') {
+        if n == 0 || msrc.char_at(n - 1) != '\\' {
+            return Some(n);
+        }
+        i = n;
+    }
+    return None;
+}
+
+#[test]
+fn finds_local_module_starts() {
+    let src = "mod foo {\n    pub mod bar {\n        fn baz() {}\n    }\n}\n";
+    let point = src.find_str("baz").unwrap();
+    assert_eq!(vec![0, 14], get_local_module_starts(src, point));
+    assert_eq!(vec![0], get_local_module_starts(src, 18));
+}
+
+#[test]
+fn gets_use_path_prefix() {
+    let src = "use foo::{self, Ba";
+    assert_eq!(Some("foo::".to_string()), get_use_path_prefix(src, src.len() - 2));
+    let src = "pub use std::io";
+    assert_eq!(Some("".to_string()), get_use_path_prefix(src, 8));
+    let src = "fn main() { let a = Ba";
+    assert_eq!(None, get_use_path_prefix(src, src.len() - 2));
+}
+
+#[test]
+fn get_start_of_search_expr_handles_literals_and_indexing() {
+    let src = "let a = \"abc\".le";
+    assert_eq!(8, get_start_of_search_expr(src, src.len()));
+    let src = "foo([1u, 2].it";
+    assert_eq!(4, get_start_of_search_expr(src, src.len()));
+    let src = "let b = v[0].fie";
+    assert_eq!(8, get_start_of_search_expr(src, src.len()));
+}
+
+/// If point is inside the argument list of a call, returns the point of the
+/// opening paren and the index of the argument that point is in.
+/// e.g. for 'foo(a, b|' returns (3, 1)
+pub fn find_enclosing_call(msrc: &str, point: uint) -> Option<(uint, uint)> {
+    let mut levels = 0i;
+    let mut argindex = 0u;
+    for (i, c) in msrc.slice_to(point).char_indices().rev() {
+        match c {
+            ')' | ']' | '}' => levels += 1,
+            '[' => levels -= 1,
+            '{' if levels == 0 => return None,
+            '{' => levels -= 1,
+            ';' if levels == 0 => return None,
+            ',' if levels == 0 => argindex += 1,
+            '(' if levels == 0 => {
+                // must be a call rather than e.g. a tuple or 'if ('
+                let callee = msrc.slice_to(i).trim_right();
+                let is_call = callee.chars().rev().next().map_or(false, util::is_ident_char) &&
+                    !callee.ends_with(" if") && !callee.ends_with(" while") &&
+                    !callee.ends_with(" match");
+                return if is_call { Some((i, argindex)) } else { None };
+            }
+            '(' => levels -= 1,
+            _ => {}
+        }
+    }
+    return None;
+}
+
+#[test]
+fn finds_enclosing_call_and_arg_index() {
+    assert_eq!(Some((3, 2)), find_enclosing_call("foo(a, bar(b, c), d", 19));
+    assert_eq!(Some((10, 1)), find_enclosing_call("foo(a, bar(b, c), d", 15));
+    assert_eq!(None, find_enclosing_call("foo(a); b", 9));
+}
+
+/// If point is where a field name goes in a struct literal or pattern, the
+/// range of the path naming the struct and the fields already given.
+/// e.g. for 'Foo { a: 1, |' returns (0, 3, ["a"])
+pub fn get_struct_field_context(msrc: &str, point: uint) -> Option<(uint, uint, Vec<String>)> {
+    let mut levels = 0i;
+    let mut fieldend = point;
+    let mut given = Vec::new();
+    for (i, c) in msrc.slice_to(point).char_indices().rev() {
+        match c {
+            ')' | ']' | '}' => levels += 1,
+            '(' | '[' if levels == 0 => return None,
+            '(' | '[' => levels -= 1,
+            ';' if levels == 0 => return None,
+            ',' | '{' if levels == 0 => {
+                let mut field = msrc.slice(i + 1, fieldend).trim_left();
+                if fieldend == point {
+                    // nothing but the name being typed, e.g. not after 'a: '
+                    if !field.is_empty() {
+                        return None;
+                    }
+                } else {
+                    // 'a: 1' or 'a' or, in a pattern, 'ref mut a'
+                    for kw in ["ref ", "mut "].iter() {
+                        if field.starts_with(*kw) {
+                            field = field.slice_from(kw.len()).trim_left();
+                        }
+                    }
+                    let name = field.slice_to(util::find_ident_end(field, 0));
+                    if !name.is_empty() {
+                        given.push(name.to_string());
+                    }
+                }
+                if c == '{' {
+                    return struct_path_before(msrc, i).map(|(start, end)| (start, end, given));
+                }
+                fieldend = i;
+            }
+            '{' => levels -= 1,
+            _ => {}
+        }
+    }
+    return None;
+}
+
+// The path in front of the '{' at brace, if it could name a struct (i.e.
+// isn't e.g. 'impl Foo {' or 'fn foo() -> Foo {')
+fn struct_path_before(msrc: &str, brace: uint) -> Option<(uint, uint)> {
+    let before = msrc.slice_to(brace).trim_right();
+    let pathstart = before.rfind(|c: char| !util::is_path_char(c)).map_or(0, |n| n + 1);
+    let path = before.slice_from(pathstart);
+    // struct names are capitalised, which also rules out 'else {' etc.
+    let name = path.slice_from(path.rfind(':').map_or(0, |n| n + 1));
+    if !name.chars().next().map_or(false, |c| c.is_uppercase()) {
+        return None;
+    }
+    let prev = before.slice_to(pathstart).trim_right();
+    let prevword = prev.slice_from(prev.rfind(|c: char| !util::is_ident_char(c))
+                                       .map_or(0, |n| n + 1));
+    let is_decl = ["struct", "enum", "impl", "trait", "for", "mod", "fn"].iter()
+        .any(|kw| *kw == prevword);
+    if is_decl || prev.ends_with("->") {
+        return None;
+    }
+    return Some((pathstart, before.len()));
+}
+
+#[test]
+fn gets_struct_field_context() {
+    let src = "let a = Foo { a: 1, ref mut b, ";
+    assert_eq!(Some((8, 11, vec!["b".to_string(), "a".to_string()])),
+               get_struct_field_context(src, src.len()));
+    let src = "let a = foo::Foo { ";
+    assert_eq!(Some((8, 16, vec![])), get_struct_field_context(src, src.len()));
+    let src = "let a = Foo { a: ";
+    assert_eq!(None, get_struct_field_context(src, src.len()));
+    let src = "impl Foo { ";
+    assert_eq!(None, get_struct_field_context(src, src.len()));
+}
+
+// A '|' at i starts a closure's parameter list rather than being an 'or'
+// if it follows something that can't end an expression
+fn is_closure_start(msrc: &str, i: uint) -> bool {
+    let before = msrc.slice_to(i).trim_right();
+    return match before.chars().rev().next() {
+        None => true,
+        Some(c) => "(,={;:[".contains_char(c) ||
+            before.ends_with(" move") || before.ends_with(" return")
+    };
+}
+
+// True if the closure body starting at bodystart is still open at point,
+// i.e. it isn't ended by an unmatched closing bracket or a ',' or ';'
+fn closure_body_encloses(msrc: &str, bodystart: uint, point: uint) -> bool {
+    let mut levels = 0i;
+    for c in msrc.slice(bodystart, point).chars() {
+        match c {
+            '(' | '[' | '{' => levels += 1,
+            ')' | ']' | '}' if levels == 0 => return false,
+            ')' | ']' | '}' => levels -= 1,
+            ',' | ';' if levels == 0 => return false,
+            _ => {}
+        }
+    }
+    return true;
+}
+
+/// The closures starting in [from, point) whose bodies enclose point,
+/// innermost first. Each is given as the points of the two '|'s around its
+/// parameter list. msrc must have its comments masked.
+/// e.g. for 'v.map(|a| a.foo(|b| b.' returns [(16, 18), (6, 8)]
+pub fn find_enclosing_closures(msrc: &str, from: uint, point: uint) -> Vec<(uint, uint)> {
+    let mut out = Vec::new();
+    let mut i = from;
+    while i < point {
+        let open = match msrc.slice(i, point).find('|') {
+            Some(n) => i + n,
+            None => break
+        };
+        if !is_closure_start(msrc, open) {
+            i = open + 1;
+            continue;
+        }
+        let close = match msrc.slice(open + 1, point).find('|') {
+            Some(n) => open + 1 + n,
+            None => break     // point is in the parameter list
+        };
+        if closure_body_encloses(msrc, close + 1, point) {
+            out.push((open, close));
+        }
+        i = close + 1;
+    }
+    out.reverse();
+    return out;
+}
+
+#[test]
+fn finds_enclosing_closures() {
+    let src = "v.map(|a| a.foo(|b| b.";
+    assert_eq!(vec![(16, 18), (6, 8)], find_enclosing_closures(src, 0, src.len()));
+    let src = "v.map(|a| a.foo(), |b| b.";
+    assert_eq!(vec![(19, 21)], find_enclosing_closures(src, 0, src.len()));
+    let src = "let x = a || b; x.";
+    assert_eq!(Vec::<(uint, uint)>::new(), find_enclosing_closures(src, 0, src.len()));
+}
+
+/// If the scope starting at scopestart is the body of a match expression,
+/// the start of the expression being matched. msrc must have its comments
+/// masked. e.g. for 'let a = match x {' returns 14
+pub fn find_match_discriminant(msrc: &str, scopestart: uint) -> Option<uint> {
+    if scopestart == 0 {
+        return None;
+    }
+    let stmtstart = match find_stmt_start(msrc, scopestart - 1) {
+        Some(n) => n,
+        None => return None
+    };
+    let header = msrc.slice(stmtstart, scopestart - 1);
+    return util::find_ident_occurrences(header, "match").pop().and_then(|n| {
+        // the '{' opening the scope must be the first after the keyword
+        if header.slice_from(n).contains_char('{') {
+            None
+        } else {
+            Some(stmtstart + n + "match".len())
+        }
+    });
+}
+
+/// The start of the arm of the match body starting at bodystart that
+/// contains point. An arm ends at a ',' or at the '}' of a block body.
+/// msrc must have its comments masked.
+pub fn find_match_arm_start(msrc: &str, bodystart: uint, point: uint) -> uint {
+    let s = msrc.slice(bodystart, point);
+    let mut armstart = 0u;
+    let mut levels = 0i;
+    let mut blockbody = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => {
+                // a block body, as opposed to e.g. a struct pattern
+                if levels == 0 && s.slice_to(i).trim_right().ends_with("=>") {
+                    blockbody = true;
+                }
+                levels += 1;
+            }
+            '(' | '[' => levels += 1,
+            ')' | ']' => levels -= 1,
+            '}' => {
+                levels -= 1;
+                if levels == 0 && blockbody {
+                    armstart = i + 1;
+                    blockbody = false;
+                }
+            }
+            ',' if levels == 0 => {
+                armstart = i + 1;
+                blockbody = false;
+            }
+            _ => {}
+        }
+    }
+    let arm = s.slice_from(armstart);
+    return bodystart + armstart + (arm.len() - arm.trim_left().len());
+}
+
+/// The pattern of the match arm enclosing point, as the points of its
+/// start and of the '=>' ending it. None if point is still in the pattern.
+/// e.g. for 'A => 1, Some(y) => y.' from 0 returns (8, 16)
+pub fn find_match_arm_pattern(msrc: &str, bodystart: uint, point: uint) -> Option<(uint, uint)> {
+    let armstart = find_match_arm_start(msrc, bodystart, point);
+    return msrc.slice(armstart, point).find_str("=>").map(|n| (armstart, armstart + n));
+}
+
+#[test]
+fn finds_match_arm_pattern() {
+    let src = "A => 1, Some(y) => y.";
+    assert_eq!(Some((8, 16)), find_match_arm_pattern(src, 0, src.len()));
+    let src = "Foo { a, b } => { a }, Bar(c) => { c.";
+    assert_eq!(Some((23, 30)), find_match_arm_pattern(src, 0, src.len()));
+    let src = "A => 1, Some(y";
+    assert_eq!(None, find_match_arm_pattern(src, 0, src.len()));
+}
+
+#[test]
+fn finds_match_discriminant() {
+    let src = "fn foo() { let a = match x { Some(y) => y.foo() }; }";
+    assert_eq!(Some(24), find_match_discriminant(src, 28));
+    assert_eq!(None, find_match_discriminant(src, 10));
+}
+
 pub fn expand_search_expr(msrc: &str, point: uint) -> (uint,uint) {
     let start = get_start_of_search_expr(msrc, point);
     return (start, util::find_ident_end(msrc, point));
@@ -264,16 +633,15 @@ pub fn point_to_coords(src:&str, point:uint) -> (uint, uint) {
 }
 
 pub fn point_to_coords2(path: &Path, point:uint) -> Option<(uint, uint)> {
+    let src = super::load_file(path);
     let mut lineno = 0;
-    let mut file = BufferedReader::new(File::open(path));
     let mut p = 0;
-    for line_r in file.lines() {
-        let line = line_r.unwrap();
+    for line in src.as_slice().split('\n') {
         lineno += 1;
-        if point < (p + line.len()) {
+        if point < (p + line.len() + 1) {
             return Some((lineno, point - p));
         }
-        p += line.len();
+        p += line.len() + 1;  // +1 for the \n
     }
     return None;
 }
@@ -0,0 +1,230 @@
+// Workspace symbol search. Indexes the items declared in every module of a
+// crate (and of its Cargo dependencies) using the matchers, then fuzzy
+// matches a query against their names.
+
+use std::ascii::AsciiExt;
+use std::cmp;
+
+use super::{Match, SearchType};
+use super::{cargo, cfg, codeiter, matchers, nameres, util};
+
+/// An item found by search_symbols, with its fully qualified path
+#[deriving(Show, Clone)]
+pub struct Symbol {
+    pub m: Match,
+    pub path: String,
+    pub score: int
+}
+
+// Scores how well the query fuzzy matches the name: the query characters
+// must appear in the name in order (ignoring case). Exact and prefix
+// matches score highest, then contiguous runs and matches at word starts.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<int> {
+    let q = query.to_ascii_lower();
+    let n = name.to_ascii_lower();
+    if q.is_empty() {
+        return Some(0);
+    }
+    if q == n {
+        return Some(1000);
+    }
+
+    let namechars = name.chars().collect::<Vec<char>>();
+    let lowerchars = n.as_slice().chars().collect::<Vec<char>>();
+    let mut score = 0i;
+    let mut ni = 0u;
+    let mut prev_matched = false;
+    for qc in q.as_slice().chars() {
+        let mut found = false;
+        while ni < lowerchars.len() {
+            let i = ni;
+            ni += 1;
+            if lowerchars[i] == qc {
+                let word_start = i == 0 || namechars[i-1] == '_' ||
+                    (namechars[i].is_uppercase() && !namechars[i-1].is_uppercase());
+                score += if word_start { 10 } else if prev_matched { 5 } else { 1 };
+                prev_matched = true;
+                found = true;
+                break;
+            }
+            prev_matched = false;
+        }
+        if !found {
+            return None;
+        }
+    }
+
+    if n.as_slice().starts_with(q.as_slice()) {
+        score += 500;
+    } else if n.as_slice().contains(q.as_slice()) {
+        score += 250;
+    }
+    // prefer shorter names, all else being equal
+    return Some(score - cmp::min(name.len() - query.len(), 100) as int);
+}
+
+fn strip_pub(blob: &str) -> &str {
+    if blob.starts_with("pub ") { blob.slice_from(4) } else { blob }
+}
+
+// Runs the matcher for the kind of item declared by the blob
+fn match_item(msrc: &str, blobstart: uint, blobend: uint, filepath: &Path) -> Option<Match> {
+    let blob = strip_pub(msrc.slice(blobstart, blobend));
+    let s = SearchType::StartsWith;
+    if blob.starts_with("struct ") {
+        matchers::match_struct(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("enum ") {
+        matchers::match_enum(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("trait ") {
+        matchers::match_trait(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("fn ") {
+        matchers::match_fn(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("const ") {
+        matchers::match_const(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("static ") {
+        matchers::match_static(msrc, blobstart, blobend, "", filepath, s, true)
+    } else if blob.starts_with("type ") {
+        matchers::match_type(msrc, blobstart, blobend, "", filepath, s, true)
+    } else {
+        None
+    }
+}
+
+// Indexes the items in the scope [scopestart, scopeend) of a file, following
+// 'mod' declarations into inline modules and other files
+fn index_scope(src: &str, msrc: &str, scopestart: uint, scopeend: uint, filepath: &Path,
+               searchdir: &Path, modpath: &Vec<String>, visited: &mut Vec<Path>,
+               out: &mut Vec<(Match, String)>) {
+    for (start, end) in codeiter::iter_stmts(msrc.slice(scopestart, scopeend)) {
+        let (blobstart, blobend) = (scopestart + start, scopestart + end);
+        let blob = msrc.slice(blobstart, blobend);
+
+        // same as nameres::search_scope, skip attributes and the items they
+        // disable
+        if blob.starts_with("#") || !cfg::is_item_enabled(src, msrc, blobstart) {
+            continue;
+        }
+
+        let decl = strip_pub(blob);
+        if decl.starts_with("mod ") {
+            let nameend = util::find_ident_end(decl, 4);
+            let name = decl.slice(4, nameend).to_string();
+            let mut subpath = modpath.clone();
+            subpath.push(name.clone());
+            let bracepos = decl.find('{');
+            let semipos = decl.find(';');
+            match (bracepos, semipos) {
+                (Some(b), s) if s.map_or(true, |s| b < s) => {
+                    // inline module
+                    let bodystart = blobend - decl.len() + b + 1;
+                    index_scope(src, msrc, bodystart, blobend - 1, filepath,
+                                &searchdir.join(name.as_slice()), &subpath, visited, out);
+                }
+                _ => {
+                    nameres::get_module_file(name.as_slice(), searchdir).map(|modfile| {
+                        index_file(&modfile, &subpath, visited, out);
+                    });
+                }
+            }
+            continue;
+        }
+
+        match_item(msrc, blobstart, blobend, filepath).map(|m| {
+            let mut path = modpath.clone();
+            path.push(m.matchstr.clone());
+            out.push((m, path.connect("::")));
+        });
+    }
+}
+
+fn index_file(filepath: &Path, modpath: &Vec<String>, visited: &mut Vec<Path>,
+              out: &mut Vec<(Match, String)>) {
+    if visited.contains(filepath) {
+        return;
+    }
+    visited.push(filepath.clone());
+    let src = super::load_file(filepath);
+    let msrc = super::load_file_and_mask_comments(filepath);
+    index_scope(src.as_slice(), msrc.as_slice(), 0, msrc.len(), filepath, &filepath.dir_path(),
+                modpath, visited, out);
+}
+
+// The root module of the crate containing currentdir
+fn find_crate_root(currentdir: &Path) -> Option<Path> {
+    let root = nameres::find_possible_crate_root_modules(currentdir).into_iter().next();
+    if root.is_some() {
+        return root;
+    }
+    // e.g. at the top of a cargo package, rather than in src/
+    cargo::find_manifest(&currentdir.join("Cargo.toml")).and_then(|manifest| {
+        cargo::get_package_lib_file(&manifest.dir_path()).or_else(|| {
+            let mainfile = manifest.dir_path().join_many(&["src", "main.rs"]);
+            if super::vfs::exists(&mainfile) { Some(mainfile) } else { None }
+        })
+    })
+}
+
+/// Every item declared in the crate containing currentdir and in the crates
+/// it depends on, paired with its fully qualified path
+pub fn index_crate(currentdir: &Path) -> Vec<(Match, String)> {
+    let mut out = Vec::new();
+    let mut visited = Vec::new();
+    let root = match find_crate_root(currentdir) {
+        Some(root) => root,
+        None => return out
+    };
+    debug!("symbols: indexing crate {}", root.as_str());
+    index_file(&root, &Vec::new(), &mut visited, &mut out);
+
+    for (cratename, libfile) in cargo::get_dependency_crates(&root).into_iter() {
+        debug!("symbols: indexing dependency {} {}", cratename, libfile.as_str());
+        index_file(&libfile, &vec![cratename], &mut visited, &mut out);
+    }
+    return out;
+}
+
+/// Fuzzy searches the items of the crate containing currentdir (and its
+/// dependencies) for the query. Best matches first.
+pub fn search_symbols(query: &str, currentdir: &Path) -> Vec<Symbol> {
+    let mut out = Vec::new();
+    for (m, path) in index_crate(currentdir).into_iter() {
+        fuzzy_score(query, m.matchstr.as_slice()).map(|score| {
+            out.push(Symbol{ m: m, path: path, score: score });
+        });
+    }
+    out.sort_by(|a, b| {
+        match b.score.cmp(&a.score) {
+            cmp::Equal => a.path.cmp(&b.path),
+            ordering => ordering
+        }
+    });
+    return out;
+}
+
+#[test]
+fn fuzzy_score_prefers_exact_then_prefix_then_subsequence() {
+    let exact = fuzzy_score("parser", "Parser").unwrap();
+    let prefix = fuzzy_score("parser", "ParserState").unwrap();
+    let subseq = fuzzy_score("psr", "ParserState").unwrap();
+    assert!(exact > prefix);
+    assert!(prefix > subseq);
+    assert_eq!(None, fuzzy_score("xyz", "Parser"));
+}
+
+#[test]
+fn indexes_inline_modules_with_qualified_paths() {
+    let src = "
+pub mod foo {
+    pub mod bar {
+        pub struct Parser;
+    }
+    pub fn parse() {}
+}
+const MAX: uint = 3;
+";
+    let path = Path::new("symbols_test_dir").join("lib.rs");
+    super::vfs::set_buffer(&path, src.to_string());
+    let got = index_crate(&path.dir_path()).into_iter().map(|(_, p)| p).collect::<Vec<_>>();
+    super::vfs::remove_buffer(&path);
+    assert_eq!(vec!["foo::bar::Parser".to_string(), "foo::parse".to_string(), "MAX".to_string()], got);
+}
@@ -0,0 +1,172 @@
+// Records the steps taken while resolving a query (scopes searched, uses and
+// globs followed, crate roots chosen, types inferred) as a tree, so that
+// '--explain' can show why a completion or definition was or wasn't found.
+//
+// The recorder is part of the state of a query (see query.rs), so that the
+// parser tasks of a query (see ast.rs) record into it while other queries
+// running at the same time don't. Nothing is recorded (or formatted) unless
+// a query is run via record().
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use super::query;
+
+/// A step in resolving a query, and the steps it took in turn
+#[deriving(Show, Clone)]
+pub struct Step {
+    pub desc: String,
+    pub result: Option<String>,
+    pub failed: bool,
+    pub steps: Vec<Step>
+}
+
+impl Step {
+    fn new(desc: String) -> Step {
+        Step{ desc: desc, result: None, failed: false, steps: Vec::new() }
+    }
+}
+
+pub struct Recorder {
+    // the open steps, outermost first. stack[0] holds the finished top level steps
+    stack: Vec<Step>
+}
+
+fn recorder() -> Option<Arc<Mutex<Recorder>>> {
+    query::state().recorder.clone()
+}
+
+pub fn enabled() -> bool {
+    query::state().recorder.is_some()
+}
+
+/// Runs the query, returning its result along with the steps recorded
+/// while it ran
+pub fn record<T, F: FnOnce() -> T>(f: F) -> (T, Vec<Step>) {
+    let recorder = Arc::new(Mutex::new(Recorder{ stack: vec![Step::new("".to_string())] }));
+    let res = query::with_recorder(recorder.clone(), f);
+    let mut stack = mem::replace(&mut recorder.lock().stack, Vec::new());
+    // close any steps left open, e.g. by a task that failed
+    while stack.len() > 1 {
+        let step = stack.pop().unwrap();
+        stack.last_mut().unwrap().steps.push(step);
+    }
+    let steps = stack.pop().map(|root| root.steps).unwrap_or(Vec::new());
+    return (res, steps);
+}
+
+/// An open step. Steps started while it is alive become its children, and
+/// it is closed when dropped.
+pub struct Span {
+    // the recorder and the index of the step in its stack, None if not recording
+    open: Option<(Arc<Mutex<Recorder>>, uint)>
+}
+
+/// Opens a step described by desc(), which is only called when recording
+pub fn span<F: FnOnce() -> String>(desc: F) -> Span {
+    let recorder = match recorder() {
+        Some(recorder) => recorder,
+        None => return Span{ open: None }
+    };
+    let depth = {
+        let mut r = recorder.lock();
+        r.stack.push(Step::new(desc()));
+        r.stack.len() - 1
+    };
+    return Span{ open: Some((recorder, depth)) };
+}
+
+/// Records a step with no children
+pub fn note<F: FnOnce() -> String>(desc: F) {
+    span(desc);
+}
+
+/// Records a step that failed, i.e. a point where resolution stopped
+pub fn note_failure<F: FnOnce() -> String>(desc: F) {
+    span(desc).fail("failed".to_string());
+}
+
+impl Span {
+    fn set_result(&self, result: String, failed: bool) {
+        self.open.as_ref().map(|&(ref recorder, depth)| {
+            let mut r = recorder.lock();
+            if depth < r.stack.len() {
+                let step = &mut r.stack[depth];
+                step.result = Some(result);
+                step.failed = failed;
+            }
+        });
+    }
+
+    pub fn succeed(&self, result: String) {
+        self.set_result(result, false);
+    }
+
+    pub fn fail(&self, result: String) {
+        self.set_result(result, true);
+    }
+
+    /// Records the number of matches found, failing if there were none
+    pub fn found(&self, nmatches: uint) {
+        if self.open.is_none() {
+            return;
+        }
+        match nmatches {
+            0 => self.fail("no matches".to_string()),
+            1 => self.succeed("1 match".to_string()),
+            n => self.succeed(format!("{} matches", n))
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.open.as_ref().map(|&(ref recorder, depth)| {
+            let mut r = recorder.lock();
+            // children are dropped first, but a failed task can leave them open
+            while r.stack.len() > depth + 1 {
+                let step = r.stack.pop().unwrap();
+                r.stack.last_mut().unwrap().steps.push(step);
+            }
+            if r.stack.len() == depth + 1 && depth > 0 {
+                let step = r.stack.pop().unwrap();
+                r.stack.last_mut().unwrap().steps.push(step);
+            }
+        });
+    }
+}
+
+/// Where the chain of steps broke: follows the last failed step down to
+/// the innermost failure
+pub fn find_break(steps: &[Step]) -> Option<&Step> {
+    let failed = match steps.iter().rev().find(|s| s.failed) {
+        Some(step) => step,
+        None => return None
+    };
+    return match find_break(failed.steps.as_slice()) {
+        Some(step) => Some(step),
+        None => Some(failed)
+    };
+}
+
+#[test]
+fn records_nested_steps_and_finds_the_break() {
+    let (_, steps) = record(|| {
+        let outer = span(|| "outer".to_string());
+        {
+            let inner = span(|| "inner".to_string());
+            note(|| "a note".to_string());
+            inner.found(0);
+        }
+        outer.found(0);
+    });
+    assert_eq!(1, steps.len());
+    assert_eq!("outer", steps[0].desc.as_slice());
+    assert_eq!("inner", steps[0].steps[0].desc.as_slice());
+    assert_eq!("a note", steps[0].steps[0].steps[0].desc.as_slice());
+    assert_eq!("inner", find_break(steps.as_slice()).unwrap().desc.as_slice());
+
+    // nothing is recorded outside of record()
+    span(|| "ignored".to_string()).found(1);
+    let (_, steps) = record(|| {});
+    assert!(steps.is_empty());
+}
@@ -2,15 +2,16 @@
 
 use super::{Match};
 use super::nameres::{resolve_path_with_str};
-use super::{ast,codeiter,scopes};
+use super::{ast,codeiter,nameres,scopes,trace};
 
 use super::SearchType::ExactMatch;
-use super::Namespace::TypeNamespace;
+use super::Namespace::{TypeNamespace, ValueNamespace};
+use super::CompletionType::{CompleteField, CompletePath, CompleteStructField};
 use super::util::txt_matches;
 
-fn find_start_of_function_body(src: &str) -> uint {
+fn find_start_of_function_body(src: &str) -> Option<uint> {
     // TODO: this should ignore anything inside parens so as to skip the arg list
-    return src.find_str("{").unwrap();
+    return src.find_str("{");
 }
 
 // Removes the body of the statement (anything in the braces {...}), leaving just
@@ -18,9 +19,13 @@ fn find_start_of_function_body(src: &str) -> uint {
 // TODO: this should skip parens (e.g. function arguments)
 pub fn generate_skeleton_for_parsing(src: &str) -> String {
     let mut s = String::new();
-    let n = src.find_str("{").unwrap();
-    s.push_str(src.slice_to(n+1));
-    s.push_str("};");
+    match src.find_str("{") {
+        Some(n) => {
+            s.push_str(src.slice_to(n+1));
+            s.push_str("};");
+        }
+        None => s.push_str(src)   // no body, e.g. 'mod foo;'
+    }
     return s;
 }
 
@@ -48,9 +53,10 @@ fn get_type_of_self_arg(m: &Match, msrc: &str) -> Option<super::Ty> {
         if decl.as_slice().starts_with("impl") {
             let implres = ast::parse_impl(decl);
             debug!("get_type_of_self_arg implres |{}|", implres);
-            return resolve_path_with_str(&implres.name_path.expect("failed parsing impl name"), 
-                                         &m.filepath, start,
-                                         ExactMatch, TypeNamespace).nth(0).map(|m| super::Ty::TyMatch(m));
+            return implres.name_path.and_then(|name_path| {
+                resolve_path_with_str(&name_path, &m.filepath, start,
+                                      ExactMatch, TypeNamespace).nth(0).map(|m| super::Ty::TyMatch(m))
+            });
         } else {
             // // must be a trait
             return ast::parse_trait(decl).name.and_then(|name| {
@@ -58,6 +64,8 @@ fn get_type_of_self_arg(m: &Match, msrc: &str) -> Option<super::Ty> {
                            filepath: m.filepath.clone(), 
                            point: start,
                            local: m.local,
+                           accessible: true,
+                           score: 0,
                            mtype: super::MatchType::Trait,
                            contextstr: super::matchers::first_line(msrc.slice_from(start)),
                            generic_args: Vec::new(), generic_types: Vec::new()
@@ -74,26 +82,35 @@ fn get_type_of_fnarg(m: &Match, msrc: &str) -> Option<super::Ty> {
         return get_type_of_self_arg(m, msrc);
     }
 
-    let point = scopes::find_stmt_start(msrc, m.point).unwrap();
+    let point = match scopes::find_stmt_start(msrc, m.point) {
+        Some(point) => point,
+        None => return None
+    };
     for (start,end) in codeiter::iter_stmts(msrc.slice_from(point)) { 
         let blob = msrc.slice(point+start,point+end);
+        let bodystart = match find_start_of_function_body(blob) {
+            Some(n) => n,
+            None => return None
+        };
         // wrap in "impl blah { }" so that methods get parsed correctly too
         let mut s = String::new();
         s.push_str("impl blah {");
         let impl_header_len = s.len();
-        s.push_str(blob.slice_to(find_start_of_function_body(blob)+1));
+        s.push_str(blob.slice_to(bodystart+1));
         s.push_str("}}");
-        let fn_ = ast::parse_fn(s, super::Scope::from_match(m));
+        let fn_ = ast::parse_fn(s.clone(), super::Scope::from_match(m));
         let mut result = None;
         for (_/*name*/, pos, ty_) in fn_.args.into_iter() {
             let globalpos = pos - impl_header_len + start + point;
-            if globalpos == m.point && ty_.is_some() {
-                result = resolve_path_with_str(&ty_.unwrap(), 
-                                               &m.filepath, 
-                                               globalpos, 
-                                               super::SearchType::ExactMatch,
-                                               super::Namespace::TypeNamespace,  // just the type namespace
-                                               ).nth(0).map(|m| super::Ty::TyMatch(m));
+            if let (true, Some(ty_)) = (globalpos == m.point, ty_) {
+                result = get_type_of_type_param(&ty_, m, msrc, s.clone(), point + start).or_else(|| {
+                    resolve_path_with_str(&ty_, 
+                                          &m.filepath, 
+                                          globalpos, 
+                                          super::SearchType::ExactMatch,
+                                          super::Namespace::TypeNamespace,  // just the type namespace
+                                          ).nth(0).map(|m| super::Ty::TyMatch(m))
+                });
             }
         }
         return result;
@@ -101,9 +118,151 @@ fn get_type_of_fnarg(m: &Match, msrc: &str) -> Option<super::Ty> {
     None
 }
 
+// If path names a type parameter of the fn declared in fndecl or of the impl
+// enclosing it, a TypeParameter match carrying the traits the parameter is
+// bound by (in its declaration or a where clause) as its generic types
+fn get_type_of_type_param(path: &super::Path, m: &Match, msrc: &str,
+                          fndecl: String, fnstart: uint) -> Option<super::Ty> {
+    if path.global || path.segments.len() != 1 {
+        return None;
+    }
+    let name = path.segments[0].name.as_slice();
+
+    let mut decls = vec![(fndecl, fnstart)];
+    let scopestart = scopes::scope_start(msrc, fnstart);
+    if scopestart > 0 {
+        // the statement holding the '{' that opens the fn's scope
+        if let Some(implstart) = scopes::find_stmt_start(msrc, scopestart - 1) {
+            if msrc.slice_from(implstart).starts_with("impl") {
+                decls.push((generate_skeleton_for_parsing(msrc.slice_from(implstart)), implstart));
+            }
+        }
+    }
+
+    let mut declpoint = None;
+    let mut bounds = Vec::new();
+    for (decl, declstart) in decls.into_iter() {
+        debug!("get_type_of_type_param looking for {} in |{}|", name, decl);
+        for (param, paths) in ast::parse_generics(decl).bounds.into_iter() {
+            if param.as_slice() == name {
+                declpoint = declpoint.or(Some(declstart));
+                bounds.extend(paths.into_iter().map(|path| super::PathSearch {
+                    path: path,
+                    filepath: m.filepath.clone(),
+                    point: declstart
+                }));
+            }
+        }
+    }
+
+    return match declpoint {
+        Some(declpoint) => Some(super::Ty::TyMatch(Match {
+            matchstr: name.to_string(),
+            filepath: m.filepath.clone(),
+            point: declpoint,
+            local: m.local,
+            accessible: true,
+            score: 0,
+            mtype: super::MatchType::TypeParameter,
+            contextstr: super::matchers::first_line(msrc.slice_from(declpoint)),
+            generic_args: Vec::new(),
+            generic_types: bounds
+        })),
+        None => None
+    };
+}
+
+// The type that the callee expects for the argument at argindex of a
+// closure passed as its parameter at paramindex, from the parameter's
+// Fn/FnMut/FnOnce bound. e.g. T for the closure in 'fn foo<F: Fn(T)>(f: F)'
+fn get_closure_param_type_of_function(fnmatch: &Match, paramindex: uint,
+                                      argindex: uint) -> Option<super::Ty> {
+    return get_fn_decl_for_parsing(fnmatch).and_then(|decl| {
+        debug!("get_closure_param_type_of_function: passing in |{}|",decl);
+        ast::parse_fn_closure_param(decl, paramindex, argindex, super::Scope::from_match(fnmatch))
+    });
+}
+
+// Infers the type of a closure argument from the function or method the
+// closure is passed to, e.g. x in 'v.retain(|x| ...)'
+fn get_type_of_closure_arg_from_callee(m: &Match, msrc: &str, open: uint,
+                                       argindex: uint) -> Option<super::Ty> {
+    let (parenpos, paramindex) = match scopes::find_enclosing_call(msrc, open) {
+        Some(call) => call,
+        None => return None
+    };
+    let calleestart = scopes::get_start_of_search_expr(msrc, parenpos);
+    let callee = msrc.slice(calleestart, parenpos);
+    let (contextstr, searchstr, completetype) = scopes::split_into_context_and_completion(callee);
+    debug!("get_type_of_closure_arg_from_callee callee |{}| param {} arg {}",
+           callee, paramindex, argindex);
+
+    match completetype {
+        CompleteField => {
+            // a method call: get the type of the receiver and find the method.
+            // The method's first param is self, so skip it
+            let context = ast::get_type_of(contextstr.to_string(), &m.filepath, calleestart);
+            return context.and_then(|ty| {
+                let contextm = match ty {
+                    super::Ty::TyMatch(contextm) => contextm,
+                    _ => return None
+                };
+                let method = nameres::search_for_impl_methods(contextm.matchstr.as_slice(),
+                                                              searchstr,
+                                                              contextm.point,
+                                                              &contextm.filepath,
+                                                              contextm.local,
+                                                              ExactMatch).nth(0);
+                method
+                    .and_then(|method| get_closure_param_type_of_function(&method, paramindex + 1,
+                                                                          argindex))
+                    .and_then(|ty| ast::path_to_match_including_generics(ty, &contextm))
+            });
+        }
+        CompletePath => {
+            let path = super::Path::from_str(callee);
+            return resolve_path_with_str(&path, &m.filepath, calleestart,
+                                         ExactMatch, ValueNamespace).nth(0)
+                .and_then(|fnmatch| get_closure_param_type_of_function(&fnmatch, paramindex,
+                                                                       argindex))
+                .and_then(ast::path_to_match);
+        }
+        // a callee is never a struct literal field
+        CompleteStructField => return None
+    }
+}
+
+fn get_type_of_closure_arg(m: &Match, msrc: &str) -> Option<super::Ty> {
+    debug!("get type of closure arg {}",m);
+
+    // the contextstr is the closure's parameter list, e.g. '|a, b: Foo|'
+    let open = match msrc.slice_to(m.point).rfind('|') {
+        Some(open) => open,
+        None => return None
+    };
+    let fn_ = ast::parse_fn(m.contextstr.clone() + " ();", super::Scope::from_match(m));
+    let mut argindex = 0u;
+    for (i, (_/*name*/, pos, ty_)) in fn_.args.into_iter().enumerate() {
+        if open + pos == m.point {
+            match ty_ {
+                // explicitly typed
+                Some(ty_) => return resolve_path_with_str(&ty_, &m.filepath, m.point,
+                                                          ExactMatch, TypeNamespace)
+                    .nth(0).map(|m| super::Ty::TyMatch(m)),
+                None => {}
+            }
+            argindex = i;
+        }
+    }
+    return get_type_of_closure_arg_from_callee(m, msrc, open, argindex);
+}
+
 fn get_type_of_let_expr(m: &Match, msrc: &str) -> Option<super::Ty> {
     // ASSUMPTION: this is being called on a let decl
-    let point = scopes::find_stmt_start(msrc, m.point).unwrap();
+    let point = match scopes::find_stmt_start(msrc, m.point) {
+        Some(point) => point,
+        None => return None
+    };
 
     let src = msrc.slice_from(point);
     for (start,end) in codeiter::iter_stmts(src) { 
@@ -112,18 +271,48 @@ fn get_type_of_let_expr(m: &Match, msrc: &str) -> Option<super::Ty> {
 
         let pos = m.point - point - start;
         let scope = super::Scope{ filepath: m.filepath.clone(), point: m.point };
+        if blob.starts_with("for ") {
+            // a for loop binding. The body isn't needed, and may not parse
+            return ast::get_let_type(generate_skeleton_for_parsing(blob), pos, scope);
+        }
         return ast::get_let_type(blob.to_string(), pos, scope);
     }
     return None;
 }
 
+// The type of a binding in the pattern of a match arm, from the type of the
+// expression being matched. e.g. y in 'match x { Some(y) => ...'
+fn get_type_of_match_arm(m: &Match, msrc: &str) -> Option<super::Ty> {
+    let bodystart = scopes::scope_start(msrc, m.point);
+    let exprstart = match scopes::find_match_discriminant(msrc, bodystart) {
+        Some(n) => n,
+        None => return None
+    };
+    let patstart = scopes::find_match_arm_start(msrc, bodystart, m.point);
+    let patend = match msrc.slice_from(patstart).find_str("=>") {
+        Some(n) => patstart + n,
+        None => return None
+    };
+
+    // parse as a match with just this arm, leaving out the arm bodies
+    let header = format!("match {} {{ ", msrc.slice(exprstart, bodystart - 1));
+    let pos = header.len() + m.point - patstart;
+    let blob = format!("{}{} => () }}", header, msrc.slice(patstart, patend));
+    debug!("get_type_of_match_arm parsing |{}|", blob);
+    let scope = super::Scope{ filepath: m.filepath.clone(), point: m.point };
+    return ast::get_let_type(blob, pos, scope);
+}
+
 pub fn get_struct_field_type(fieldname: &str, structmatch: &Match) -> Option<super::Ty> {
     assert!(structmatch.mtype == super::MatchType::Struct);
 
     let src = super::load_file(&structmatch.filepath);
+    let msrc = super::load_file_and_mask_comments(&structmatch.filepath);
 
-    let opoint = scopes::find_stmt_start(&*src, structmatch.point);
-    let structsrc = scopes::end_of_next_scope(src.slice_from(opoint.unwrap()));
+    let structsrc = match scopes::find_stmt_start(&*msrc, structmatch.point) {
+        Some(point) => scopes::end_of_next_scope(src.slice_from(point)),
+        None => return None
+    };
 
     let fields = ast::parse_struct_fields(String::from_str(structsrc), 
                                           super::Scope::from_match(structmatch));
@@ -141,14 +330,18 @@ pub fn get_tuplestruct_field_type(fieldnum: uint, structmatch: &Match) -> Option
 
     let structsrc = if let super::MatchType::EnumVariant = structmatch.mtype {
         // decorate the enum variant src to make it look like a tuple struct
-        let to = src.slice_from(structmatch.point).find_str("(")
-            .map(|n| scopes::find_closing_paren(&*src, structmatch.point + n+1))
-            .unwrap();
+        let to = match src.slice_from(structmatch.point).find_str("(") {
+            Some(n) => scopes::find_closing_paren(&*src, structmatch.point + n+1),
+            None => return None     // not a tuple variant
+        };
         "struct ".to_string() + src.slice(structmatch.point, to+1) + ";"
     } else {
         assert!(structmatch.mtype == super::MatchType::Struct);
-        let opoint = scopes::find_stmt_start(&*src, structmatch.point);
-        get_first_stmt(src.slice_from(opoint.unwrap())).to_string()
+        let msrc = super::load_file_and_mask_comments(&structmatch.filepath);
+        match scopes::find_stmt_start(&*msrc, structmatch.point) {
+            Some(point) => get_first_stmt(src.slice_from(point)).to_string(),
+            None => return None
+        }
     };
 
     debug!("get_tuplestruct_field_type structsrc=|{}|",structsrc);
@@ -178,6 +371,8 @@ pub fn get_type_of_match(m: Match, msrc: &str) -> Option<super::Ty> {
     return match m.mtype {
         super::MatchType::Let => get_type_of_let_expr(&m, msrc),
         super::MatchType::FnArg => get_type_of_fnarg(&m, msrc),
+        super::MatchType::ClosureArg => get_type_of_closure_arg(&m, msrc),
+        super::MatchType::MatchArm => get_type_of_match_arm(&m, msrc),
         super::MatchType::Struct => Some(super::Ty::TyMatch(m)),
         super::MatchType::Enum => Some(super::Ty::TyMatch(m)),
         super::MatchType::Function => Some(super::Ty::TyMatch(m)),
@@ -186,19 +381,183 @@ pub fn get_type_of_match(m: Match, msrc: &str) -> Option<super::Ty> {
     }
 }
 
-pub fn get_return_type_of_function(fnmatch: &Match) -> Option<super::Ty> {
+// The declaration of the function (without its body) wrapped so that it
+// can be parsed, e.g. 'impl blah {fn foo(a: uint) -> uint {}}'
+fn get_fn_decl_for_parsing(fnmatch: &Match) -> Option<String> {
     let src = super::load_file(&fnmatch.filepath);
-    let point = scopes::find_stmt_start(&*src, fnmatch.point).unwrap();
+    let msrc = super::load_file_and_mask_comments(&fnmatch.filepath);
+    let point = match scopes::find_stmt_start(&*msrc, fnmatch.point) {
+        Some(point) => point,
+        None => return None
+    };
 
-    //debug!("get_return_type_of_function |{}|",src.slice_from(point));
-    
-    return src.slice_from(point).find_str("{").and_then(|n|{
-        // wrap in "impl blah { }" so that methods get parsed correctly too
-        let mut decl = String::new();
-        decl.push_str("impl blah {");
-        decl.push_str(src.slice(point, point+n+1));
-        decl.push_str("}}");
+    // the declaration ends at the body, or the ';' of a required trait method
+    let blob = get_first_stmt(msrc.slice_from(point));
+    let (declend, body) = match blob.find_str("{") {
+        Some(n) => (n + 1, "}}"),
+        None => (blob.trim_right().trim_right_chars(';').len(), "{}}")
+    };
+
+    // wrap in "impl blah { }" so that methods get parsed correctly too
+    let mut decl = String::new();
+    decl.push_str("impl blah {");
+    decl.push_str(src.slice(point, point + declend));
+    decl.push_str(body);
+    return Some(decl);
+}
+
+pub fn get_return_type_of_function(fnmatch: &Match) -> Option<super::Ty> {
+    return get_fn_decl_for_parsing(fnmatch).and_then(|decl| {
         debug!("get_return_type_of_function: passing in |{}|",decl);
-        return ast::parse_fn_output(decl, super::Scope::from_match(fnmatch));
+        ast::parse_fn_output(decl, super::Scope::from_match(fnmatch))
+    });
+}
+
+// The most derefs followed when walking a deref chain
+static MAX_DEREFS: uint = 10;
+
+// The type named by the 'impl <traitname> for X' statement in blob, either
+// its associated type ('type <assocname> = ...') or, in the older form,
+// the trait's type parameter at oldparam. e.g. Foo for 'impl Deref<Foo> for X'
+fn get_impl_type_path(blob: &str, traitname: &str, assocname: &str,
+                      oldparam: uint) -> Option<super::Path> {
+    let implres = ast::parse_impl(generate_skeleton_for_parsing(blob));
+    let traitseg = match implres.trait_path.as_ref().and_then(|p| p.segments.last()) {
+        Some(seg) if seg.name.as_slice() == traitname => seg.clone(),
+        _ => return None
+    };
+    let body = match blob.find_str("{") {
+        Some(n) => blob.slice_from(n+1),
+        None => return None
+    };
+    let assocdecl = format!("type {}", assocname);
+    for (start, end) in codeiter::iter_stmts(body) {
+        let stmt = body.slice(start, end);
+        if stmt.starts_with(assocdecl.as_slice()) {
+            return ast::parse_type(stmt.to_string()).type_;
+        }
+    }
+    return traitseg.types.into_iter().nth(oldparam);
+}
+
+// The type given by the implementation of the trait traitname for the type
+// in m (see get_impl_type_path), with m's generic types substituted in
+fn get_trait_impl_type(m: &Match, traitname: &str, assocname: &str,
+                       oldparam: uint) -> Option<Match> {
+    for implm in nameres::search_crate_for_impls(m.matchstr.as_slice(), &m.filepath,
+                                                 m.local, false).into_iter() {
+        if implm.matchstr != m.matchstr {
+            continue;
+        }
+        let msrc = super::load_file_and_mask_comments(&implm.filepath);
+        let implstart = match scopes::find_stmt_start(msrc.as_slice(), implm.point) {
+            Some(start) => start,
+            None => continue
+        };
+        let blob = get_first_stmt(msrc.slice_from(implstart));
+        debug!("get_trait_impl_type {} impl |{}|", traitname, blob);
+        let ty = get_impl_type_path(blob, traitname, assocname, oldparam).and_then(|path| {
+            let scope = super::Scope{ filepath: implm.filepath.clone(), point: implstart };
+            ast::path_to_match_including_generics(super::Ty::TyPathSearch(path, scope), m)
+        });
+        if let Some(super::Ty::TyMatch(ty)) = ty {
+            return Some(ty);
+        }
+    }
+    return None;
+}
+
+// The first generic type of m, e.g. Foo for Box<Foo>
+pub fn get_first_generic_type(m: &Match) -> Option<Match> {
+    return m.generic_types.iter().nth(0).and_then(|typesearch| {
+        let scope = super::Scope{ filepath: typesearch.filepath.clone(), point: typesearch.point };
+        ast::path_to_match(super::Ty::TyPathSearch(typesearch.path.clone(), scope))
+    }).and_then(|ty| match ty {
+        super::Ty::TyMatch(m) => Some(m),
+        _ => None
+    });
+}
+
+/// The type that values of the type in m deref to, e.g. Foo for Rc<Foo>.
+/// Box's deref is built into the compiler, so it has no impl to find
+pub fn get_deref_target(m: &Match) -> Option<Match> {
+    if m.mtype == super::MatchType::Builtin {
+        return None;
+    }
+    if m.matchstr.as_slice() == "Box" {
+        return get_first_generic_type(m);
+    }
+    return get_trait_impl_type(m, "Deref", "Target", 0);
+}
+
+/// The type of the result of the operator trait traitname (e.g. Add) for
+/// the type in m. Operators on builtin types give the same type
+pub fn get_operator_type(m: &Match, traitname: &str) -> Option<Match> {
+    if m.mtype == super::MatchType::Builtin {
+        return Some(m.clone());
+    }
+    // the older forms of the binary operator traits take the right hand
+    // side's type first, e.g. Add<RHS, Result>
+    let oldparam = match traitname {
+        "Neg" | "Not" => 0,
+        _ => 1
+    };
+    return get_trait_impl_type(m, traitname, "Output", oldparam);
+}
+
+/// The type of the elements of the type in m, as given by indexing it.
+/// e.g. Foo for [Foo] or Vec<Foo>
+pub fn get_index_type(m: &Match) -> Option<Match> {
+    if m.mtype == super::MatchType::Builtin && m.matchstr.as_slice() == "[T]" {
+        return get_first_generic_type(m);
+    }
+    return get_trait_impl_type(m, "Index", "Output", 1);
+}
+
+/// The type in m followed by the types it derefs to in turn, as the
+/// compiler's autoderef does. e.g. Rc<Vec<Foo>>, Vec<Foo>
+pub fn deref_chain(m: Match) -> Vec<Match> {
+    let mut chain = vec![m];
+    while chain.len() < MAX_DEREFS {
+        let target = match get_deref_target(chain.last().unwrap()) {
+            Some(target) => target,
+            None => break
+        };
+        // stop at a cycle rather than following it to the limit
+        if chain.iter().any(|m| m.filepath == target.filepath && m.point == target.point) {
+            break;
+        }
+        trace::note(|| format!("deref {} to {}", chain.last().unwrap().matchstr, target.matchstr));
+        chain.push(target);
+    }
+    return chain;
+}
+
+// True for std's Vec and its and slices' iterators, whose item type is
+// their first generic type. The iterators' impls are generated by a macro
+// so can't be found by searching
+fn is_std_vec_or_slice_iterator(m: &Match) -> bool {
+    let filename = m.filepath.filename_str().unwrap_or("");
+    return (filename == "vec.rs" || filename == "slice.rs") &&
+        ["Vec", "Items", "MutItems", "MoveItems", "Iter", "IterMut", "IntoIter"]
+        .iter().any(|name| *name == m.matchstr.as_slice());
+}
+
+/// The type of the items iterated over by a for loop over a value of the
+/// type in m, from its Iterator impl or else its IntoIterator impl's
+/// iterator. e.g. Foo for vec::Items<Foo>
+pub fn get_iterator_item_type(m: &Match) -> Option<Match> {
+    if is_std_vec_or_slice_iterator(m) {
+        return get_first_generic_type(m);
+    }
+    return get_trait_impl_type(m, "Iterator", "Item", 0).or_else(|| {
+        get_trait_impl_type(m, "IntoIterator", "IntoIter", 0).and_then(|it| {
+            trace::note(|| format!("iterate {} via {}", m.matchstr, it.matchstr));
+            if is_std_vec_or_slice_iterator(&it) {
+                get_first_generic_type(&it)
+            } else {
+                get_trait_impl_type(&it, "Iterator", "Item", 0)
+            }
+        })
     });
 }
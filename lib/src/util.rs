@@ -1,17 +1,13 @@
 // Small functions of utility
-use std::io::{File, BufferedReader};
 use super::{SearchType};
 use super::SearchType::{ExactMatch, StartsWith};
 use std;
 
 pub fn getline(filepath : &Path, linenum : uint) -> String {
-    let mut i = 0;
-    let mut file = BufferedReader::new(File::open(filepath));
-    for line in file.lines() {
-        //print!("{}", line);
-        i += 1;
-        if i == linenum {
-            return line.unwrap().to_string();
+    let src = super::load_file(filepath);
+    if linenum > 0 {
+        if let Some(line) = src.as_slice().lines().nth(linenum - 1) {
+            return line.to_string();
         }
     }
     return "not found".to_string();
@@ -160,6 +156,30 @@ pub fn find_ident_end(s : &str, pos : uint) -> uint {
     return end;
 }
 
+/// The start points of each whole-word occurrence of ident in s
+pub fn find_ident_occurrences(s: &str, ident: &str) -> Vec<uint> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while let Some(n) = s.slice_from(pos).find_str(ident) {
+        let start = pos + n;
+        let end = start + ident.len();
+        let before_ok = start == 0 || !is_ident_char(s.char_at_reverse(start));
+        let after_ok = end == s.len() || !is_ident_char(s.char_at(end));
+        if before_ok && after_ok {
+            out.push(start);
+        }
+        pos = end;
+    }
+    return out;
+}
+
+/// The (trimmed) text of the line containing point
+pub fn line_at_point<'a>(s: &'a str, point: uint) -> &'a str {
+    let start = s.slice_to(point).rfind('\n').map(|n| n + 1).unwrap_or(0);
+    let end = s.slice_from(point).find('\n').map(|n| point + n).unwrap_or(s.len());
+    return s.slice(start, end).trim();
+}
+
 pub fn to_refs<'a>(v: &'a Vec<String>) -> Vec<&'a str> {
     let mut out = Vec::new();
     for item in v.iter() {
@@ -168,3 +188,7 @@ pub fn to_refs<'a>(v: &'a Vec<String>) -> Vec<&'a str> {
     return out;
 }
 
+#[test]
+fn finds_whole_word_ident_occurrences() {
+    assert_eq!(vec![0, 13], find_ident_occurrences("foo(foobar); foo", "foo"));
+}
@@ -0,0 +1,116 @@
+// Virtual file system. Editors can register the text of unsaved buffers here,
+// and all of racer's file loads see that text instead of what is on disk.
+//
+// The overlay is global rather than thread local because the parser runs
+// in separate tasks (see ast.rs), and those need to see the buffers too.
+
+use std::collections::HashMap;
+use std::io::File;
+use std::mem;
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+struct Overlay {
+    buffers: HashMap<Path, (uint, String)>,   // path -> (version, text)
+    next_version: uint
+}
+
+impl Overlay {
+    fn insert(&mut self, filepath: Path, text: String) {
+        self.next_version += 1;
+        self.buffers.insert(filepath, (self.next_version, text));
+    }
+}
+
+static mut OVERLAY: *const Mutex<Overlay> = 0 as *const Mutex<Overlay>;
+static INIT: Once = ONCE_INIT;
+
+fn overlay() -> &'static Mutex<Overlay> {
+    unsafe {
+        INIT.doit(|| {
+            let m: Box<Mutex<Overlay>> = box Mutex::new(Overlay{ buffers: HashMap::new(),
+                                                                 next_version: 0 });
+            OVERLAY = mem::transmute(m);
+        });
+        return &*OVERLAY;
+    }
+}
+
+/// Registers the text of an unsaved buffer. Subsequent loads of filepath will
+/// return this text rather than the file contents.
+pub fn set_buffer(filepath: &Path, text: String) {
+    overlay().lock().insert(filepath.clone(), text);
+}
+
+/// Registers a number of buffers at once (e.g. all the modified buffers in
+/// an editor session)
+pub fn set_buffers(buffers: Vec<(Path, String)>) {
+    let mut overlay = overlay().lock();
+    for (filepath, text) in buffers.into_iter() {
+        overlay.insert(filepath, text);
+    }
+}
+
+/// Drops a buffer, so that loads of filepath go back to the disk
+pub fn remove_buffer(filepath: &Path) {
+    overlay().lock().buffers.remove(filepath);
+}
+
+/// Runs f with text standing in for filepath (e.g. the substitute file of a
+/// command), then puts back the buffer filepath had before, if any
+pub fn with_buffer<T>(filepath: &Path, text: String, f: || -> T) -> T {
+    let prev = get_buffer(filepath);
+    set_buffer(filepath, text);
+    let res = f();
+    match prev {
+        Some(text) => set_buffer(filepath, text),
+        None => remove_buffer(filepath)
+    }
+    return res;
+}
+
+pub fn clear() {
+    overlay().lock().buffers.clear();
+}
+
+pub fn get_buffer(filepath: &Path) -> Option<String> {
+    overlay().lock().buffers.get(filepath).map(|&(_, ref text)| text.clone())
+}
+
+/// Every call to set_buffer gives the buffer a new version number, so
+/// callers can tell whether the text has changed without comparing it.
+pub fn get_buffer_version(filepath: &Path) -> Option<uint> {
+    overlay().lock().buffers.get(filepath).map(|&(version, _)| version)
+}
+
+pub fn has_buffer(filepath: &Path) -> bool {
+    overlay().lock().buffers.contains_key(filepath)
+}
+
+/// True if the file has a registered buffer or exists on disk
+pub fn exists(filepath: &Path) -> bool {
+    has_buffer(filepath) || File::open(filepath).is_ok()
+}
+
+#[test]
+fn buffers_override_and_can_be_removed() {
+    let path = Path::new("vfs_test_nonexistent_file.rs");
+    assert!(!exists(&path));
+    set_buffer(&path, "fn foo() {}".to_string());
+    assert!(exists(&path));
+    assert_eq!(Some("fn foo() {}".to_string()), get_buffer(&path));
+    remove_buffer(&path);
+    assert!(!exists(&path));
+}
+
+#[test]
+fn with_buffer_puts_back_the_previous_buffer() {
+    let path = Path::new("vfs_test_with_buffer.rs");
+    let got = with_buffer(&path, "fn foo() {}".to_string(), || get_buffer(&path));
+    assert_eq!(Some("fn foo() {}".to_string()), got);
+    assert!(!has_buffer(&path));
+
+    set_buffer(&path, "fn bar() {}".to_string());
+    with_buffer(&path, "fn foo() {}".to_string(), || ());
+    assert_eq!(Some("fn bar() {}".to_string()), get_buffer(&path));
+    remove_buffer(&path);
+}